@@ -1,6 +1,6 @@
 use crate::characters; // to reference Character
 
-use sdl2::rect::Rect;
+use crate::physics::rect::Rect;
 use serde_derive::{Serialize, Deserialize}; // NOT YET OFFICIALLY AUTHORIZED
 // EDIT: update based on moves to characterAbstract
 
@@ -20,29 +20,103 @@ pub enum State {
 	LKick,
 	HKick,
 	Block,
+	Hitstun,   // reeling from a hit until the stun runs out
+	Blockstun, // still guarding after blocking until the stun runs out
+	Knockdown, // on the ground from a move that knocks down, until the stun runs out
+	GetUp,     // back on its feet after a knockdown
 	// Stretch goal: add more
 }
 
+// how a character shows one State, read from its character file (see characters::loader)
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Animation {
+	pub texture: String,         // sprite sheet, one sprite after another left to right
+	pub frames: i32,             // how long the state lasts
+	pub sprite_starts: Vec<i32>, // the frame each sprite starts on, the first always 0
+	pub attack: Option<MoveData>, // for states that are attacks
+	pub hurtboxes: Vec<FrameBox>, // where the fighter can be hit, block boxes while blocking
+	pub hitboxes: Vec<FrameBox>,  // where an attack hits, all on its active frames
+}
+
+// which part of the fighter a box covers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum BodyPart {
+	Head,
+	Body,
+	Limb,
+}
+
+// a box on some frames of a state, from the fighter's origin (the point between its feet) facing right:
+// x forward and y down, so everything above the feet has a negative y
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FrameBox {
+	pub part: BodyPart,
+	pub first: i32, // frames count from 1 like MoveData's
+	pub last: i32,
+	pub x: i32,
+	pub y: i32,
+	pub width: u32,
+	pub height: u32,
+}
+
+impl FrameBox {
+	pub fn on_frame(&self, frame: i32) -> bool {
+		frame >= self.first && frame <= self.last
+	}
+
+	// where the box is for a fighter with its origin at `origin`, turned around when it faces left
+	pub fn place(&self, origin: (i32, i32), facing_right: bool) -> Rect {
+		let x = if facing_right { origin.0 + self.x } else { origin.0 - self.x - self.width as i32 };
+		Rect::new(x, origin.1 + self.y, self.width, self.height)
+	}
+}
+
+// an attack's frame data, frames count from 1 on the first frame of the move
+// startup + active + recovery is the whole state
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MoveData {
+	pub startup: i32,         // frames before the hitbox comes out
+	pub active: i32,          // frames the hitbox is out
+	pub recovery: i32,        // frames after it goes away
+	pub damage: i32,
+	pub chip_damage: i32,     // damage that gets through a block
+	pub hitstun: i32,         // frames a fighter who was hit can't act
+	pub blockstun: i32,       // frames a fighter who blocked can't act
+	pub pushback: f32,        // how fast a block slides the defender away
+	pub knockback: (f32, f32), // the velocity a hit gives the defender, for an attacker facing right
+	pub knockdown: bool,      // a hit puts the defender on the ground instead of in hitstun
+}
+
+impl MoveData {
+	pub fn is_active(&self, frame: i32) -> bool {
+		frame > self.startup && frame <= self.startup + self.active
+	}
+}
+
+impl Animation {
+	// the sprite to show frame_count frames into the state
+	pub fn sprite_at(&self, frame_count: i32) -> i32 {
+		self.sprite_starts.iter().filter(|start| **start <= frame_count).count() as i32 - 1
+	}
+
+	pub fn hurtboxes_at(&self, frame_count: i32) -> impl Iterator<Item = &FrameBox> {
+		self.hurtboxes.iter().filter(move |b| b.on_frame(frame_count))
+	}
+
+	pub fn hitboxes_at(&self, frame_count: i32) -> impl Iterator<Item = &FrameBox> {
+		self.hitboxes.iter().filter(move |b| b.on_frame(frame_count))
+	}
+}
+
 // Gets the rectangle to use for positioning view of sprite
 pub fn get_rectangle(f: u32) -> Rect { // current frame
 	let x = W*f; // + 0
 	return Rect::new(x as i32, Y, W, H);
 }
 
-// Gets the numbers of frames per move
+// Gets the numbers of frames per move, from the character's file
 pub fn get_frame_cnt(c: &characters::characterAbstract::CharacterState) -> i32 {
-	// TODO: ensure every character has same # of animations/state
-	match c.state {
-		//Frames per Animation, Sprites per animation
-		State::Idle 	=> { return 30; },	//5
-		State::Walk 	=> { return 30; },	//6
-		State::Jump 	=> { return 30; },	//6
-		State::FJump 	=> { return 42; },	//7
-		State::LPunch 	=> { return 17; },	//3
-		State::LKick 	=> { return 25; },	//3
-		State::HKick 	=> { return 35; },	//5
-		State::Block 	=> { return 1; },	//1
-	}
+	c.animation().frames
 }
 
 // get character texture
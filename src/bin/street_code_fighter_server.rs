@@ -0,0 +1,10 @@
+// dedicated server, builds without SDL so it can run on a machine with no display
+// cargo run --no-default-features --bin street_code_fighter_server -- --bind 0.0.0.0:1666
+use std::env;
+use street_code_fighter::networking;
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = env::args().collect();
+    let net_config = networking::config::NetConfig::load(true, &args[1..])?;
+    networking::server::run_server(&net_config)
+}
@@ -1,27 +1,20 @@
 use core::cell::RefCell;
+use std::collections::HashMap;
 use crate::animation; // to reference sprite State
-use crate::animation::sprites::State;
+use crate::animation::sprites::{Animation, MoveData, State};
+use crate::characters::registry::{self, CharacterId};
 use crate::input;
 use crate::input::movement::Direction; // use to reference Direction
 
-use sdl2::rect::{Rect};
-use sdl2::render::Texture;
-use std::collections::HashMap;
+use crate::physics::rect::Rect;
 use std::rc::Rc;
+use std::sync::Arc;
 use crate::physics::collisions::*;
 use crate::physics::vecmath::*;
 use crate::physics::nodes::*;
 use crate::physics::particle::*;
 use crate::view::globals::*;
-
-// Enums
-// defines optional Characters
-#[derive(Hash, Eq, PartialEq, Debug)]
-pub enum Characters {
-	Python,
-	Java,
-	// Stretch goal: add more
-}
+use serde_derive::{Serialize, Deserialize};
 
 // Structs
 // defines the current state of the character
@@ -36,15 +29,60 @@ pub struct CharacterState {
 	pub auto_repeat: bool,
 	pub direction: input::movement::Direction,
 	pub next_state: animation::sprites::State,
-	pub hitbox: Option<RefCell<CollisionObject>>,
-	pub hurtbox: Option<RefCell<CollisionObject>>,
-	pub blockbox: Option<RefCell<CollisionObject>>,
+	pub pushbox: Option<RefCell<CollisionObject>>,
+	pub hurtboxes: Vec<RefCell<CollisionObject>>, // block boxes while blocking
+	pub hitboxes: Vec<RefCell<CollisionObject>>,
+	pub animations: Arc<HashMap<State, Animation>>, // one for every State, from the character's file
 }
 //self.current_frame = (self.current_frame + 1) % self.frames_per_state; }
 
+// copy of everything needed to rewind a CharacterState (used by rollback and resync)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CharacterSnapshot {
+	pub particle: Particle,
+	pub state: animation::sprites::State,
+	pub frames_per_state: i32,
+	pub frame_count: i32,
+	pub current_frame: i32,
+	pub sprite: Rect,
+	pub auto_repeat: bool,
+	pub direction: input::movement::Direction,
+	pub next_state: animation::sprites::State,
+}
+
+// the numbers a character file sets, see loader
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stats {
+	pub health: i32, // starting and most health
+	pub mass: f32,
+	pub speed: i32,
+	pub weight: i32,
+	pub gravity: f32,
+	pub max_fall_speed: i32,
+	pub walk_speed: i32,
+	pub run_speed: i32,
+	pub max_air_speed: i32,
+	pub aerial_transition_speed: i32,
+	pub crawl_speed: i32,
+	pub dodge_speed: i32,
+	pub friction: f32,
+	pub static_grip: i32,
+	pub pivot_grip: i32,
+	pub air_resistance: f32,
+	pub air_control: i32,
+	pub jumps: i32,
+	pub jump_height: i32,
+	pub short_hop_height: i32,
+	pub air_jump_height: i32,
+	pub heavy_land_lag: i32,
+	pub fastfall_multiplier: f32,
+	pub shield_size: i32,
+}
+
 // EDIT: consider updating integers to f64
-pub struct Fighter<'t> {
-	pub name: Characters,
+pub struct Fighter {
+	pub character: CharacterId,
 	pub char_state: CharacterState,
 	pub health: i32,
 	pub speed: i32,
@@ -69,38 +107,49 @@ pub struct Fighter<'t> {
     pub heavy_land_lag: i32,
     pub fastfall_multiplier: f32,
     pub shield_size: i32,
-  	pub textures: HashMap<animation::sprites::State, Texture<'t>>,
 }
 
-impl <'t> Fighter <'t> {
-	pub fn new (c: CharacterState) -> Fighter<'t> {
+impl Fighter {
+	// the default character until told otherwise, CharacterDef::build makes the others
+	// only tests build a fighter nobody picked, a match goes through Simulation::new
+	pub fn new (c: CharacterState) -> Fighter {
+		let character = registry::character(registry::DEFAULT_CHARACTER).expect("the default character's file is missing");
+		Fighter::with_stats(registry::DEFAULT_CHARACTER, &character.stats, c)
+	}
+
+	pub fn with_stats (character: CharacterId, stats: &Stats, c: CharacterState) -> Fighter {
+		{
+			let mut particle = c.particle.borrow_mut();
+			particle.health = stats.health;
+			particle.max_health = stats.health;
+			particle.inverse_mass = 1.0 / stats.mass;
+		}
 		Fighter {
-			name: Characters::Python,
-			health: 270,
+			character,
+			health: stats.health,
 			char_state: c,
-			speed: 20, // arbitrary #
-			weight: 180,
-			gravity: -9.8,
-			max_fall_speed: 20,
-			walk_speed: 500,
-			run_speed: 15,
-			max_air_speed: 5,
-			aerial_transition_speed: 3,
-			crawl_speed: 3,
-			dodge_speed: 5,
-			friction: -0.1,
-			static_grip: 20,
-			pivot_grip: 25,
-			air_resistance: -0.1,
-			air_control: 5,
-			jumps: 2,
-			jump_height: 10,
-			short_hop_height: 5,
-			air_jump_height: 7,
-			heavy_land_lag: 2,
-			fastfall_multiplier: 1.25,
-			shield_size: 3,
-      		textures: HashMap::new(),
+			speed: stats.speed,
+			weight: stats.weight,
+			gravity: stats.gravity,
+			max_fall_speed: stats.max_fall_speed,
+			walk_speed: stats.walk_speed,
+			run_speed: stats.run_speed,
+			max_air_speed: stats.max_air_speed,
+			aerial_transition_speed: stats.aerial_transition_speed,
+			crawl_speed: stats.crawl_speed,
+			dodge_speed: stats.dodge_speed,
+			friction: stats.friction,
+			static_grip: stats.static_grip,
+			pivot_grip: stats.pivot_grip,
+			air_resistance: stats.air_resistance,
+			air_control: stats.air_control,
+			jumps: stats.jumps,
+			jump_height: stats.jump_height,
+			short_hop_height: stats.short_hop_height,
+			air_jump_height: stats.air_jump_height,
+			heavy_land_lag: stats.heavy_land_lag,
+			fastfall_multiplier: stats.fastfall_multiplier,
+			shield_size: stats.shield_size,
 		}
 	}
 
@@ -128,17 +177,6 @@ impl <'t> Fighter <'t> {
     pub fn fastfall_multiplier(&self) -> &f32 {&self.fastfall_multiplier}
     pub fn shield_size(&self) -> &i32 {&self.shield_size}
 
-	pub fn textures(&self) -> &Texture<'t> {
-		match &self.textures.get(&self.char_state.state) {
-			Some(texture) => texture,
-			None => panic!("Texture issue in fighter"),
-		}
-	}
-
-	pub fn add_texture(&mut self, s: animation::sprites::State, t: Texture<'t>) {
-            &self.textures.insert(s, t);
-	}
-
 	// update Particle position
 	pub fn update_position(&mut self, force: &PhysVec) {
 		let mut scaled = force.clone();
@@ -188,8 +226,13 @@ impl <'t> Fighter <'t> {
 
 // Implementations
 impl CharacterState {
-	// initialize
+	// initialize, animated like the default character
 	pub fn new() -> CharacterState {
+		let character = registry::character(registry::DEFAULT_CHARACTER).expect("the default character's file is missing");
+		CharacterState::with_animations(character.animations.clone())
+	}
+
+	pub fn with_animations(animations: Arc<HashMap<State, Animation>>) -> CharacterState {
 		// current default values
 		// Stretch goals: expand to not use default values
 		let position = Particle::new(PhysVec::new(0f32,-300f32), 0.01, 180f32, 270, 5);
@@ -204,114 +247,19 @@ impl CharacterState {
 			auto_repeat: true,
 			next_state: animation::sprites::State::Idle,
 			direction: input::movement::Direction::Up,
-			hitbox: None,
-			hurtbox: None,
-			blockbox: None,
+			pushbox: None,
+			hurtboxes: Vec::new(),
+			hitboxes: Vec::new(),
+			animations,
 		}
 	}
 
-    // advancing frames
+    // advancing frames, the sprite for each frame comes from the character's animation for the state
     pub fn advance_frame(&mut self) {
 		self.frame_count = (self.frame_count + 1) % (self.frames_per_state+1);
-
-    	match self.state{
-    		animation::sprites::State::Idle =>{
-    			if self.frame_count < 7{
-    				self.current_frame = 0;
-    			}else if self.frame_count < 13 {
-    				self.current_frame = 1;
-    			}else if self.frame_count < 19 {
-    				self.current_frame = 2;
-    			}else if self.frame_count < 24 {
-    				self.current_frame = 3;
-    			}else{
-    				self.current_frame = 4;
-    			}
-    		}
-    		animation::sprites::State::Walk =>{
-    			if self.frame_count < 6 {
- 					self.current_frame = 0;
-    			}else if self.frame_count < 11{
- 					self.current_frame = 1;
-    			}else if self.frame_count < 16{
- 					self.current_frame = 2;
-    			}else if self.frame_count < 21{
- 					self.current_frame = 3;
-    			}else if self.frame_count < 26{
- 					self.current_frame = 4;
-    			}else{
- 					self.current_frame = 5;
-    			}
-    		}
-    		animation::sprites::State::Jump =>{
-    			if self.frame_count < 6{
-    				self.current_frame = 0;
-    			}else if self.frame_count < 11 {
-    				self.current_frame = 1;
-    			}else if self.frame_count < 16 {
-    				self.current_frame = 2;
-    			}else if self.frame_count < 21 {
-    				self.current_frame = 3;
-    			}else if self.frame_count < 26{
-    				self.current_frame = 4;
-    			}else{
-    				self.current_frame = 5;
-    			}
-    		}
-    		animation::sprites::State::FJump =>{
-    			if self.frame_count < 7 {
- 					self.current_frame = 0;
-    			}else if self.frame_count < 13{
- 					self.current_frame = 1;
-    			}else if self.frame_count < 19{
- 					self.current_frame = 2;
-    			}else if self.frame_count < 25{
- 					self.current_frame = 3;
-    			}else if self.frame_count < 31{
- 					self.current_frame = 4;
-    			}else if self.frame_count < 37{
- 					self.current_frame = 5;
-    			}else{
-    				self.current_frame = 6;
-    			}
-    		}
-    		animation::sprites::State::LPunch =>{
-    			if self.frame_count < 6 {
-    				self.current_frame = 0;
-    			}else if self.frame_count < 11 {
-    				self.current_frame = 1;
-    			}else if self.frame_count <= 17 {
-    				self.current_frame = 2;
-    			}
-    		}
-    		animation::sprites::State::LKick =>{
-    			if self.frame_count < 8 {
-    				self.current_frame = 0;
-    			}else if self.frame_count < 14  {
-    				self.current_frame = 1;
-    			}else{
-    				self.current_frame = 2;
-    			}
-    		}
-    		animation::sprites::State::HKick =>{
-    			if self.frame_count < 6 {
-    				self.current_frame = 0;
-    			}else if self.frame_count < 10{
-    				self.current_frame = 1;
-    			}else if self.frame_count < 14{
-    				self.current_frame = 2;
-    			}else if self.frame_count < 21{
-    				self.current_frame = 3;
-    			}else if self.frame_count <= 35{
-    				self.current_frame = 4
-    			}
-    		}
-    		animation::sprites::State::Block =>{}
-    	}
+		self.current_frame = self.animation().sprite_at(self.frame_count);
     	//println!("Frame count is: {}    Frame Per State is: {}    Current Frame is: {}    State is: {:?}",
     	//	self.frame_count, self.frames_per_state, self.current_frame, self.state);
-
-
     }
 	// convenience f(x)
 	// getters
@@ -319,12 +267,14 @@ impl CharacterState {
 	pub fn state(&self)     	-> &animation::sprites::State 	{ &self.state }
 	pub fn frames_per_state(&self) -> i32 						{ self.frames_per_state } // for testing
 	pub fn current_frame(&self) -> i32 							{ self.current_frame }
+	pub fn animation(&self)		-> &Animation					{ &self.animations[&self.state] }
 	pub fn sprite(&self) 		-> &Rect 						{ &self.sprite }
 	pub fn auto_repeat(&self)	-> bool 						{ self.auto_repeat }
 	pub fn next_state(&self) 	-> &animation::sprites::State 	{ &self.next_state }
 	pub fn x(&self)				-> i32							{ self.particle.borrow().position.x as i32 }
 	pub fn y(&self)				-> i32							{ self.particle.borrow().position.y as i32 }
 	pub fn health(&self)		-> i32 							{ self.particle.borrow().health}
+	pub fn max_health(&self)	-> i32 							{ self.particle.borrow().max_health }
 	pub fn velocity(&self)		-> (f32, f32)					{ self.particle.borrow().velocity.raw() }
 	pub fn acceleration(&self)		-> (f32, f32)					{ self.particle.borrow().acceleration.raw() }
 	pub fn direction(&self)		-> &input::movement::Direction	{ &self.direction }
@@ -334,6 +284,7 @@ impl CharacterState {
 	// pub fn set_position(&mut self, p: PhysVec)						{ self.position.borrow().position.replace(&p); }
 	pub fn set_state(&mut self, s: animation::sprites::State)		{ self.state = s;
 																	  self.frames_per_state = animation::sprites::get_frame_cnt(self);
+																	  self.particle.borrow_mut().move_landed = false;
 																	  // println!("s: {:?}, cf: {}", self.state, self.current_frame);
 																	}
 	pub fn set_current_frame(&mut self, i: i32)						{ self.current_frame = (self.current_frame + i) % self.frames_per_state; } // need to stay within # of frames
@@ -354,62 +305,57 @@ impl CharacterState {
 		}
 	}
 	
-	pub fn remove(&mut self, box_type: String) {
-		let mut none = None;
-		let link = {match box_type.as_str() {
-			"hurt" => &mut self.hurtbox,
-			"hit" => &mut self.hitbox,
-			"block" => &mut self.blockbox,
-			_ => &mut none,
-		}};
-
-		link.take().map(|l| {
-			l.borrow().getNodeRef().map(|n| {
-				// println!("\nremoving {:?}\n", n);
-				n.remove()
-			});
-		});
+	// take this fighter's "hurt" (and block), "hit" or "push" boxes out of the tree
+	pub fn remove(&mut self, bvh: &BVHierarchy, box_type: String) {
+		let types = match box_type.as_str() {
+			"hurt" => { self.hurtboxes.clear(); vec![CollisionObjectType::HurtBox, CollisionObjectType::BlockBox] },
+			"hit" => { self.hitboxes.clear(); vec![CollisionObjectType::HitBox] },
+			"push" => { self.pushbox = None; vec![CollisionObjectType::PushBox] },
+			_ => Vec::new(),
+		};
+		let particle = self.particle.clone();
+		bvh.remove_where(|co| Rc::ptr_eq(&co.particle, &particle) && types.contains(&co.obj_type));
+	}
+	// the point between the fighter's feet, which its hurt and hit boxes are placed from
+	pub fn origin(&self) -> (i32, i32) {
+		(self.x()+W_OFFSET+SPRITE_W as i32/2, self.y()+H_OFFSET+SPRITE_H as i32)
 	}
-	pub fn insert_hit_box(&mut self, bvh: &BVHierarchy) {
+	// lean into the attack, every frame of it
+	pub fn attack_velocity(&mut self) {
+		let mut particle = self.particle.borrow_mut();
+		particle.velocity.x = if self.direction == Direction::Right { 25.0 } else { -25.0 };
+		particle.velocity.y = 300.0;
+	}
+	pub fn insert_hit_boxes(&mut self, bvh: &BVHierarchy, data: &MoveData) {
 		// println!("inserting hit box...");
-		self.remove("hit".to_string());
-		let vel_particle = self.particle.clone();
-		let rect = {
-			if self.direction == Direction::Right {
-				vel_particle.borrow_mut().velocity.x = 25.0;
-				Rect::new(self.x()+W_OFFSET+SPRITE_W as i32/2, self.y()+H_OFFSET, SPRITE_W as u32, SPRITE_H/2)
-			}
-			else {
-				vel_particle.borrow_mut().velocity.x = -25.0;
-				Rect::new(self.x()+W_OFFSET-SPRITE_W as i32/2, self.y()+H_OFFSET, SPRITE_W as u32, SPRITE_H/2)
-			}
-		};
-		vel_particle.borrow_mut().velocity.y = 300.0;
-		self.hitbox = Some(bvh.insert(
-			CollisionObject {
-				obj_type: CollisionObjectType::HitBox, 
-				area: SPRITE_W as u32 * SPRITE_H/2,
-				rect: rect,
-				noderef: None,
-				particle: vel_particle,
-			}
-		));
+		self.remove(bvh, "hit".to_string());
+		let facing_right = self.direction == Direction::Right;
+		let (origin, animations) = (self.origin(), self.animations.clone());
+		for b in animations[&self.state].hitboxes_at(self.frame_count) {
+			let mut hitbox = CollisionObject::new_from(CollisionObjectType::HitBox, b.place(origin, facing_right), self.particle.clone());
+			hitbox.attack = Some(Attack::new(data, facing_right));
+			self.hitboxes.push(bvh.insert(hitbox));
+		}
 	}
-	pub fn insert_hurt_box(&mut self, bvh: &BVHierarchy) {
+	// block boxes instead while blocking
+	pub fn insert_hurt_boxes(&mut self, bvh: &BVHierarchy) {
 		// println!("inserting hurt box...");
-		self.remove("hurt".to_string());
-		self.hurtbox = Some(bvh.insert(
-			CollisionObject::new(
-				CollisionObjectType::HurtBox, self.x()+W_OFFSET, self.y()+H_OFFSET, SPRITE_W, SPRITE_H, self.particle.clone())
-		));
+		self.remove(bvh, "hurt".to_string());
+		let guarding = self.state == State::Block || self.state == State::Blockstun;
+		let obj_type = if guarding { CollisionObjectType::BlockBox } else { CollisionObjectType::HurtBox };
+		let facing_right = self.direction == Direction::Right;
+		let (origin, animations) = (self.origin(), self.animations.clone());
+		for b in animations[&self.state].hurtboxes_at(self.frame_count) {
+			self.hurtboxes.push(bvh.insert(
+				CollisionObject::new_from(obj_type, b.place(origin, facing_right), self.particle.clone())
+			));
+		}
 	}
-	pub fn insert_block_box(&mut self, bvh: &BVHierarchy) {
-		// println!("inserting block box...");
-		self.remove("block".to_string());
-		self.particle.borrow_mut().velocity.y = 300.0;
-		self.blockbox = Some(bvh.insert(
+	pub fn insert_push_box(&mut self, bvh: &BVHierarchy) {
+		self.remove(bvh, "push".to_string());
+		self.pushbox = Some(bvh.insert(
 			CollisionObject::new(
-				CollisionObjectType::BlockBox, self.x()+W_OFFSET, self.y()+H_OFFSET, SPRITE_W, SPRITE_H, self.particle.clone())
+				CollisionObjectType::PushBox, self.x()+W_OFFSET, self.y()+H_OFFSET, SPRITE_W, SPRITE_H, self.particle.clone())
 		));
 	}
 	pub fn update_bounding_boxes(&mut self, bvh: &BVHierarchy) {
@@ -425,47 +371,64 @@ impl CharacterState {
         else if y <= 120.0 && x > -600.0 && x < 600.0 {
 		    self.particle.borrow_mut().position.y = y.clamp(-1000.0, 92.0);
         }
+		self.insert_push_box(bvh);
+		self.insert_hurt_boxes(bvh);
 		match &self.state {
-			State::Block => {
-				self.remove("hit".to_string());
-				self.remove("hurt".to_string());
-				self.insert_block_box(&bvh);
+			State::Block | State::Blockstun => {
+				self.remove(bvh, "hit".to_string());
+				self.particle.borrow_mut().velocity.y = 300.0;
 			},
+			// an attack can be hit all the way through, its hitboxes are only out on the active frames
+			// and go away once it connects
 			State::LPunch | State::HKick | State::LKick => {
-				self.remove("block".to_string());
-				self.remove("hurt".to_string());
-				self.insert_hit_box(&bvh);
-			},
-			_ => {
-				self.remove("hit".to_string());
-				self.remove("block".to_string());
-				self.insert_hurt_box(&bvh);
+				self.attack_velocity();
+				let data = self.animation().attack.clone();
+				match data {
+					Some(data) if data.is_active(self.frame_count) && !self.particle.borrow().move_landed => self.insert_hit_boxes(bvh, &data),
+					_ => self.remove(bvh, "hit".to_string()),
+				}
 			},
+			_ => self.remove(bvh, "hit".to_string()),
 		}
-		// println!("\nhitbox: {:?}\nblockbox: {:?}\nhurtbox: {:?}\n", self.hitbox, self.blockbox, self.hurtbox);
+		// println!("\nhitboxes: {:?}\nhurtboxes: {:?}\n", self.hitboxes, self.hurtboxes);
 	}
-	pub fn get_bb(&self) -> Rect {
-		if self.hurtbox.is_some() {
-			self.hurtbox.clone().unwrap().borrow().rect.clone()
+	// save the simulation state of this character
+	pub fn snapshot(&self) -> CharacterSnapshot {
+		CharacterSnapshot {
+			particle: self.particle.borrow().snapshot(),
+			state: self.state,
+			frames_per_state: self.frames_per_state,
+			frame_count: self.frame_count,
+			current_frame: self.current_frame,
+			sprite: self.sprite,
+			auto_repeat: self.auto_repeat,
+			direction: self.direction,
+			next_state: self.next_state,
 		}
-		else if self.hitbox.is_some() {
-			self.hitbox.clone().unwrap().borrow().rect.clone()
-		}
-		else if self.blockbox.is_some(){
-			self.blockbox.clone().unwrap().borrow().rect.clone()
-		}
-		else {Rect::new(0,0,0,0)}
+	}
+	// rewind to a saved state, particle is restored in place since the bvh shares it
+	// bounding boxes are rebuilt on the next update_bounding_boxes
+	pub fn restore(&mut self, snapshot: &CharacterSnapshot) {
+		self.particle.borrow_mut().restore(&snapshot.particle);
+		self.state = snapshot.state;
+		self.frames_per_state = snapshot.frames_per_state;
+		self.frame_count = snapshot.frame_count;
+		self.current_frame = snapshot.current_frame;
+		self.sprite = snapshot.sprite;
+		self.auto_repeat = snapshot.auto_repeat;
+		self.direction = snapshot.direction;
+		self.next_state = snapshot.next_state;
+	}
+	// the fighter's whole body
+	pub fn get_bb(&self) -> Rect {
+		self.pushbox.as_ref().map_or(Rect::new(0,0,0,0), |b| b.borrow().rect)
 	}
 	pub fn get_node(&self) -> CollisionObject {
-		if self.hurtbox.is_some() {
-			self.hurtbox.clone().unwrap().borrow().clone()
-		}
-		else if self.hitbox.is_some() {
-			self.hitbox.clone().unwrap().borrow().clone()
-		}
-		else {
-			self.blockbox.clone().unwrap().borrow().clone()
-		}
+		self.pushbox.clone().unwrap().borrow().clone()
+	}
+	// where the fighter can be hit and is hitting this frame, to draw
+	pub fn hit_and_hurt_boxes(&self) -> Vec<Rect> {
+		self.hurtboxes.iter().chain(self.hitboxes.iter()).map(|b| b.borrow().rect).collect()
 	}
 }
 
@@ -477,10 +440,10 @@ pub mod test {
 		let mut f = Fighter::new(CharacterState::new());
 		let platform = Rect::new(40, 620, CAM_W-80, CAM_H-680);
 		let collisions = BVHierarchy::new(CollisionObject::new_from(CollisionObjectType::Platform, platform.clone(),
-		RefCell::new(Particle::new(
-			PhysVec::new(platform.x as f32, platform.y as f32), 0.5, 2000000000.0))));
+		Rc::new(RefCell::new(Particle::new(
+			PhysVec::new(platform.x as f32, platform.y as f32), 0.5, 2000000000.0, 0, 0)))));
 		f.char_state.update_bounding_boxes(&collisions);
 
-		assert_eq!(f.char_state.position().position.raw(), (0.0, 0.0));
+		assert_eq!(f.char_state.position().position.raw(), (0.0, -300.0));
 	}
 }
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+use serde_derive::Deserialize;
+
+use crate::animation::sprites::{Animation, BodyPart, FrameBox, MoveData, State};
+use crate::characters::characterAbstract::{CharacterState, Fighter, Stats};
+use crate::characters::registry::CharacterId;
+
+// every State a character file has to animate, by the name its section uses
+pub const STATE_NAMES: [(&str, State); 12] = [
+	("idle", State::Idle),
+	("walk", State::Walk),
+	("jump", State::Jump),
+	("fjump", State::FJump),
+	("lpunch", State::LPunch),
+	("lkick", State::LKick),
+	("hkick", State::HKick),
+	("block", State::Block),
+	("hitstun", State::Hitstun),
+	("blockstun", State::Blockstun),
+	("knockdown", State::Knockdown),
+	("getup", State::GetUp),
+];
+
+// the parts a box can be for, by the name after `hurtbox.` or `hitbox.`
+const PART_NAMES: [(&str, BodyPart); 3] = [
+	("head", BodyPart::Head),
+	("body", BodyPart::Body),
+	("limb", BodyPart::Limb),
+];
+
+// a character file as TOML gives it to us, CharacterDef::parse checks what the types can't
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CharacterFile {
+	id: i64,
+	name: String,
+	stats: Stats,
+	state: HashMap<String, StateFile>,
+}
+
+// one [state.<name>] section, the frame data is only there for attacks
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+	texture: String,
+	frames: i32,
+	sprite_starts: Vec<i32>,
+	startup: Option<i32>,
+	active: Option<i32>,
+	recovery: Option<i32>,
+	damage: Option<i32>,
+	chip_damage: Option<i32>,
+	hitstun: Option<i32>,
+	blockstun: Option<i32>,
+	pushback: Option<f32>,
+	knockback: Option<Vec<f32>>,
+	knockdown: Option<bool>,
+	#[serde(default)]
+	hurtbox: HashMap<String, Vec<i32>>, // by part name
+	#[serde(default)]
+	hitbox: HashMap<String, Vec<i32>>,
+}
+
+// everything a character file describes
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharacterDef {
+	pub id: CharacterId,
+	pub name: String, // shown in menus
+	pub stats: Stats,
+	pub animations: Arc<HashMap<State, Animation>>,
+}
+
+impl CharacterDef {
+	// a character file is TOML: `id` and `name`, then a [stats] section and a [state.<name>] section for every State
+	pub fn parse(contents: &str) -> Result<CharacterDef, String> {
+		let file: CharacterFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+		if file.id < 1 || file.id > u16::MAX as i64 {
+			return Err(format!("id must be a whole number from 1 to {}", u16::MAX));
+		}
+		let name = file.name.trim().to_string();
+		if name.is_empty() {
+			return Err("name must be some \"text\"".to_string());
+		}
+		check_stats(&file.stats)?;
+		let mut states = file.state;
+		if let Some(unknown) = states.keys().filter(|name| !STATE_NAMES.iter().any(|(n, _)| n == name)).min() {
+			return Err(format!("unknown section [state.{}]", unknown));
+		}
+		let mut animations = HashMap::new();
+		for (state_name, state) in STATE_NAMES.iter() {
+			let settings = states.remove(*state_name).ok_or(format!("no [state.{}] section", state_name))?;
+			let animation = build_animation(settings).map_err(|e| format!("[state.{}]: {}", state_name, e))?;
+			animations.insert(*state, animation);
+		}
+		Ok(CharacterDef { id: CharacterId(file.id as u16), name, stats: file.stats, animations: Arc::new(animations) })
+	}
+
+	pub fn load(path: &str) -> Result<CharacterDef, String> {
+		let contents = fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+		CharacterDef::parse(&contents).map_err(|e| format!("{}: {}", path, e))
+	}
+
+	// a fresh fighter of this character, standing at the spawn point
+	pub fn build(&self) -> Fighter {
+		Fighter::with_stats(self.id, &self.stats, CharacterState::with_animations(self.animations.clone()))
+	}
+}
+
+// serde has already made sure every stat is given, and nothing else
+fn check_stats(stats: &Stats) -> Result<(), String> {
+	if stats.health <= 0 || stats.mass <= 0.0 {
+		return Err("health and mass must be above 0".to_string());
+	}
+	Ok(())
+}
+
+fn build_animation(mut settings: StateFile) -> Result<Animation, String> {
+	let frames = settings.frames;
+	let attack = build_move(&settings)?;
+	let hurtboxes = build_boxes(&mut settings.hurtbox, "hurtbox", frames)?;
+	let hitboxes = build_boxes(&mut settings.hitbox, "hitbox", frames)?;
+	if frames < 1 {
+		return Err("frames must be at least 1".to_string());
+	}
+	if hurtboxes.is_empty() {
+		return Err("needs at least one hurtbox".to_string());
+	}
+	match &attack {
+		None if !hitboxes.is_empty() => return Err("hitboxes need the frame data of an attack".to_string()),
+		Some(_) if hitboxes.is_empty() => return Err("an attack needs at least one hitbox".to_string()),
+		Some(data) if hitboxes.iter().any(|b| !data.is_active(b.first) || !data.is_active(b.last)) =>
+			return Err("hitboxes have to stay within the active frames".to_string()),
+		_ => (),
+	}
+	let sprite_starts = settings.sprite_starts;
+	if sprite_starts.first() != Some(&0) || sprite_starts.windows(2).any(|w| w[0] >= w[1]) || sprite_starts.iter().any(|s| *s > frames) {
+		return Err(format!("sprite_starts must go up from 0 and stay within the {} frames", frames));
+	}
+	Ok(Animation { texture: settings.texture, frames, sprite_starts, attack, hurtboxes, hitboxes })
+}
+
+// `<kind>.<part> = [first frame, last frame, x, y, width, height]`, more boxes for the part follow on in the same list
+fn build_boxes(parts: &mut HashMap<String, Vec<i32>>, kind: &str, frames: i32) -> Result<Vec<FrameBox>, String> {
+	let mut boxes = Vec::new();
+	for (part_name, part) in PART_NAMES.iter() {
+		let key = format!("{}.{}", kind, part_name);
+		let numbers = match parts.remove(*part_name) {
+			None => continue,
+			Some(numbers) if !numbers.is_empty() && numbers.len() % 6 == 0 => numbers,
+			_ => return Err(format!("{} = [first, last, x, y, width, height, ...] takes six numbers a box", key)),
+		};
+		for n in numbers.chunks(6) {
+			if n[0] < 1 || n[0] > n[1] || n[1] > frames || n[4] < 1 || n[5] < 1 {
+				return Err(format!("{} has a box outside the {} frames or without a size", key, frames));
+			}
+			boxes.push(FrameBox { part: *part, first: n[0], last: n[1], x: n[2], y: n[3], width: n[4] as u32, height: n[5] as u32 });
+		}
+	}
+	if let Some(unknown) = parts.keys().min() {
+		return Err(format!("unknown setting `{}.{}`", kind, unknown));
+	}
+	Ok(boxes)
+}
+
+// a state that has any of the frame data is an attack and needs all of it (knockdown is optional)
+fn build_move(settings: &StateFile) -> Result<Option<MoveData>, String> {
+	let counts = [
+		("startup", settings.startup), ("active", settings.active), ("recovery", settings.recovery),
+		("damage", settings.damage), ("chip_damage", settings.chip_damage),
+		("hitstun", settings.hitstun), ("blockstun", settings.blockstun),
+	];
+	if counts.iter().all(|(_, n)| n.is_none()) && settings.pushback.is_none() && settings.knockback.is_none() && settings.knockdown.is_none() {
+		return Ok(None);
+	}
+	let mut n = [0; 7];
+	for (i, (key, count)) in counts.iter().enumerate() {
+		n[i] = match count {
+			Some(count) if *count >= 0 => *count,
+			Some(_) => return Err(format!("`{}` can't be below 0", key)),
+			None => return Err(format!("an attack needs {} = number", key)),
+		};
+	}
+	let [startup, active, recovery, damage, chip_damage, hitstun, blockstun] = n;
+	let pushback = settings.pushback.ok_or("an attack needs pushback = number".to_string())?;
+	let knockback = match settings.knockback.as_deref() {
+		Some([x, y]) => (*x, *y),
+		_ => return Err("an attack needs knockback = [x, y]".to_string()),
+	};
+	let knockdown = settings.knockdown.unwrap_or(false);
+	if active < 1 || startup + active + recovery != settings.frames {
+		return Err(format!("startup, active and recovery have to add up to the {} frames, with at least 1 active", settings.frames));
+	}
+	Ok(Some(MoveData { startup, active, recovery, damage, chip_damage, hitstun, blockstun, pushback, knockback, knockdown }))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::physics::rect::Rect;
+
+	const PYTHON_FILE: &str = include_str!("../assets/characters/python.toml");
+
+	#[test]
+	pub fn testShippedFiles() {
+		let python = CharacterDef::parse(PYTHON_FILE).unwrap();
+		let java = CharacterDef::load("src/assets/characters/java.toml").unwrap();
+		assert_eq!((python.id, python.name.as_str()), (CharacterId(1), "Python"));
+		assert_eq!((java.id, java.name.as_str()), (CharacterId(2), "Java"));
+		assert_eq!(python.animations[&State::FJump].frames, 42);
+		assert_eq!(java.animations[&State::Idle].texture, "src/assets/images/characters/java/idle.png");
+
+		let fighter = java.build();
+		assert_eq!(fighter.character, CharacterId(2));
+		assert_eq!(fighter.get_health(), 270);
+		assert_eq!(fighter.char_state.health(), 270);
+		// health is capped at what the file gives, not at 270
+		let tank = CharacterDef::parse(&PYTHON_FILE.replacen("health = 270", "health = 300", 1)).unwrap().build();
+		tank.char_state.particle.borrow_mut().update_health(-10);
+		assert_eq!((tank.char_state.health(), tank.char_state.max_health()), (300, 300));
+		assert_eq!(*fighter.jumps(), 2);
+		// the sprite timings the game had before they moved into the files
+		let hkick = &fighter.char_state.animations[&State::HKick];
+		let sprites: Vec<i32> = [0, 5, 6, 13, 14, 35].iter().map(|f| hkick.sprite_at(*f)).collect();
+		assert_eq!(sprites, vec![0, 0, 1, 2, 3, 4]);
+		let lkick = fighter.char_state.animations[&State::LKick].attack.clone().unwrap();
+		assert_eq!((lkick.startup, lkick.active, lkick.recovery, lkick.damage, lkick.knockback), (7, 6, 12, 15, (250.0, -200.0)));
+		assert!(fighter.char_state.animations[&State::Walk].attack.is_none());
+		assert!(!lkick.knockdown && fighter.char_state.animations[&State::HKick].attack.as_ref().unwrap().knockdown);
+		// the jump tucks its legs up, a box turned around lands on the other side of the origin
+		let jump = &fighter.char_state.animations[&State::Jump];
+		let legs: Vec<Rect> = [1, 10].iter().map(|f| jump.hurtboxes_at(*f).find(|b| b.part == BodyPart::Limb).unwrap().place((0, 0), true)).collect();
+		assert!(legs[1].bottom() < legs[0].bottom());
+		let kick = fighter.char_state.animations[&State::LKick].hitboxes[0].clone();
+		assert_eq!((kick.place((0, 0), true).left(), kick.place((0, 0), false).right()), (25, -25));
+	}
+
+	#[test]
+	pub fn testBadFiles() {
+		let error = |from: &str, to: &str| CharacterDef::parse(&PYTHON_FILE.replacen(from, to, 1)).unwrap_err();
+		assert_eq!(error("id = 1", "id = 70000"), "id must be a whole number from 1 to 65535");
+		assert!(error("id = 1\n", "").starts_with("missing field `id`"));
+		assert_eq!(error("name = \"Python\"", "name = \" \""), "name must be some \"text\"");
+		assert!(error("jumps = 2", "jumps = 2.5").contains("for key `stats.jumps` at line 24"));
+		assert!(error("jumps = 2", "jumps = two").ends_with("at line 24 column 9"));
+		assert!(error("jumps = 2", "jumpz = 2").starts_with("unknown field `jumpz`"));
+		assert_eq!(error("[state.block]", "[state.crouch]"), "unknown section [state.crouch]");
+		assert!(error("[state.block]", "[state.walk]").contains("`state.walk` at line 132"));
+		assert!(error("frames = 1\n", "frames = 1\nspeed = 3\n").contains("unknown field `speed`"));
+		assert_eq!(error("[state.block]\n", "[state.block]\nhurtbox.tail = [1, 1, 0, 0, 1, 1]\n"), "[state.block]: unknown setting `hurtbox.tail`");
+		assert_eq!(error("sprite_starts = [0, 6, 11]", "sprite_starts = [0, 11, 6]"),
+			"[state.lpunch]: sprite_starts must go up from 0 and stay within the 17 frames");
+		assert_eq!(error("hitstun = 12\n", ""), "[state.lpunch]: an attack needs hitstun = number");
+		assert_eq!(error("recovery = 7", "recovery = 8"),
+			"[state.lpunch]: startup, active and recovery have to add up to the 17 frames, with at least 1 active");
+		assert_eq!(error("chip_damage = 1", "chip_damage = -1"), "[state.lpunch]: `chip_damage` can't be below 0");
+		assert_eq!(error("knockback = [200.0, -150.0]", "knockback = [200.0]"), "[state.lpunch]: an attack needs knockback = [x, y]");
+		assert!(error("knockdown = true\nhurtbox", "knockdown = 1\nhurtbox").contains("expected a boolean for key `state.hkick.knockdown`"));
+		assert_eq!(error("hitbox.limb = [6, 10, 30, -175, 60, 30]", "hitbox.limb = [5, 10, 30, -175, 60, 30]"),
+			"[state.lpunch]: hitboxes have to stay within the active frames");
+		assert_eq!(error("hitbox.limb = [6, 10, 30, -175, 60, 30]", "hitbox.limb = [6, 10, 30, -175, 60]"),
+			"[state.lpunch]: hitbox.limb = [first, last, x, y, width, height, ...] takes six numbers a box");
+		assert_eq!(error("hurtbox.head = [1, 30, -20, -210, 40, 50]", "hurtbox.head = [1, 31, -20, -210, 40, 50]"),
+			"[state.idle]: hurtbox.head has a box outside the 30 frames or without a size");
+		assert_eq!(error("sprite_starts = [0, 7, 13, 19, 24]", "sprite_starts = [0, 7, 13, 19, 24]\nhitbox.body = [1, 2, 0, 0, 1, 1]"),
+			"[state.idle]: hitboxes need the frame data of an attack");
+		assert!(CharacterDef::load("src/assets/characters/nobody.toml").unwrap_err().starts_with("couldn't read"));
+	}
+
+	#[test]
+	pub fn testTomlSyntax() {
+		// all of TOML works, not just one value a line
+		let file = PYTHON_FILE
+			.replacen("name = \"Python\"", "name = \"C# \\\"sharp\\\"\" # a comment", 1)
+			.replacen("sprite_starts = [0, 7, 13, 19, 24]", "sprite_starts = [\n\t0, 7, 13, # comments too\n\t19, 24,\n]", 1);
+		let character = CharacterDef::parse(&file).unwrap();
+		assert_eq!(character.name, "C# \"sharp\"");
+		assert_eq!(character.animations[&State::Idle].sprite_starts, vec![0, 7, 13, 19, 24]);
+	}
+}
@@ -1 +1,3 @@
-pub mod characterAbstract;
\ No newline at end of file
+pub mod characterAbstract;
+pub mod loader;
+pub mod registry;
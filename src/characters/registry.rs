@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::OnceLock;
+use bincode::serialize;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::characters::loader::{CharacterDef, STATE_NAMES};
+use crate::networking::checksum;
+
+// where the game looks for character files, relative to where it runs from like the textures
+// every player and the server need the same files, they decide how a match plays out
+pub const CHARACTERS_DIR: &str = "src/assets/characters";
+
+// a character as its file numbers it with `id = ...`
+// replays and the network carry these, so a character keeps its id once it ships
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CharacterId(pub u16);
+
+// what Fighter::new and CharacterState::new build when nobody picked
+pub const DEFAULT_CHARACTER: CharacterId = CharacterId(1); // Python
+
+// every character that was found, by id
+#[derive(Debug)]
+pub struct Registry {
+	characters: BTreeMap<CharacterId, CharacterDef>,
+	hash: u64,
+}
+
+impl Registry {
+	// two characters can't share an id or a name
+	pub fn new(characters: Vec<CharacterDef>) -> Result<Registry, String> {
+		let mut registry = Registry { characters: BTreeMap::new(), hash: 0 };
+		for character in characters {
+			if let Some(other) = registry.get(character.id) {
+				return Err(format!("{} and {} both have id {}", other.name, character.name, character.id.0));
+			}
+			if registry.find(&character.name).is_some() {
+				return Err(format!("two characters are called {}", character.name));
+			}
+			registry.characters.insert(character.id, character);
+		}
+		if registry.characters.is_empty() {
+			return Err("no characters".to_string());
+		}
+		registry.hash = digest(&registry.characters);
+		Ok(registry)
+	}
+
+	// every .toml file in dir
+	pub fn load(dir: &str) -> Result<Registry, String> {
+		let entries = fs::read_dir(dir).map_err(|e| format!("couldn't read {}: {}", dir, e))?;
+		let mut paths: Vec<String> = entries
+			.filter_map(|entry| entry.ok())
+			.map(|entry| entry.path())
+			.filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+			.map(|path| path.to_string_lossy().into_owned())
+			.collect();
+		paths.sort();
+		let characters = paths.iter().map(|path| CharacterDef::load(path)).collect::<Result<Vec<_>, String>>()?;
+		Registry::new(characters).map_err(|e| format!("{}: {}", dir, e))
+	}
+
+	pub fn get(&self, id: CharacterId) -> Option<&CharacterDef> { self.characters.get(&id) }
+
+	// by name, any case, for the command line
+	pub fn find(&self, name: &str) -> Option<&CharacterDef> {
+		self.characters.values().find(|character| character.name.eq_ignore_ascii_case(name))
+	}
+
+	// in id order, for menus
+	pub fn list(&self) -> impl Iterator<Item = &CharacterDef> { self.characters.values() }
+	pub fn len(&self) -> usize { self.characters.len() }
+	pub fn is_empty(&self) -> bool { self.characters.is_empty() }
+
+	// what the players and the server compare before a match, files that differ would play it differently
+	pub fn hash(&self) -> u64 { self.hash }
+}
+
+// FNV-1a over every character in id order, so it is the same on every build and platform
+fn digest(characters: &BTreeMap<CharacterId, CharacterDef>) -> u64 {
+	characters.values().fold(checksum::FNV_OFFSET, |hash, character| {
+		let hash = checksum::fnv1a(hash, &serialize(&(character.id, &character.name, &character.stats)).expect("stats always encode"));
+		STATE_NAMES.iter().fold(hash, |hash, (_, state)| {
+			checksum::fnv1a(hash, &serialize(&character.animations[state]).expect("animations always encode"))
+		})
+	})
+}
+
+static REGISTRY: OnceLock<Result<Registry, String>> = OnceLock::new();
+
+// the characters in CHARACTERS_DIR, read the first time anything asks
+// files that couldn't be read are the same error every time after
+pub fn global() -> Result<&'static Registry, String> {
+	REGISTRY.get_or_init(|| Registry::load(CHARACTERS_DIR)).as_ref().map_err(|e| e.clone())
+}
+
+// a character the match needs, an id nobody has means a mismatched install
+pub fn character(id: CharacterId) -> Result<&'static CharacterDef, String> {
+	global()?.get(id).ok_or(format!("no character has id {} in {}", id.0, CHARACTERS_DIR))
+}
+
+// a character picked by name on the command line
+pub fn named(name: &str) -> Result<CharacterId, String> {
+	global()?.find(name).map(|character| character.id).ok_or(format!("no character is called `{}`, `characters` lists them", name))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	pub fn testShippedCharacters() {
+		let registry = global().unwrap();
+		let names: Vec<(u16, &str)> = registry.list().map(|c| (c.id.0, c.name.as_str())).collect();
+		assert_eq!(names[..2], [(1, "Python"), (2, "Java")]);
+		assert_eq!(registry.find("java").map(|c| c.id), Some(CharacterId(2)));
+		assert!(registry.find("cobol").is_none());
+		assert_eq!(character(DEFAULT_CHARACTER).unwrap().name, "Python");
+		assert!(character(CharacterId(999)).is_err());
+		assert_eq!(named("JAVA"), Ok(CharacterId(2)));
+		assert!(named("cobol").is_err());
+	}
+
+	#[test]
+	pub fn testClashes() {
+		let python = character(CharacterId(1)).unwrap().clone();
+		let mut imposter = python.clone();
+		imposter.name = "Snake".to_string();
+		assert_eq!(Registry::new(vec![python.clone(), imposter.clone()]).err(), Some("Python and Snake both have id 1".to_string()));
+		imposter.id = CharacterId(9);
+		imposter.name = "PYTHON".to_string();
+		assert_eq!(Registry::new(vec![python.clone(), imposter.clone()]).err(), Some("two characters are called PYTHON".to_string()));
+		imposter.name = "Snake".to_string();
+		assert_eq!(Registry::new(vec![python, imposter]).map(|r| r.len()), Ok(2));
+		assert!(Registry::new(Vec::new()).is_err());
+		assert!(Registry::load("src/assets/nowhere").unwrap_err().starts_with("couldn't read"));
+	}
+
+	#[test]
+	pub fn testHash() {
+		let python = character(CharacterId(1)).unwrap().clone();
+		let java = character(CharacterId(2)).unwrap().clone();
+		let hash = Registry::new(vec![python.clone(), java.clone()]).unwrap().hash();
+		// files are found in any order
+		assert_eq!(Registry::new(vec![java.clone(), python.clone()]).unwrap().hash(), hash);
+		let mut tank = python;
+		tank.stats.health += 1;
+		assert_ne!(Registry::new(vec![tank, java]).unwrap().hash(), hash);
+	}
+}
@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 
+#[cfg(feature = "gui")]
 use sdl2::keyboard::Keycode;
 
 use crate::characters; // use to get get acces to Fighter struct
@@ -8,6 +9,15 @@ use crate::animation; // used to get States
 
 
 pub fn keyboard_input(player_input: &HashSet<u8>, fighter: &mut characters::characterAbstract::Fighter){
+    // keys are checked lowest first, a HashSet's own order differs between sets and so between peers
+    let mut pressed_keys: Vec<u8> = player_input.iter().cloned().collect();
+    pressed_keys.sort();
+
+    //getting hit or blocking takes over from whatever the fighter was doing
+    if react(fighter) {
+        return;
+    }
+
     //if character animation is over, reset to idle
     if fighter.char_state.frame_count == animation::sprites::get_frame_cnt(&fighter.char_state){
         fighter.char_state.set_state(animation::sprites::State::Idle);
@@ -18,7 +28,7 @@ pub fn keyboard_input(player_input: &HashSet<u8>, fighter: &mut characters::char
     //inputs accepted while idle
     if fighter.char_state.state ==  animation::sprites::State::Idle
        && !player_input.is_empty(){
-        for pressed in player_input.iter(){
+        for pressed in pressed_keys.iter(){
             match pressed{
                 1 =>       {fighter.char_state.direction = input::movement::Direction::Left;
                                      fighter.char_state.set_state(animation::sprites::State::Walk);
@@ -56,7 +66,7 @@ pub fn keyboard_input(player_input: &HashSet<u8>, fighter: &mut characters::char
             fighter.char_state.reset_current_frame();
         }else{
             //inputs that intterupt walk
-            for pressed in player_input.iter(){
+            for pressed in pressed_keys.iter(){
                 match pressed{
                     // 3 =>  {fighter.char_state.set_state(animation::sprites::State::Block);   
                     //                      fighter.char_state.reset_current_frame();   
@@ -105,6 +115,48 @@ pub fn keyboard_input(player_input: &HashSet<u8>, fighter: &mut characters::char
 
 
 
+// hitstun, blockstun and knockdowns, set off by the stun and knockdown a move leaves on the particle (see collisions)
+// the fighter can't act until the stun runs out, then a knockdown still has to get up
+// returns whether input is locked this frame
+fn react(fighter: &mut characters::characterAbstract::Fighter) -> bool {
+    use animation::sprites::State;
+    let state = fighter.char_state.state;
+    let (stun, knockdown) = {
+        let particle = fighter.char_state.particle.borrow();
+        (particle.stun, particle.knockdown)
+    };
+    if stun > 0 {
+        // a fighter on the ground stays there when hit again
+        let reaction = if knockdown || state == State::Knockdown { State::Knockdown }
+                       else if state == State::Block || state == State::Blockstun { State::Blockstun }
+                       else { State::Hitstun };
+        if state != reaction {
+            fighter.char_state.set_state(reaction);
+            fighter.char_state.reset_current_frame();
+        }
+        fighter.char_state.particle.borrow_mut().stun = stun - 1;
+        return true;
+    }
+    match state {
+        //back in control this frame
+        State::Hitstun | State::Blockstun => {
+            fighter.char_state.set_state(State::Idle);
+            fighter.char_state.reset_current_frame();
+            false
+        },
+        State::Knockdown => {
+            fighter.char_state.particle.borrow_mut().knockdown = false;
+            fighter.char_state.set_state(State::GetUp);
+            fighter.char_state.reset_current_frame();
+            true
+        },
+        //until the animation is over
+        State::GetUp => fighter.char_state.frame_count != animation::sprites::get_frame_cnt(&fighter.char_state),
+        _ => false,
+    }
+}
+
+#[cfg(feature = "gui")]
 pub fn convert_input(player_input: &HashSet<Keycode>) -> HashSet<u8>{
 
     let mut set: HashSet<u8> = HashSet::new();
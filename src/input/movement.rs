@@ -95,6 +95,20 @@ pub fn move_char(f: &mut characters::characterAbstract::Fighter){
             f.update_position(&force);
         },
 
+        //knocked back by the hit, falling back down after it
+        animation::sprites::State::Hitstun | animation::sprites::State::Knockdown => {
+            f.char_state.particle.borrow_mut().add_force(&gravity);
+        },
+
+        //sliding on the block's pushback
+        animation::sprites::State::Blockstun => (),
+
+        //standing up in place
+        animation::sprites::State::GetUp => {
+            f.char_state.particle.borrow_mut().velocity.x = 0.0;
+            f.char_state.particle.borrow_mut().velocity.y = 270.0;
+        },
+
         _ => (),
     }
 }
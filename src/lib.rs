@@ -4,7 +4,10 @@ pub mod animation;
 pub mod input;
 pub mod characters;
 pub mod physics;
+pub mod replay;
+pub mod simulation;
 // don't need these mods yet:
 // pub mod physics;
 pub mod view; 
+#[cfg(feature = "gui")]
 pub mod audio;
\ No newline at end of file
@@ -9,8 +9,6 @@ use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::cell::RefCell;
-use std::rc::Rc;
 use std::path::Path;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
@@ -18,22 +16,12 @@ use sdl2::video::WindowContext;
 use std::time::{Instant, Duration}; // needed for FPS
 use std::thread;
 use std::env;
-use physics::collisions::*;
-use physics::vecmath::*;
-use physics::particle::*;
 use view::globals::*;
 use rand::prelude::*;
-use std::collections::VecDeque;
 use std::fs;
 use sdl2::image::InitFlag;
 
-pub mod characters; // for characterAbstract
-pub mod view; // for core
-pub mod input; // for inputHandler and movement
-pub mod animation;
-pub mod networking;
-pub mod physics;
-pub mod audio;
+use street_code_fighter::{view, input, animation, characters, networking, audio, replay, simulation};
 
 //use crate::view::core; // need for SDLCore and TextureManager
 //use crate::view::core::Demo; // need for SDLCore's Demo
@@ -46,7 +34,8 @@ const CAM_H: u32 = 720;
 const FRAME_RATE: f64 = 1.0/60.0;
 
 
-pub fn run_game() -> Result<(), String>{
+// record saves a replay of the match there, p1 and p2 are who plays
+pub fn run_game(record: Option<&str>, p1: characters::registry::CharacterId, p2: characters::registry::CharacterId) -> Result<(), String>{
     let frame_time = Duration::from_secs_f64(FRAME_RATE);
 
     let mut game_window = {
@@ -56,76 +45,21 @@ pub fn run_game() -> Result<(), String>{
         }
     };
 
-    // Creating initial character state
-    let fighter = characters::characterAbstract::CharacterState::new();
-    let fighter2 = characters::characterAbstract::CharacterState::new();
-
-    let mut fighter = characters::characterAbstract::Fighter::new(fighter);
-    let mut fighter2 = characters::characterAbstract::Fighter::new(fighter2);
-    //this is just to make fighter2 spawn a little to the right of fighter
-    fighter2.char_state.particle.borrow_mut().position.replace(&PhysVec::new(300.0, 0.0));
-    fighter2.name = characters::characterAbstract::Characters::Java;
-
-    let mut hazard = physics::hazard::Hazard::new();
+    // the fighters, hazard and stage, with a fresh seed for the hazard every match
+    let sim_config = simulation::SimConfig { p1, p2, seed: rand::random() };
+    let mut sim = simulation::Simulation::new(sim_config)?;
+    let mut recording = record.map(|_| replay::Replay::new(sim_config));
 
     let texture_creator = game_window.wincan.texture_creator();
 
-    let platform = Rect::new(100, 560, CAM_W-200, 30);
-    let wall_l = Rect::new(WALL_L.0, WALL_L.1, WALL_SIZE.0, WALL_SIZE.1);
-    let wall_r = Rect::new(WALL_R.0, WALL_R.1, WALL_SIZE.0, WALL_SIZE.1);
-    let arch = Rect::new(ARCH.0, ARCH.1, ARCH_SIZE.0, ARCH_SIZE.1);
-
 
     //////////////////////////
     // FUNCTIONING
-    // EDIT: Modularize. Challenge: figuring out how to deal with texture's + hashmap lifetime
-    // create HashMap of all textures
-    let mut python_textures = HashMap::new();
-    let mut java_textures = HashMap::new();
-
-    let idle = texture_creator.load_texture("src/assets/images/characters/python/idle.png")?;
-    let walk = texture_creator.load_texture("src/assets/images/characters/python/walk.png")?;
-    let jump = texture_creator.load_texture("src/assets/images/characters/python/jump.png")?;
-    let fjump = texture_creator.load_texture("src/assets/images/characters/python/fjump.png")?;
-    let lpunch = texture_creator.load_texture("src/assets/images/characters/python/lpunch.png")?;
-    let lkick = texture_creator.load_texture("src/assets/images/characters/python/lkick.png")?;
-    let hkick = texture_creator.load_texture("src/assets/images/characters/python/hkick.png")?;
-    let block = texture_creator.load_texture("src/assets/images/characters/python/block.png")?;
-    let hazard_texture = texture_creator.load_texture("src/assets/images/hazards/stalactite100x100.png")?;
-    let background = texture_creator.load_texture("src/assets/images/background/small_background.png")?;
-    let healthbar_left = texture_creator.load_texture("src/assets/images/healthbar/healthbar_left.png")?;
-    let healthbar_right = texture_creator.load_texture("src/assets/images/healthbar/healthbar_right.png")?;
-    let healthbar_fill_left = texture_creator.load_texture("src/assets/images/healthbar/healthbar_fill_left.png")?;
-    let healthbar_fill_right = texture_creator.load_texture("src/assets/images/healthbar/healthbar_fill_right.png")?;
-    let win = texture_creator.load_texture("src/assets/images/end/win.png")?;
-    let lose = texture_creator.load_texture("src/assets/images/end/lose.png")?;
-
-    let java_idle = texture_creator.load_texture("src/assets/images/characters/java/idle.png")?;
-    let java_walk = texture_creator.load_texture("src/assets/images/characters/java/walk.png")?;
-    let java_jump = texture_creator.load_texture("src/assets/images/characters/java/jump.png")?;
-    let java_fjump = texture_creator.load_texture("src/assets/images/characters/java/fjump.png")?;
-    let java_lpunch = texture_creator.load_texture("src/assets/images/characters/java/lpunch.png")?;
-    let java_lkick = texture_creator.load_texture("src/assets/images/characters/java/lkick.png")?;
-    let java_hkick = texture_creator.load_texture("src/assets/images/characters/java/hkick.png")?;
-    let java_block = texture_creator.load_texture("src/assets/images/characters/java/block.png")?;
-
-    python_textures.insert(animation::sprites::State::Idle, idle);
-    python_textures.insert(animation::sprites::State::Walk, walk);
-    python_textures.insert(animation::sprites::State::Jump, jump);
-    python_textures.insert(animation::sprites::State::FJump, fjump);
-    python_textures.insert(animation::sprites::State::LPunch, lpunch);
-    python_textures.insert(animation::sprites::State::LKick, lkick);
-    python_textures.insert(animation::sprites::State::HKick, hkick);
-    python_textures.insert(animation::sprites::State::Block, block);
-
-    java_textures.insert(animation::sprites::State::Idle, java_idle);
-    java_textures.insert(animation::sprites::State::Walk, java_walk);
-    java_textures.insert(animation::sprites::State::Jump, java_jump);
-    java_textures.insert(animation::sprites::State::FJump, java_fjump);
-    java_textures.insert(animation::sprites::State::LPunch, java_lpunch);
-    java_textures.insert(animation::sprites::State::LKick, java_lkick);
-    java_textures.insert(animation::sprites::State::HKick, java_hkick);
-    java_textures.insert(animation::sprites::State::Block, java_block);
+    // each fighter's sprite sheets, by State
+    let fighter1_textures = load_fighter_textures(&texture_creator, sim.fighter1.character)?;
+    let fighter2_textures = load_fighter_textures(&texture_creator, sim.fighter2.character)?;
+
+    let assets = load_assets(&texture_creator)?;
 
     let mut end_message = None;
 
@@ -134,55 +68,29 @@ pub fn run_game() -> Result<(), String>{
     // Self::load_textures(&texture_creator, &mut fighter);
     ////////
 
-    // get random # (for music)
-    let mut rng = rand::thread_rng();
-    let random_num: f64 = rng.gen(); // generates a float between 0 and 1
-    println!("{}", random_num);
-
     // music 
     let clips = audio::handler::Clips::new();
-
-        // randomize between the 3x combat audio tracks
-    if random_num < 0.4 { 
-        sdl2::mixer::Channel::all().play(&clips.combat1, -1); // -1 means repeat forever
-    } else if random_num < 0.7 {
-        sdl2::mixer::Channel::all().play(&clips.combat2, -1); // -1 means repeat forever  
-    } else {
-        sdl2::mixer::Channel::all().play(&clips.combat3, -1); // -1 means repeat forever
-    }
+    start_music(&clips);
 
 
     //load window before game starts with starting texture
     let texture = {
-        match python_textures.get(&fighter.char_state.state) {
+        match fighter1_textures.get(&sim.fighter1.char_state.state) {
             Some(text) => text,
             _=> panic!("No texture found for the state! Oh nos."),
         }
     };
 
     let texture2 = {
-        match java_textures.get(&fighter2.char_state.state) {
+        match fighter2_textures.get(&sim.fighter2.char_state.state) {
             Some(text) => text,
             _=> panic!("No texture found for the state! Oh nos."),
         }
     };
 
-    game_window.render(&background, &texture, &fighter, &texture2, &fighter2, 
-            &hazard, &hazard_texture, None, &healthbar_left, &healthbar_right,
-            &healthbar_fill_left, &healthbar_fill_right)?;
-
-
-    let collisions = BVHierarchy::new(CollisionObject::new_from(CollisionObjectType::Platform, platform.clone(),
-        Rc::new(RefCell::new(Particle::new(
-            PhysVec::new(((CAM_W-platform.width())/2) as f32, 560f32), 0.5, 2000000000.0, 0, 0)))));
-
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_l, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_L.0 as f32, WALL_L.1 as f32), 0.5, 20000000000.0, 0, 0)))));
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_r, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_R.0 as f32, WALL_R.1 as f32), 0.5, 20000000000.0, 0, 0)))));
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Platform, arch, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(ARCH.0 as f32, ARCH.1 as f32), 0.5, 20000000000.0, 0, 0)))));
-
+    game_window.render(&assets.background, &texture, &sim.fighter1, &texture2, &sim.fighter2, 
+            &sim.hazard, &assets.hazard, None, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, None)?;
 
 
 //################################################-GAME-LOOP###############################################
@@ -209,78 +117,47 @@ pub fn run_game() -> Result<(), String>{
         let player_input = input::inputHandler::convert_input(&player_input);
 
     //##############################################-PROCESS-EVENTS-#######################################
-        //process player movement
-        input::inputHandler::keyboard_input(&player_input, &mut fighter);
-
-        //select frame to be rendered
-        fighter.char_state.advance_frame();
-        fighter2.char_state.advance_frame();
-
-        //move character based on current frame
-        input::movement::move_char(&mut fighter);
-        input::movement::move_char(&mut fighter2);
-
-        fighter.char_state.update_bounding_boxes(&collisions);
-        fighter2.char_state.update_bounding_boxes(&collisions);
-        hazard.update_bounding_box(&collisions);
-        // println!("\nCollisions head BEFORE: \n{:#?}\n", collisions.head);
-        // println!("\n\nupdating...");
-		// println!("\nFighter 1\n {:?}\n", fighter.char_state.get_node());
-		// println!("\nFighter 2\n {:?}\n", fighter2.char_state.get_node());
-		// println!("\nHazard\n {:?}\n", hazard.hitbox);
-        let (hazard_reset, hit_audio) = collisions.resolve_collisions();
-
-        // println!("\nCollisions head AFTER: \n{:#?}\n", collisions.head);
-        fighter.char_state.particle.borrow_mut().integrate(FRAME_RATE as f32);
-        fighter2.char_state.particle.borrow_mut().integrate(FRAME_RATE as f32);
-        hazard.particle.borrow_mut().integrate(FRAME_RATE as f32);
-
-        //move hazard
-        hazard.update_position();
-        if hazard_reset {
-           hazard.reset();
-       }
+        // the second fighter stands still for now
+        let idle = HashSet::new();
+        if let Some(replay) = recording.as_mut() { replay.record(&player_input, &idle); }
+        let events = sim.step(&player_input, &idle);
     //##################################################-RENDER-###########################################
         // get the proper texture within the game
         let texture = {
-            match python_textures.get(&fighter.char_state.state) {
+            match fighter1_textures.get(&sim.fighter1.char_state.state) {
                 Some(text) => text,
                 _=> panic!("No texture found for the state! Oh nos."),
             }
         };
         let texture2 = {
-            match java_textures.get(&fighter2.char_state.state) {
+            match fighter2_textures.get(&sim.fighter2.char_state.state) {
                 Some(text) => text,
                 _=> panic!("No texture found for the state! Oh nos."),
             }
         };
 
         end_message = {
-            if fighter.char_state.health() <= 0 || fighter2.char_state.health() <= 0 {
+            if events.knockout.is_some() {
                 sdl2::mixer::Channel::all().halt();
                 sdl2::mixer::Channel::all().play(&clips.ko, 1);
             }
 
             // check if game should continue
-            if fighter.char_state.health() <= 0 {
-                Some(&lose)
-            }
-            else if fighter2.char_state.health() <= 0 {
-                Some(&win)
-            }
-            else {
-                None
+            match sim.knocked_out() {
+                Some(1) => Some(&assets.lose),
+                Some(_) => Some(&assets.win),
+                None => None,
             }
         };
 
         // hit audio
-        if hit_audio {
+        if events.hit {
           sdl2::mixer::Channel::all().play(&clips.hit, 1);
         }
         // render canvas
-        game_window.render(&background, &texture, &fighter, &texture2, &fighter2, 
-            &hazard, &hazard_texture, end_message, &healthbar_left, &healthbar_right,
-            &healthbar_fill_left, &healthbar_fill_right)?;
+        game_window.render(&assets.background, &texture, &sim.fighter1, &texture2, &sim.fighter2, 
+            &sim.hazard, &assets.hazard, end_message, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, None)?;
         
         if end_message.is_some() {
             break 'gameloop;
@@ -290,6 +167,10 @@ pub fn run_game() -> Result<(), String>{
         thread::sleep(frame_time - loop_time.elapsed().clamp(Duration::new(0, 0), frame_time));
     }
 
+    if let (Some(replay), Some(path)) = (recording, record) {
+        save_replay(&replay, path)?;
+    }
+
     'endloop: loop {
     //################################################-GET-INPUT-##########################################
         //check if play quits
@@ -301,131 +182,393 @@ pub fn run_game() -> Result<(), String>{
             }
         }
         // render canvas
-        game_window.render(&background, &texture, &fighter, &texture2, &fighter2, 
-            &hazard, &hazard_texture, end_message, &healthbar_left, &healthbar_right,
-            &healthbar_fill_left, &healthbar_fill_right)?;
+        game_window.render(&assets.background, &texture, &sim.fighter1, &texture2, &sim.fighter2, 
+            &sim.hazard, &assets.hazard, end_message, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, None)?;
     }
 
     Ok(())
 }
 
-pub fn run_server() -> Result<(), String>{
+pub fn run_client(net_config: &networking::config::NetConfig) -> Result<(), String>{
     let frame_time = Duration::from_secs_f64(FRAME_RATE);
 
-    let socket = networking::config::server_setup();
+    let (socket, player_number, token, mut channel) = networking::config::client_setup(net_config)?;
     socket.set_read_timeout(None).expect("set_read_timeout call failed");
 
-    let mut client_addresses = HashMap::new();
-    let mut player_count: u8 = 1;
 
-    'connecting: loop{
-        player_count = networking::config::client_connect(&socket, &mut client_addresses, player_count);
-        if player_count == 3 {
-            println!("Two players found!");
-            break 'connecting;
+    let mut game_window = {
+        match view::core::SDLCore::init(TITLE, false, CAM_W, CAM_H){
+            Ok(t) => t,
+            Err(e) => panic!("{}", e),
         }
-    }
+    };
 
-    // Creating initial character state
-    let fighter1 = characters::characterAbstract::CharacterState::new();
-    let fighter2 = characters::characterAbstract::CharacterState::new();
+    // the fighters, hazard and stage, set up again with the server's characters and seed when the match starts
+    let sim_config = simulation::SimConfig::default();
+    let mut sim = simulation::Simulation::new(sim_config)?;
+    // every frame once it is final, if we were asked to record
+    let mut recording = net_config.record.as_ref().map(|_| replay::Replay::new(sim_config));
 
-    let mut fighter1 = characters::characterAbstract::Fighter::new(fighter1);
-    let mut fighter2 = characters::characterAbstract::Fighter::new(fighter2);
-    //this is just to make fighter2 spawn a little to the right of fighter
-    fighter2.char_state.particle.borrow_mut().position.replace(&PhysVec::new(300.0, 0.0));
-    fighter2.name = characters::characterAbstract::Characters::Java;
+    let texture_creator = game_window.wincan.texture_creator();
 
-    let mut hazard = physics::hazard::Hazard::new();
 
-    let platform = Rect::new(100, 560, CAM_W-200, 30);
-    let wall_l = Rect::new(WALL_L.0, WALL_L.1, WALL_SIZE.0, WALL_SIZE.1);
-    let wall_r = Rect::new(WALL_R.0, WALL_R.1, WALL_SIZE.0, WALL_SIZE.1);
-    let arch = Rect::new(ARCH.0, ARCH.1, ARCH_SIZE.0, ARCH_SIZE.1);
+    //////////////////////////
+    // FUNCTIONING
+    // each fighter's sprite sheets, by State, loaded again if the server picks other characters
+    let mut fighter1_textures = load_fighter_textures(&texture_creator, sim.fighter1.character)?;
+    let mut fighter2_textures = load_fighter_textures(&texture_creator, sim.fighter2.character)?;
+
+    let assets = load_assets(&texture_creator)?;
+    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let font = ttf_context.load_font(view::core::FONT_PATH, view::core::FONT_SIZE)?;
+
+    ///////////////////////
+    // NOT YET FUNCTIONING
+    // Self::load_textures(&texture_creator, &mut fighter);
+    ////////
 
+    // music 
+    let clips = audio::handler::Clips::new();
+    start_music(&clips);
 
-    let collisions = BVHierarchy::new(CollisionObject::new_from(CollisionObjectType::Platform, platform.clone(),
-        Rc::new(RefCell::new(Particle::new(
-            PhysVec::new(((CAM_W-platform.width())/2) as f32, 560f32), 0.5, 2000000000.0, 0, 0)))));
 
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_l, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_L.0 as f32, WALL_L.1 as f32), 0.5, 20000000000.0, 0, 0)))));
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_r, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_R.0 as f32, WALL_R.1 as f32), 0.5, 20000000000.0, 0, 0)))));
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Platform, arch, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(ARCH.0 as f32, ARCH.1 as f32), 0.5, 20000000000.0, 0, 0)))));
+    //load window before game starts with starting texture
+    let texture = {
+        match fighter1_textures.get(&sim.fighter1.char_state.state) {
+            Some(text) => text,
+            _=> panic!("No texture found for the state! Oh nos."),
+        }
+    };
 
+    let texture2 = {
+        match fighter2_textures.get(&sim.fighter2.char_state.state) {
+            Some(text) => text,
+            _=> panic!("No texture found for the state! Oh nos."),
+        }
+    };
 
-    for address in client_addresses.keys(){
-        socket.send_to(&[0], address).expect("message not sent");
-    }
+    game_window.render(&assets.background, &texture, &sim.fighter1, &texture2, &sim.fighter2, 
+            &sim.hazard, &assets.hazard, None, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, None)?;
+
+
+    let mut session = networking::rollback::RollbackSession::new(player_number);
+    // every input packet repeats the last few frames in case earlier packets were lost
+    let mut recent_inputs = networking::transmit::RecentInputs::new();
+    // set once the opponent (or the server) is gone, the match is frozen from then on
+    let mut opponent_left: Option<networking::protocol::DisconnectReason> = None;
+    // the opponent dropped, the server holds the match until they rejoin
+    let mut paused = false;
+    // we lost the server at this time and keep asking to rejoin until the grace window runs out
+    let mut reconnecting: Option<Instant> = None;
+    let mut last_rejoin: Option<Instant> = None;
+    // bumped by every resync, inputs from older epochs are stale
+    let mut epoch: u32 = 0;
+    // in the lobby until the server says both players are in
+    let mut waiting = true;
+    // chat is open while nobody is fighting, Enter starts typing and sends, Escape cancels
+    let mut chat_log = networking::chat::ChatLog::new();
+    let mut chat_limit = networking::chat::RateLimiter::new();
+    let mut typing: Option<String> = None;
+    // checksums we sent, kept so a Desync from the server can be logged field by field
+    let mut sent_digests: Vec<networking::checksum::StateDigest> = Vec::new();
+    // round trip to the server, and how far ahead of the opponent we are running
+    let mut pinger = networking::timing::Pinger::new();
+    let mut time_sync = networking::timing::TimeSync::new();
+    // F3 shows ping, loss, bandwidth and rollbacks over the match
+    let mut show_stats = false;
+    let mut stats_meter = networking::stats::StatsMeter::new(Instant::now());
 
+    println!("Waiting for other player...");
     socket.set_nonblocking(true).unwrap();
+    // count what goes through it for the stats
+    let socket = networking::stats::Metered::new(socket);
 
   //################################################-GAME-LOOP###############################################
     'gameloop: loop{
-        let readout_time = Instant::now();
+        let loop_time = Instant::now();
     //################################################-GET-INPUT-##########################################
-        let mut input_1: HashSet<u8> = HashSet::new();
-        let mut input_2: HashSet<u8> = HashSet::new();
-        let mut message_1 = false;
-        let mut message_2 = false;        
-        
-        'peeking: loop{
-            if networking::transmit::ready_to_read(&socket){break;}
+        let round_over = sim.knocked_out().is_some();
+        let chat_open = opponent_left.is_none() && (waiting || paused || reconnecting.is_some() || round_over);
+        if !chat_open && typing.is_some() {
+            typing = None;
+            game_window.text_input.stop();
         }
 
-        let receive_time = Instant::now();
-        'receiving: loop{
-            networking::transmit::receive_input(&socket, &client_addresses, &mut input_1, 
-                &mut input_2, &mut message_1, &mut message_2, &readout_time);
-        
-            if receive_time.elapsed().as_millis() >= Duration::from_secs_f64(FRAME_RATE*2.0).as_millis() 
-                || message_1 && message_2 { break; }
+        //ceck if play quits
+        for event in game_window.event_pump.poll_iter() {
+            match event {
+                Event::KeyDown{keycode: Some(Keycode::Escape), ..} if typing.is_some() => {
+                    typing = None;
+                    game_window.text_input.stop();
+                },
+                Event::KeyDown{keycode: Some(Keycode::Return), ..} if chat_open => {
+                    match typing.take() {
+                        Some(text) => {
+                            game_window.text_input.stop();
+                            if let Some(text) = networking::chat::clean(&text) {
+                                if chat_limit.allow(Instant::now()) {
+                                    networking::transmit::send_chat(&socket, &mut channel, &text);
+                                } else {
+                                    chat_log.push(networking::chat::ChatLine::notice("slow down"), Instant::now());
+                                }
+                            }
+                        },
+                        None => {
+                            typing = Some(String::new());
+                            game_window.text_input.start();
+                        },
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::F3), repeat: false, ..} => show_stats = !show_stats,
+                Event::KeyDown{keycode: Some(Keycode::Backspace), ..} if typing.is_some() => {
+                    if let Some(text) = typing.as_mut() { text.pop(); }
+                },
+                Event::TextInput{text: typed, ..} => {
+                    if let Some(text) = typing.as_mut() {
+                        if text.len() + typed.len() <= networking::chat::MAX_CHAT_LENGTH { text.push_str(&typed); }
+                    }
+                },
+                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
+                    if opponent_left.is_none() {
+                        networking::transmit::send_quit(&socket, &mut channel);
+                    }
+                    break 'gameloop
+                },
+                //_ => { input::inputHandler::keyboard_input(&event, &mut fighter); }
+                _=> {},
+            }
+        }
+
+
+        //gather player input, keys typed into chat don't move the fighter
+        let player_input: HashSet<Keycode> = if typing.is_some() { HashSet::new() } else {
+            game_window.event_pump
+                .keyboard_state()
+                .pressed_scancodes()
+                .filter_map(Keycode::from_scancode)
+                .collect()
+        };
+
+        let player_input = input::inputHandler::convert_input(&player_input);
+
+        // only commit an input for a frame we are actually going to simulate
+        let mut advancing = opponent_left.is_none() && !waiting && !paused && reconnecting.is_none() && session.can_advance(sim.frame());
+        if advancing {
+            // played input_delay frames from now, the newest packet carries every frame that got an input
+            let mut newest = None;
+            for (frame, keys) in session.add_delayed_input(sim.frame(), player_input) {
+                newest = Some(recent_inputs.next(frame, epoch, keys));
+            }
+            if let Some(mut player_input) = newest {
+                player_input.advantage = time_sync.local_advantage();
+                networking::transmit::send_input(&socket, &mut channel, &player_input);
+            }
+        }
+        else if opponent_left.is_none() && !waiting && !paused && reconnecting.is_none() {
+            if let Some(mut player_input) = recent_inputs.repeat() {
+                player_input.advantage = time_sync.local_advantage();
+                networking::transmit::send_input(&socket, &mut channel, &player_input);
+            }
+        }
+
+        while opponent_left.is_none() {
+            match networking::transmit::receive_server_message(&socket, &mut channel) {
+                Ok(Some(networking::transmit::MatchMessage::Input(remote))) => {
+                    if remote.epoch == epoch {
+                        for (frame, keys) in remote.frames() {
+                            session.add_remote_input(frame, keys.clone());
+                        }
+                        time_sync.remote(remote.advantage);
+                    }
+                },
+                Ok(Some(networking::transmit::MatchMessage::Start(start))) => {
+                    if waiting {
+                        println!("Starting Game, inputs are delayed {} frames", start.input_delay);
+                        session.set_input_delay(start.input_delay);
+                        start.check()?;
+                        sim = simulation::Simulation::new(start.config)?;
+                        fighter1_textures = load_fighter_textures(&texture_creator, sim.fighter1.character)?;
+                        fighter2_textures = load_fighter_textures(&texture_creator, sim.fighter2.character)?;
+                        if let Some(recording) = recording.as_mut() {
+                            *recording = replay::Replay::new(start.config);
+                        }
+                        waiting = false;
+                    }
+                },
+                Ok(Some(networking::transmit::MatchMessage::Ping(id))) => networking::transmit::send_pong(&socket, &mut channel, id),
+                Ok(Some(networking::transmit::MatchMessage::Pong(id))) => { pinger.pong(id, Instant::now()); },
+                Ok(Some(networking::transmit::MatchMessage::ChatLine(line))) => chat_log.push(line, Instant::now()),
+                Ok(Some(networking::transmit::MatchMessage::Desync{ frame, values })) => {
+                    // the resync that fixes it is on its way
+                    println!("Desync at frame {}:", frame);
+                    match sent_digests.iter().find(|d| d.frame == frame) {
+                        Some(digest) => for line in digest.diff(&values) { println!("  {}", line); },
+                        None => println!("  too old to compare"),
+                    }
+                },
+                Ok(Some(networking::transmit::MatchMessage::Pause)) => {
+                    println!("Opponent dropped, waiting for them to reconnect");
+                    paused = true;
+                },
+                Ok(Some(networking::transmit::MatchMessage::Resync(resync))) => {
+                    if resync.epoch > epoch {
+                        println!("Resynced at frame {}", resync.snapshot.frame);
+                        epoch = resync.epoch;
+                        sim.load(&resync.snapshot);
+                        session.resync(sim.frame());
+                        time_sync = networking::timing::TimeSync::new();
+                        sent_digests.clear();
+                        paused = false;
+                        reconnecting = None;
+                        // the input sent above was for the old timeline
+                        advancing = false;
+                    }
+                },
+                Ok(Some(networking::transmit::MatchMessage::Forfeit(reason))) => {
+                    println!("Match over: {}", reason);
+                    opponent_left = Some(reason);
+                },
+                Ok(Some(_)) => {}, // heartbeats, and messages only the server acts on
+                Ok(None) => break,
+                Err(e @ networking::protocol::ProtocolError::VersionMismatch{..}) => return Err(e.to_string()),
+                Err(e) => {
+                    println!("Match over: {}", e);
+                    opponent_left = Some(networking::protocol::DisconnectReason::TimedOut);
+                },
+            }
         }
 
+        if opponent_left.is_none() {
+            let now = Instant::now();
+            match reconnecting {
+                None if channel.timed_out(now, net_config.timeout) => {
+                    println!("Lost the server, trying to rejoin");
+                    reconnecting = Some(now);
+                    last_rejoin = None;
+                },
+                None => {
+                    if pinger.due(now) {
+                        networking::transmit::send_ping(&socket, &mut channel, pinger.ping(now));
+                    }
+                    else if channel.needs_heartbeat(now) {
+                        networking::transmit::send_heartbeat(&socket, &mut channel);
+                    }
+                },
+                Some(since) if now.duration_since(since) > net_config.grace => {
+                    println!("Match over: lost the server");
+                    opponent_left = Some(networking::protocol::DisconnectReason::TimedOut);
+                },
+                Some(_) => {
+                    // the server opens a fresh channel for every rejoin, so we do too, it keeps our session
+                    if last_rejoin.map_or(true, |t| now.duration_since(t) >= networking::protocol::REJOIN_INTERVAL) {
+                        channel = networking::protocol::Channel::for_session(channel.session());
+                        networking::transmit::send_rejoin(&socket, &mut channel, token);
+                        last_rejoin = Some(now);
+                    }
+                },
+            }
+        }
 
     //##############################################-PROCESS-EVENTS-#######################################
-        //process player movement
-        input::inputHandler::keyboard_input(&input_1, &mut fighter1);
-        input::inputHandler::keyboard_input(&input_2, &mut fighter2);
+        // a late input disagreed with our prediction, rewind and re-simulate up to now
+        let current_frame = sim.frame();
+        if let Some(rollback_frame) = session.take_rollback(current_frame) {
+            stats_meter.rollback(current_frame - rollback_frame);
+            if let Some(snapshot) = session.snapshot(rollback_frame) {
+                sim.load(snapshot);
+            }
+            for frame in rollback_frame .. current_frame {
+                session.save_snapshot(sim.save());
+                let (input_1, input_2) = session.inputs_for(frame);
+                sim.step(&input_1, &input_2);
+            }
+        }
 
+        if advancing {
+            session.save_snapshot(sim.save());
+            let (input_1, input_2) = session.inputs_for(current_frame);
+            sim.step(&input_1, &input_2);
+            time_sync.local(sim.frame(), session.latest_remote(), session.input_delay(), pinger.rtt().unwrap_or_default());
+        }
+
+        // frames that can't be rolled back any more, let the server check we agree
+        for digest in session.take_confirmed() {
+            networking::transmit::send_checksum(&socket, &mut channel, epoch, &digest);
+            sent_digests.push(digest);
+            if sent_digests.len() > networking::checksum::CHECKSUM_HISTORY { sent_digests.remove(0); }
+        }
+        // a resync jumps over frames that were never final here, so the recording stops at the first one
+        for (frame, input_1, input_2) in session.take_final_inputs() {
+            if let Some(replay) = recording.as_mut() {
+                if frame == replay.len() { replay.record(&input_1, &input_2); }
+            }
+        }
+    //##################################################-RENDER-###########################################
+
+        // get the proper texture within the game
+        let texture = {
+            match fighter1_textures.get(&sim.fighter1.char_state.state) {
+                Some(text) => text,
+                _=> panic!("No texture found for the state! Oh nos."),
+            }
+        };
+        let texture2 = {
+            match fighter2_textures.get(&sim.fighter2.char_state.state) {
+                Some(text) => text,
+                _=> panic!("No texture found for the state! Oh nos."),
+            }
+        };
 
-        //select frame to be rendered
-        fighter1.char_state.advance_frame();
-        fighter2.char_state.advance_frame();
+        let end_message = {
+            // check if game should continue
+            if let Some(loser) = sim.knocked_out() {
+                if loser == player_number { Some(&assets.lose) } else { Some(&assets.win) }
+            }
+            else if opponent_left.is_some() {
+                Some(&assets.disconnected)
+            }
+            else {
+                None
+            }
+        };
 
-        //move character based on current frame
-        input::movement::move_char(&mut fighter1);
-        input::movement::move_char(&mut fighter2);
+        let stats = stats_meter.update(Instant::now(), socket.totals(), &pinger, &channel, session.input_delay());
+        let overlay = view::core::Overlay{
+            font: &font,
+            lines: chat_log.visible(Instant::now()),
+            prompt: typing.clone(),
+            stats: if show_stats { Some(stats.lines()) } else { None },
+        };
 
-        fighter1.char_state.update_bounding_boxes(&collisions);
-        fighter2.char_state.update_bounding_boxes(&collisions);
-        hazard.update_bounding_box(&collisions);
-        
+        // render canvas
+        game_window.render(&assets.background, &texture, &sim.fighter1, &texture2, &sim.fighter2, 
+            &sim.hazard, &assets.hazard, end_message, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, Some(&overlay))?;
+    //##################################################-SLEEP-############################################
 
-        let (hazard_reset, hit_audio) = collisions.resolve_collisions();
-        fighter1.char_state.particle.borrow_mut().integrate(FRAME_RATE as f32);
-        fighter2.char_state.particle.borrow_mut().integrate(FRAME_RATE as f32);
+        // a little longer while we are ahead of the opponent, so they catch up without stalling
+        let frame_time = time_sync.frame_time(frame_time);
+        thread::sleep(frame_time - loop_time.elapsed().clamp(Duration::new(0, 0), frame_time));
+    }
 
-        //move hazard
-        hazard.update_position();
-        if hazard_reset {
-           hazard.reset();
-       }
-    //#############################################-SEND-GAMESTATE-#######################################
-        
-        let current_frame = networking::transmit::GameState::new(&fighter1, &fighter2, &hazard);
-        networking::transmit::send_game_state(&socket, &client_addresses, &current_frame);    
+    if let (Some(replay), Some(path)) = (recording, net_config.record.as_ref()) {
+        save_replay(&replay, path)?;
     }
     Ok(())
 }
 
-pub fn run_client() -> Result<(), String>{
+
+pub fn run_spectator(net_config: &networking::config::NetConfig) -> Result<(), String>{
     let frame_time = Duration::from_secs_f64(FRAME_RATE);
 
-    let (socket, player_number) = networking::config::client_setup();
-    socket.set_read_timeout(None).expect("set_read_timeout call failed");
+    let (socket, mut channel) = networking::config::spectator_setup(net_config)?;
+    let watching_room = match net_config.room {
+        networking::protocol::RoomChoice::Room(id) => Some(id),
+        _ => None, // the lobby picks a match for us
+    };
+    socket.set_nonblocking(true).unwrap();
 
 
     let mut game_window = {
@@ -435,144 +578,62 @@ pub fn run_client() -> Result<(), String>{
         }
     };
 
-    // Creating initial character state
-    let fighter1 = characters::characterAbstract::CharacterState::new();
-    let fighter2 = characters::characterAbstract::CharacterState::new();
-
-    let mut fighter1 = characters::characterAbstract::Fighter::new(fighter1);
-    let mut fighter2 = characters::characterAbstract::Fighter::new(fighter2);
-    //this is just to make fighter2 spawn a little to the right of fighter
-    fighter2.char_state.particle.borrow_mut().position.replace(&PhysVec::new(300.0, 0.0));
-    fighter2.name = characters::characterAbstract::Characters::Java;
-
-    let mut hazard = physics::hazard::Hazard::new();
+    // the fighters, hazard and stage, set up again with the match's characters when the first Resync comes
+    let mut sim = simulation::Simulation::new(simulation::SimConfig::default())?;
 
     let texture_creator = game_window.wincan.texture_creator();
 
-    let platform = Rect::new(100, 560, CAM_W-200, 30);
-    let wall_l = Rect::new(WALL_L.0, WALL_L.1, WALL_SIZE.0, WALL_SIZE.1);
-    let wall_r = Rect::new(WALL_R.0, WALL_R.1, WALL_SIZE.0, WALL_SIZE.1);
-    let arch = Rect::new(ARCH.0, ARCH.1, ARCH_SIZE.0, ARCH_SIZE.1);
-
 
     //////////////////////////
     // FUNCTIONING
-    // EDIT: Modularize. Challenge: figuring out how to deal with texture's + hashmap lifetime
-    // create HashMap of all textures
-    let mut python_textures = HashMap::new();
-    let mut java_textures = HashMap::new();
-
-    let idle = texture_creator.load_texture("src/assets/images/characters/python/idle.png")?;
-    let walk = texture_creator.load_texture("src/assets/images/characters/python/walk.png")?;
-    let jump = texture_creator.load_texture("src/assets/images/characters/python/jump.png")?;
-    let fjump = texture_creator.load_texture("src/assets/images/characters/python/fjump.png")?;
-    let lpunch = texture_creator.load_texture("src/assets/images/characters/python/lpunch.png")?;
-    let lkick = texture_creator.load_texture("src/assets/images/characters/python/lkick.png")?;
-    let hkick = texture_creator.load_texture("src/assets/images/characters/python/hkick.png")?;
-    let block = texture_creator.load_texture("src/assets/images/characters/python/block.png")?;
-    let hazard_texture = texture_creator.load_texture("src/assets/images/hazards/stalactite100x100.png")?;
-    let background = texture_creator.load_texture("src/assets/images/background/small_background.png")?;
-    let healthbar_left = texture_creator.load_texture("src/assets/images/healthbar/healthbar_left.png")?;
-    let healthbar_right = texture_creator.load_texture("src/assets/images/healthbar/healthbar_right.png")?;
-    let healthbar_fill_left = texture_creator.load_texture("src/assets/images/healthbar/healthbar_fill_left.png")?;
-    let healthbar_fill_right = texture_creator.load_texture("src/assets/images/healthbar/healthbar_fill_right.png")?;
-    let win = texture_creator.load_texture("src/assets/images/end/win.png")?;
-    let lose = texture_creator.load_texture("src/assets/images/end/lose.png")?;
-
-    let java_idle = texture_creator.load_texture("src/assets/images/characters/java/idle.png")?;
-    let java_walk = texture_creator.load_texture("src/assets/images/characters/java/walk.png")?;
-    let java_jump = texture_creator.load_texture("src/assets/images/characters/java/jump.png")?;
-    let java_fjump = texture_creator.load_texture("src/assets/images/characters/java/fjump.png")?;
-    let java_lpunch = texture_creator.load_texture("src/assets/images/characters/java/lpunch.png")?;
-    let java_lkick = texture_creator.load_texture("src/assets/images/characters/java/lkick.png")?;
-    let java_hkick = texture_creator.load_texture("src/assets/images/characters/java/hkick.png")?;
-    let java_block = texture_creator.load_texture("src/assets/images/characters/java/block.png")?;
-
-    python_textures.insert(animation::sprites::State::Idle, idle);
-    python_textures.insert(animation::sprites::State::Walk, walk);
-    python_textures.insert(animation::sprites::State::Jump, jump);
-    python_textures.insert(animation::sprites::State::FJump, fjump);
-    python_textures.insert(animation::sprites::State::LPunch, lpunch);
-    python_textures.insert(animation::sprites::State::LKick, lkick);
-    python_textures.insert(animation::sprites::State::HKick, hkick);
-    python_textures.insert(animation::sprites::State::Block, block);
-
-    java_textures.insert(animation::sprites::State::Idle, java_idle);
-    java_textures.insert(animation::sprites::State::Walk, java_walk);
-    java_textures.insert(animation::sprites::State::Jump, java_jump);
-    java_textures.insert(animation::sprites::State::FJump, java_fjump);
-    java_textures.insert(animation::sprites::State::LPunch, java_lpunch);
-    java_textures.insert(animation::sprites::State::LKick, java_lkick);
-    java_textures.insert(animation::sprites::State::HKick, java_hkick);
-    java_textures.insert(animation::sprites::State::Block, java_block);
+    // each fighter's sprite sheets, by State, loaded again if the match has other characters
+    let mut fighter1_textures = load_fighter_textures(&texture_creator, sim.fighter1.character)?;
+    let mut fighter2_textures = load_fighter_textures(&texture_creator, sim.fighter2.character)?;
+
+    let assets = load_assets(&texture_creator)?;
+    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let font = ttf_context.load_font(view::core::FONT_PATH, view::core::FONT_SIZE)?;
 
     ///////////////////////
     // NOT YET FUNCTIONING
     // Self::load_textures(&texture_creator, &mut fighter);
     ////////
 
-    // get random # (for music)
-    let mut rng = rand::thread_rng();
-    let random_num: f64 = rng.gen(); // generates a float between 0 and 1
-    println!("{}", random_num);
-
     // music 
     let clips = audio::handler::Clips::new();
-
-        // randomize between the 3x combat audio tracks
-    if random_num < 0.4 { 
-        sdl2::mixer::Channel::all().play(&clips.combat1, -1); // -1 means repeat forever
-    } else if random_num < 0.7 {
-        sdl2::mixer::Channel::all().play(&clips.combat2, -1); // -1 means repeat forever  
-    } else {
-        sdl2::mixer::Channel::all().play(&clips.combat3, -1); // -1 means repeat forever
-    }
+    start_music(&clips);
 
 
     //load window before game starts with starting texture
     let texture = {
-        match python_textures.get(&fighter1.char_state.state) {
+        match fighter1_textures.get(&sim.fighter1.char_state.state) {
             Some(text) => text,
             _=> panic!("No texture found for the state! Oh nos."),
         }
     };
 
     let texture2 = {
-        match java_textures.get(&fighter2.char_state.state) {
+        match fighter2_textures.get(&sim.fighter2.char_state.state) {
             Some(text) => text,
             _=> panic!("No texture found for the state! Oh nos."),
         }
     };
 
-    game_window.render(&background, &texture, &fighter1, &texture2, &fighter2, 
-            &hazard, &hazard_texture, None, &healthbar_left, &healthbar_right,
-            &healthbar_fill_left, &healthbar_fill_right)?;
-
+    game_window.render(&assets.background, &texture, &sim.fighter1, &texture2, &sim.fighter2, 
+            &sim.hazard, &assets.hazard, None, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, None)?;
 
-    let collisions = BVHierarchy::new(CollisionObject::new_from(CollisionObjectType::Platform, platform.clone(),
-        Rc::new(RefCell::new(Particle::new(
-            PhysVec::new(((CAM_W-platform.width())/2) as f32, 560f32), 0.5, 2000000000.0, 0, 0)))));
 
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_l, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_L.0 as f32, WALL_L.1 as f32), 0.5, 20000000000.0, 0, 0)))));
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_r, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_R.0 as f32, WALL_R.1 as f32), 0.5, 20000000000.0, 0, 0)))));
-    collisions.insert(CollisionObject::new_from(CollisionObjectType::Platform, arch, 
-        Rc::new(RefCell::new(Particle::new(PhysVec::new(ARCH.0 as f32, ARCH.1 as f32), 0.5, 20000000000.0, 0, 0)))));
+    // the server's copy of the match is shown as is, nothing is simulated here
+    let mut watching = false;
+    let mut last_request: Option<Instant> = None;
+    let mut match_over = false;
+    // spectators read the room's chat but can't write to it
+    let mut chat_log = networking::chat::ChatLog::new();
+    // states rebuilt from the server's deltas
+    let mut deltas = networking::delta::DeltaReceiver::new();
 
-
-
-    let mut input_buffer: VecDeque<networking::transmit::GameState> = VecDeque::new();
-
-    for i in 0 .. 6{
-        input_buffer.push_back(networking::transmit::GameState::new(&fighter1, &fighter2, &hazard));
-    }
-
-    println!("Waiting for other player...");
-    let mut buffer = [0u8; 800];
-    let (number_of_bytes) = socket.recv(&mut buffer).expect("Didn't receive data");
-    println!("Starting Game");
-    socket.set_nonblocking(true).unwrap();
+    println!("Waiting for the match...");
 
   //################################################-GAME-LOOP###############################################
     'gameloop: loop{
@@ -581,100 +642,219 @@ pub fn run_client() -> Result<(), String>{
         //ceck if play quits
         for event in game_window.event_pump.poll_iter() {
             match event {
-                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => break 'gameloop,
-                //_ => { input::inputHandler::keyboard_input(&event, &mut fighter); }
+                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
+                    if watching && !match_over {
+                        networking::transmit::send_quit(&socket, &mut channel);
+                    }
+                    break 'gameloop
+                },
                 _=> {},
             }
         }
 
+        while !match_over {
+            match networking::transmit::receive_server_message(&socket, &mut channel) {
+                Ok(Some(networking::transmit::MatchMessage::Resync(resync))) => {
+                    println!("Watching from frame {}", resync.snapshot.frame);
+                    resync.start.check()?;
+                    if (sim.fighter1.character, sim.fighter2.character) != (resync.start.config.p1, resync.start.config.p2) {
+                        sim = simulation::Simulation::new(resync.start.config)?;
+                        fighter1_textures = load_fighter_textures(&texture_creator, sim.fighter1.character)?;
+                        fighter2_textures = load_fighter_textures(&texture_creator, sim.fighter2.character)?;
+                    }
+                    sim.load(&resync.snapshot);
+                    watching = true;
+                },
+                Ok(Some(networking::transmit::MatchMessage::GameState{ frame, packet })) => {
+                    // still decoded before we watch, later states may be relative to it
+                    if let Some(state) = deltas.receive(frame, &packet) {
+                        if watching {
+                            state.apply(&mut sim.fighter1, &mut sim.fighter2, &mut sim.hazard);
+                        }
+                    }
+                },
+                Ok(Some(networking::transmit::MatchMessage::ChatLine(line))) => chat_log.push(line, Instant::now()),
+                Ok(Some(networking::transmit::MatchMessage::Forfeit(reason))) => {
+                    println!("Match over: {}", reason);
+                    match_over = true;
+                },
+                Ok(Some(_)) => {},
+                Ok(None) => break,
+                Err(e @ networking::protocol::ProtocolError::VersionMismatch{..}) => return Err(e.to_string()),
+                Err(e) => {
+                    println!("Match over: {}", e);
+                    match_over = true;
+                },
+            }
+        }
 
-        //gather player input
-        let player_input: HashSet<Keycode> = game_window.event_pump
-            .keyboard_state()
-            .pressed_scancodes()
-            .filter_map(Keycode::from_scancode)
-            .collect();
-
-        let player_input = input::inputHandler::convert_input(&player_input);
-
-    //##############################################-PROCESS-EVENTS-#######################################
-        let player_input = networking::transmit::InputStruct::new(player_input);
-        let readout_time = Instant::now();
-
-
-        networking::transmit::send_input(&socket, &player_input);
-
-        let state = input_buffer.pop_front().unwrap();
-        
-        fighter1.char_state.set_state(state.p1_state);
-        fighter1.char_state.current_frame = state.p1_frame;
-        fighter1.char_state.particle.replace(state.p1_position);
-
-        fighter2.char_state.set_state(state.p2_state);
-        fighter2.char_state.current_frame = state.p2_frame;
-        fighter2.char_state.particle.replace(state.p2_position);
+        if !match_over {
+            let now = Instant::now();
+            if !watching {
+                // keep asking until the server has a match to show us
+                if last_request.map_or(true, |t| now.duration_since(t) >= networking::protocol::REJOIN_INTERVAL) {
+                    networking::transmit::send_spectate(&socket, &mut channel, watching_room);
+                    last_request = Some(now);
+                }
+            } else if channel.timed_out(now, net_config.timeout) {
+                println!("Match over: lost the server");
+                match_over = true;
+            } else if channel.needs_heartbeat(now) {
+                networking::transmit::send_heartbeat(&socket, &mut channel);
+            }
+        }
 
-        hazard.from_packet(&state.hazard);    
+        sim.update_bounding_boxes();
     //##################################################-RENDER-###########################################
 
         // get the proper texture within the game
         let texture = {
-            match python_textures.get(&fighter1.char_state.state) {
+            match fighter1_textures.get(&sim.fighter1.char_state.state) {
                 Some(text) => text,
                 _=> panic!("No texture found for the state! Oh nos."),
             }
         };
         let texture2 = {
-            match java_textures.get(&fighter2.char_state.state) {
+            match fighter2_textures.get(&sim.fighter2.char_state.state) {
                 Some(text) => text,
                 _=> panic!("No texture found for the state! Oh nos."),
             }
         };
 
         let end_message = {
-            // check if game should continue
-            if fighter1.char_state.health() <= 0 && player_number == 1 {
-                Some(&lose)
-            }
-            else if fighter2.char_state.health() <= 0 && player_number == 1 {
-                Some(&win)
+            if let Some(loser) = sim.knocked_out() {
+                if loser == 1 { Some(&assets.lose) } else { Some(&assets.win) } // shown from player 1's side
             }
-            else if fighter1.char_state.health() <= 0 && player_number == 2 {
-                Some(&win)
-            }
-            else if fighter2.char_state.health() <= 0 && player_number == 2 {
-                Some(&lose)
+            else if match_over {
+                Some(&assets.disconnected)
             }
             else {
                 None
             }
         };
 
+        let overlay = view::core::Overlay{ font: &font, lines: chat_log.visible(Instant::now()), prompt: None, stats: None };
+
         // render canvas
-        game_window.render(&background, &texture, &fighter1, &texture2, &fighter2, 
-            &hazard, &hazard_texture, end_message, &healthbar_left, &healthbar_right,
-            &healthbar_fill_left, &healthbar_fill_right)?;
+        game_window.render(&assets.background, &texture, &sim.fighter1, &texture2, &sim.fighter2, 
+            &sim.hazard, &assets.hazard, end_message, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, Some(&overlay))?;
     //##################################################-SLEEP-############################################
-            let mut next_state = networking::transmit::GameState::new(&fighter1, &fighter2, &hazard);
-            let receive_time = Instant::now();
-           
-            'reading: loop{
-                if networking::transmit::receive_game_state(&socket, &mut next_state, &readout_time) { 
-                    break;
-                }else if(receive_time.elapsed().as_millis() > Duration::from_secs_f64(FRAME_RATE*2.0).as_millis()){
-                    break;
-                }
+
+        thread::sleep(frame_time - loop_time.elapsed().clamp(Duration::new(0, 0), frame_time));
+    }
+    Ok(())
+}
+
+
+// play a recorded match back: Space pauses, Right steps a frame while paused,
+// Up and Down change the speed, R starts over and Escape quits
+pub fn run_replay(path: &str) -> Result<(), String>{
+    let frame_time = Duration::from_secs_f64(FRAME_RATE);
+
+    let mut playback = replay::Playback::new(replay::Replay::load(path)?)?;
+    println!("Playing {} frames from {}", playback.replay.len(), path);
+
+    let mut game_window = {
+        match view::core::SDLCore::init(TITLE, false, CAM_W, CAM_H){
+            Ok(t) => t,
+            Err(e) => panic!("{}", e),
+        }
+    };
+
+    let texture_creator = game_window.wincan.texture_creator();
+
+
+    //////////////////////////
+    // FUNCTIONING
+    // each fighter's sprite sheets, by State
+    let fighter1_textures = load_fighter_textures(&texture_creator, playback.sim.fighter1.character)?;
+    let fighter2_textures = load_fighter_textures(&texture_creator, playback.sim.fighter2.character)?;
+
+    let assets = load_assets(&texture_creator)?;
+    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let font = ttf_context.load_font(view::core::FONT_PATH, view::core::FONT_SIZE)?;
+
+    let clips = audio::handler::Clips::new();
+
+  //################################################-GAME-LOOP###############################################
+    'gameloop: loop{
+        let loop_time = Instant::now();
+    //################################################-GET-INPUT-##########################################
+        let mut events = Vec::new();
+        for event in game_window.event_pump.poll_iter() {
+            match event {
+                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => break 'gameloop,
+                Event::KeyDown{keycode: Some(Keycode::Space), repeat: false, ..} => playback.toggle_pause(),
+                Event::KeyDown{keycode: Some(Keycode::Right), ..} if playback.paused => events.extend(playback.step()),
+                Event::KeyDown{keycode: Some(Keycode::Up), repeat: false, ..} => playback.faster(),
+                Event::KeyDown{keycode: Some(Keycode::Down), repeat: false, ..} => playback.slower(),
+                Event::KeyDown{keycode: Some(Keycode::R), repeat: false, ..} => playback.restart()?,
+                _=> {},
             }
+        }
 
-            input_buffer.push_back(next_state);
+    //##############################################-PROCESS-EVENTS-#######################################
+        events.extend(playback.tick());
+        for frame in events.iter() {
+            if frame.hit {
+                let _ = sdl2::mixer::Channel::all().play(&clips.hit, 1);
+            }
+            if frame.knockout.is_some() {
+                let _ = sdl2::mixer::Channel::all().play(&clips.ko, 1);
+            }
+        }
+    //##################################################-RENDER-###########################################
 
-        
+        // get the proper texture within the game
+        let texture = {
+            match fighter1_textures.get(&playback.sim.fighter1.char_state.state) {
+                Some(text) => text,
+                _=> panic!("No texture found for the state! Oh nos."),
+            }
+        };
+        let texture2 = {
+            match fighter2_textures.get(&playback.sim.fighter2.char_state.state) {
+                Some(text) => text,
+                _=> panic!("No texture found for the state! Oh nos."),
+            }
+        };
+
+        let end_message = match playback.sim.knocked_out() {
+            Some(1) => Some(&assets.lose), // shown from player 1's side
+            Some(_) => Some(&assets.win),
+            None => None,
+        };
+
+        let status = if playback.is_finished() { format!("{}  end", playback.status()) } else { playback.status() };
+        let overlay = view::core::Overlay{ font: &font, lines: vec![status], prompt: None, stats: None };
+
+        // render canvas
+        game_window.render(&assets.background, &texture, &playback.sim.fighter1, &texture2, &playback.sim.fighter2, 
+            &playback.sim.hazard, &assets.hazard, end_message, &assets.healthbar_left, &assets.healthbar_right,
+            &assets.healthbar_fill_left, &assets.healthbar_fill_right, Some(&overlay))?;
+    //##################################################-SLEEP-############################################
 
         thread::sleep(frame_time - loop_time.elapsed().clamp(Duration::new(0, 0), frame_time));
     }
     Ok(())
 }
 
+// every State's sprite sheet for a character, as its character file lists them
+fn load_fighter_textures<'a>(texture_creator: &'a TextureCreator<WindowContext>, character: characters::registry::CharacterId)
+    -> Result<HashMap<animation::sprites::State, Texture<'a>>, String>{
+    let mut textures = HashMap::new();
+    for (state, animation) in characters::registry::character(character)?.animations.iter() {
+        textures.insert(*state, texture_creator.load_texture(&animation.texture)?);
+    }
+    Ok(textures)
+}
+
+fn save_replay(replay: &replay::Replay, path: &str) -> Result<(), String>{
+    replay.save(path)?;
+    println!("Saved {} frames of replay to {}", replay.len(), path);
+    Ok(())
+}
 
 
  // run credits
@@ -736,17 +916,133 @@ pub fn run_client() -> Result<(), String>{
     Ok(())
 }
 
+// the stage, stalactite, health bars and end screens every view of a match draws
+struct Assets<'a> {
+    hazard: Texture<'a>,
+    background: Texture<'a>,
+    healthbar_left: Texture<'a>,
+    healthbar_right: Texture<'a>,
+    healthbar_fill_left: Texture<'a>,
+    healthbar_fill_right: Texture<'a>,
+    win: Texture<'a>,
+    lose: Texture<'a>,
+    disconnected: Texture<'a>,
+}
+
+fn load_assets<'a>(texture_creator: &'a TextureCreator<WindowContext>) -> Result<Assets<'a>, String>{
+    Ok(Assets {
+        hazard: texture_creator.load_texture("src/assets/images/hazards/stalactite100x100.png")?,
+        background: texture_creator.load_texture("src/assets/images/background/small_background.png")?,
+        healthbar_left: texture_creator.load_texture("src/assets/images/healthbar/healthbar_left.png")?,
+        healthbar_right: texture_creator.load_texture("src/assets/images/healthbar/healthbar_right.png")?,
+        healthbar_fill_left: texture_creator.load_texture("src/assets/images/healthbar/healthbar_fill_left.png")?,
+        healthbar_fill_right: texture_creator.load_texture("src/assets/images/healthbar/healthbar_fill_right.png")?,
+        win: texture_creator.load_texture("src/assets/images/end/win.png")?,
+        lose: texture_creator.load_texture("src/assets/images/end/lose.png")?,
+        disconnected: texture_creator.load_texture("src/assets/images/end/disconnected.png")?,
+    })
+}
+
+// loop one of the 3x combat audio tracks, picked at random
+// cosmetic only so it stays off the match rng
+fn start_music(clips: &audio::handler::Clips){
+    let random_num: f64 = rand::thread_rng().gen(); // generates a float between 0 and 1
+    if random_num < 0.4 {
+        let _ = sdl2::mixer::Channel::all().play(&clips.combat1, -1); // -1 means repeat forever
+    } else if random_num < 0.7 {
+        let _ = sdl2::mixer::Channel::all().play(&clips.combat2, -1);
+    } else {
+        let _ = sdl2::mixer::Channel::all().play(&clips.combat3, -1);
+    }
+}
+
+
+// no --server given, list what answers on the LAN and let the player pick
+fn choose_server(net_config: &mut networking::config::NetConfig) -> Result<(), String>{
+    if net_config.server.is_some() { return Ok(()); }
+
+    println!("Looking for servers on the LAN...");
+    let found = networking::config::discover(&net_config.bind, networking::config::DISCOVERY_PORT, networking::config::DISCOVERY_WAIT)?;
+    let servers: Vec<&(std::net::SocketAddr, networking::protocol::ServerInfo)> = found.iter().filter(|(_, info)| info.compatible()).collect();
+    for (address, info) in found.iter().filter(|(_, info)| !info.compatible()) {
+        println!("  skipping {} at {}", info, address);
+    }
+
+    let choice = match servers.len() {
+        0 => return Err("no servers found on the LAN, start one with `server` or give one with --server host:port".to_string()),
+        1 => 0,
+        _ => {
+            for (number, (address, info)) in servers.iter().enumerate() {
+                println!("{:>3}) {}  {}", number + 1, info, address);
+            }
+            loop {
+                print!("Pick a server [1-{}]: ", servers.len());
+                std::io::Write::flush(&mut std::io::stdout()).map_err(|e| e.to_string())?;
+                let mut line = String::new();
+                if std::io::stdin().read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                    return Err("no server picked".to_string());
+                }
+                match line.trim().parse::<usize>() {
+                    Ok(number) if number >= 1 && number <= servers.len() => break number - 1,
+                    _ => println!("`{}` is not on the list", line.trim()),
+                }
+            }
+        },
+    };
+
+    let (address, info) = servers[choice];
+    println!("Joining {} at {}", info.name, address);
+    net_config.server = Some(address.to_string());
+    Ok(())
+}
 
 fn main() -> Result<(), String> {
 
     let args: Vec<String> = env::args().collect();
     if args.len() > 1 && "server".eq(&args[1]){
-        run_server()?;
+        let net_config = networking::config::NetConfig::load(true, &args[2..])?;
+        networking::server::run_server(&net_config)?;
     }else if args.len() > 1 && "client".eq(&args[1]){
-        run_client()?;
+        let mut net_config = networking::config::NetConfig::load(false, &args[2..])?;
+        choose_server(&mut net_config)?;
+        run_client(&net_config)?;
         run_credits()?;
+    }else if args.len() > 1 && "spectate".eq(&args[1]){
+        let mut net_config = networking::config::NetConfig::load(false, &args[2..])?;
+        choose_server(&mut net_config)?;
+        run_spectator(&net_config)?;
+    }else if args.len() > 1 && "rooms".eq(&args[1]){
+        let mut net_config = networking::config::NetConfig::load(false, &args[2..])?;
+        choose_server(&mut net_config)?;
+        let rooms = networking::config::list_rooms(&net_config)?;
+        if rooms.is_empty() {
+            println!("No rooms open on {}, `client` will open one", net_config.server_address()?);
+        }
+        for room in rooms.iter() {
+            println!("{}", room);
+        }
+    }else if args.len() > 1 && "characters".eq(&args[1]){
+        let registry = characters::registry::Registry::load(characters::registry::CHARACTERS_DIR)?;
+        for character in registry.list() {
+            println!("{:>4}  {}", character.id.0, character.name);
+        }
+    }else if args.len() > 1 && "replay".eq(&args[1]){
+        let path = args.get(2).ok_or("replay needs a file, e.g. replay match.scfr".to_string())?;
+        run_replay(path)?;
     }else{
-        run_game()?;
+        // Python against Java unless someone picked
+        let usual = simulation::SimConfig::default();
+        let (mut record, mut p1, mut p2) = (None, usual.p1, usual.p2);
+        let mut flags = args[1..].iter();
+        while let Some(flag) = flags.next() {
+            match flag.as_str() {
+                "--record" => record = Some(flags.next().ok_or("--record needs a file to save the replay to, e.g. --record match.scfr".to_string())?.as_str()),
+                "--p1" => p1 = characters::registry::named(flags.next().ok_or("--p1 needs a name from `characters`, e.g. --p1 java".to_string())?)?,
+                "--p2" => p2 = characters::registry::named(flags.next().ok_or("--p2 needs a name from `characters`, e.g. --p2 python".to_string())?)?,
+                _ => return Err(format!("unknown option `{}`", flag)),
+            }
+        }
+        run_game(record, p1, p2)?;
         run_credits()?;
     }
 
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use serde_derive::{Serialize, Deserialize};
+
+// longest message in bytes, anything past it is cut off
+pub const MAX_CHAT_LENGTH: usize = 80;
+// at most CHAT_BURST messages per CHAT_WINDOW from one player
+pub const CHAT_BURST: usize = 3;
+pub const CHAT_WINDOW: Duration = Duration::from_secs(5);
+// how many lines the overlay shows and for how long
+pub const CHAT_HISTORY: usize = 6;
+pub const CHAT_LINE_LIFETIME: Duration = Duration::from_secs(15);
+
+// who the server says a notice came from
+pub const SERVER_NAME: &str = "Server";
+
+// server -> clients, one message as everyone in the room sees it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatLine {
+    pub from: String,
+    pub text: String,
+}
+
+impl ChatLine {
+    pub fn new(from: &str, text: &str) -> ChatLine {
+        ChatLine { from: from.to_string(), text: text.to_string() }
+    }
+
+    pub fn notice(text: &str) -> ChatLine { ChatLine::new(SERVER_NAME, text) }
+}
+
+// strip what can't be drawn and cut to MAX_CHAT_LENGTH, None if nothing is left to send
+pub fn clean(text: &str) -> Option<String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let text = text.trim();
+    let mut end = text.len().min(MAX_CHAT_LENGTH);
+    while !text.is_char_boundary(end) { end -= 1; }
+    let text = text[..end].trim_end();
+    if text.is_empty() { None } else { Some(text.to_string()) }
+}
+
+// sliding window over the times of recent messages
+#[derive(Default)]
+pub struct RateLimiter {
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter::default()
+    }
+
+    // records the message if it is allowed
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while let Some(oldest) = self.sent.front() {
+            if now.saturating_duration_since(*oldest) < CHAT_WINDOW { break; }
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= CHAT_BURST { return false; }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+// the last few lines a client has seen
+#[derive(Default)]
+pub struct ChatLog {
+    lines: VecDeque<(ChatLine, Instant)>,
+}
+
+impl ChatLog {
+    pub fn new() -> ChatLog {
+        ChatLog::default()
+    }
+
+    pub fn push(&mut self, line: ChatLine, now: Instant) {
+        self.lines.push_back((line, now));
+        while self.lines.len() > CHAT_HISTORY { self.lines.pop_front(); }
+    }
+
+    // oldest first, ready to draw
+    pub fn visible(&self, now: Instant) -> Vec<String> {
+        self.lines.iter()
+            .filter(|(_, at)| now.saturating_duration_since(*at) < CHAT_LINE_LIFETIME)
+            .map(|(line, _)| format!("{}: {}", line.from, line.text))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn testClean() {
+        assert_eq!(clean("  gg\n"), Some("gg".to_string()));
+        assert_eq!(clean(" \t "), None);
+        assert_eq!(clean("a\u{7}b"), Some("ab".to_string()));
+        let long = clean(&"\u{e9}".repeat(MAX_CHAT_LENGTH)).unwrap();
+        assert!(long.len() <= MAX_CHAT_LENGTH);
+        assert!(long.chars().all(|c| c == '\u{e9}'));
+    }
+
+    #[test]
+    pub fn testRateLimit() {
+        let mut limiter = RateLimiter::new();
+        let start = Instant::now();
+        for _ in 0..CHAT_BURST {
+            assert!(limiter.allow(start));
+        }
+        assert!(!limiter.allow(start + Duration::from_secs(1)));
+        assert!(limiter.allow(start + CHAT_WINDOW));
+    }
+
+    #[test]
+    pub fn testLogExpires() {
+        let mut log = ChatLog::new();
+        let start = Instant::now();
+        for i in 0..CHAT_HISTORY + 2 {
+            log.push(ChatLine::new("P1", &i.to_string()), start);
+        }
+        let lines = log.visible(start);
+        assert_eq!(lines.len(), CHAT_HISTORY);
+        assert_eq!(lines[0], "P1: 2");
+        assert!(log.visible(start + CHAT_LINE_LIFETIME).is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt::Debug;
+use bincode::serialize;
+use serde::Serialize;
+
+use crate::characters::characterAbstract::CharacterSnapshot;
+use crate::networking::rollback::FrameSnapshot;
+use crate::physics::hazard::HazardSnapshot;
+use crate::physics::particle::Particle;
+use crate::physics::rect::Rect;
+
+// clients send a checksum for every frame that is a multiple of this, twice a second at 60fps
+pub const CHECKSUM_INTERVAL: u32 = 30;
+// how many recent digests each side keeps to compare against
+pub const CHECKSUM_HISTORY: usize = 8;
+
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// FNV-1a, unlike std's hashers it is the same on every build and platform
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+// how a field's bits turn back into something readable
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Float,  // f32 bits
+    Double, // f64 bits
+    Int,
+    Flag,
+    Tag,    // enum variant index
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub kind: Kind,
+    pub value: u64,
+    pub label: Option<String>, // how a Tag reads on this side
+}
+
+impl Kind {
+    pub fn show(&self, value: u64) -> String {
+        match self {
+            Kind::Float => f32::from_bits(value as u32).to_string(),
+            Kind::Double => f64::from_bits(value).to_string(),
+            Kind::Int => (value as i64).to_string(),
+            Kind::Flag => (value != 0).to_string(),
+            Kind::Tag => format!("variant {}", value),
+        }
+    }
+}
+
+// every field of a frame that has to come out the same on the server and both clients
+// built from a FrameSnapshot so it covers exactly what rollback and resync restore
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateDigest {
+    pub frame: u32,
+    pub fields: Vec<Field>,
+}
+
+impl StateDigest {
+    pub fn of(snapshot: &FrameSnapshot) -> StateDigest {
+        let mut digest = StateDigest { frame: snapshot.frame, fields: Vec::new() };
+        digest.character("p1", &snapshot.p1);
+        digest.character("p2", &snapshot.p2);
+        digest.hazard("hazard", &snapshot.hazard);
+        digest.int("rng.state".to_string(), snapshot.rng.state() as i64);
+        digest
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.fields.iter().fold(FNV_OFFSET, |hash, field| fnv1a(hash, &field.value.to_le_bytes()))
+    }
+
+    pub fn values(&self) -> Vec<u64> {
+        self.fields.iter().map(|field| field.value).collect()
+    }
+
+    // one line per field where someone else's values disagree with ours
+    pub fn diff(&self, theirs: &[u64]) -> Vec<String> {
+        if theirs.len() != self.fields.len() {
+            return vec![format!("{} fields here, {} there", self.fields.len(), theirs.len())];
+        }
+        self.fields.iter().zip(theirs.iter())
+            .filter(|(field, value)| field.value != **value)
+            .map(|(field, value)| {
+                let ours = field.label.clone().unwrap_or(field.kind.show(field.value));
+                format!("{}: ours {}, theirs {}", field.name, ours, field.kind.show(*value))
+            })
+            .collect()
+    }
+
+    fn push(&mut self, name: String, kind: Kind, value: u64, label: Option<String>) {
+        self.fields.push(Field { name, kind, value, label });
+    }
+
+    fn float(&mut self, name: String, value: f32) { self.push(name, Kind::Float, value.to_bits() as u64, None); }
+    fn double(&mut self, name: String, value: f64) { self.push(name, Kind::Double, value.to_bits(), None); }
+    fn int(&mut self, name: String, value: i64) { self.push(name, Kind::Int, value as u64, None); }
+    fn flag(&mut self, name: String, value: bool) { self.push(name, Kind::Flag, value as u64, None); }
+
+    // bincode writes a unit variant as its index
+    fn tag<T: Serialize + Debug>(&mut self, name: String, value: &T) {
+        let index = serialize(value).ok()
+            .filter(|bytes| bytes.len() >= 4)
+            .map_or(u64::MAX, |bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64);
+        self.push(name, Kind::Tag, index, Some(format!("{:?}", value)));
+    }
+
+    fn particle(&mut self, prefix: &str, particle: &Particle) {
+        self.float(format!("{}.position.x", prefix), particle.position.x);
+        self.float(format!("{}.position.y", prefix), particle.position.y);
+        self.float(format!("{}.velocity.x", prefix), particle.velocity.x);
+        self.float(format!("{}.velocity.y", prefix), particle.velocity.y);
+        self.float(format!("{}.acceleration.x", prefix), particle.acceleration.x);
+        self.float(format!("{}.acceleration.y", prefix), particle.acceleration.y);
+        self.float(format!("{}.damping", prefix), particle.damping);
+        self.float(format!("{}.inverse_mass", prefix), particle.inverse_mass);
+        self.float(format!("{}.force_accumulator.x", prefix), particle.force_accumulator.x);
+        self.float(format!("{}.force_accumulator.y", prefix), particle.force_accumulator.y);
+        self.int(format!("{}.health", prefix), particle.health as i64);
+        self.int(format!("{}.jump_count", prefix), particle.jump_count as i64);
+        self.int(format!("{}.damage", prefix), particle.damage as i64);
+        self.int(format!("{}.stun", prefix), particle.stun as i64);
+        self.int(format!("{}.move_landed", prefix), particle.move_landed as i64);
+        self.int(format!("{}.knockdown", prefix), particle.knockdown as i64);
+    }
+
+    fn rect(&mut self, prefix: &str, rect: &Rect) {
+        self.int(format!("{}.x", prefix), rect.x as i64);
+        self.int(format!("{}.y", prefix), rect.y as i64);
+        self.int(format!("{}.w", prefix), rect.w as i64);
+        self.int(format!("{}.h", prefix), rect.h as i64);
+    }
+
+    fn character(&mut self, prefix: &str, character: &CharacterSnapshot) {
+        self.particle(&format!("{}.particle", prefix), &character.particle);
+        self.tag(format!("{}.state", prefix), &character.state);
+        self.int(format!("{}.frames_per_state", prefix), character.frames_per_state as i64);
+        self.int(format!("{}.frame_count", prefix), character.frame_count as i64);
+        self.int(format!("{}.current_frame", prefix), character.current_frame as i64);
+        self.rect(&format!("{}.sprite", prefix), &character.sprite);
+        self.flag(format!("{}.auto_repeat", prefix), character.auto_repeat);
+        self.tag(format!("{}.direction", prefix), &character.direction);
+        self.tag(format!("{}.next_state", prefix), &character.next_state);
+    }
+
+    fn hazard(&mut self, prefix: &str, hazard: &HazardSnapshot) {
+        self.flag(format!("{}.active", prefix), hazard.active);
+        self.tag(format!("{}.name", prefix), &hazard.name);
+        self.flag(format!("{}.falling", prefix), hazard.falling);
+        self.flag(format!("{}.fell", prefix), hazard.fell);
+        self.flag(format!("{}.hit", prefix), hazard.hit);
+        self.double(format!("{}.fall_speed", prefix), hazard.fall_speed);
+        self.double(format!("{}.damage", prefix), hazard.damage);
+        self.int(format!("{}.position.x", prefix), hazard.position.x as i64);
+        self.int(format!("{}.position.y", prefix), hazard.position.y as i64);
+        self.rect(&format!("{}.sprite", prefix), &hazard.sprite);
+        self.particle(&format!("{}.particle", prefix), &hazard.particle);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::characters::characterAbstract::{CharacterState, Fighter};
+    use crate::physics::collisions::build_stage;
+    use crate::physics::hazard::Hazard;
+    use crate::simulation::MatchRng;
+
+    fn digest() -> (StateDigest, Fighter, Fighter, Hazard) {
+        let p1 = Fighter::new(CharacterState::new());
+        let p2 = Fighter::new(CharacterState::new());
+        let hazard = Hazard::new();
+        let snapshot = FrameSnapshot::save(30, &p1, &p2, &hazard, &MatchRng::new(0), &build_stage());
+        (StateDigest::of(&snapshot), p1, p2, hazard)
+    }
+
+    #[test]
+    pub fn testSameStateSameHash() {
+        let (first, _, _, _) = digest();
+        let (second, _, _, _) = digest();
+        assert_eq!(first.hash(), second.hash());
+        assert!(first.diff(&second.values()).is_empty());
+
+        // the server answers a bad checksum with all of its values in one datagram
+        use crate::networking::protocol::{Channel, MessageType, MAX_PACKET_SIZE};
+        assert!(Channel::new().encode(MessageType::Desync, 30, &first.values()).len() <= MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    pub fn testDiffNamesField() {
+        let (ours, mut p1, p2, hazard) = digest();
+        p1.char_state.particle.borrow_mut().position.x = 12.5;
+        let theirs = StateDigest::of(&FrameSnapshot::save(30, &p1, &p2, &hazard, &MatchRng::new(0), &build_stage()));
+
+        assert_ne!(ours.hash(), theirs.hash());
+        let diff = ours.diff(&theirs.values());
+        assert_eq!(diff.len(), 1);
+        assert!(diff[0].starts_with("p1.particle.position.x: ours"));
+        assert!(diff[0].ends_with("theirs 12.5"));
+    }
+}
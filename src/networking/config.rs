@@ -1,74 +1,470 @@
 use std::io;
-use std::net::{SocketAddr, UdpSocket};
+use std::fs;
+use std::env;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use bincode::{serialize, deserialize}; 
 use serde_derive::{Serialize, Deserialize};
 
-pub fn client_setup() -> (UdpSocket, u8){
-    // ADDRESSING
-    let client_addresses: [SocketAddr; 2] = [
-        SocketAddr::from(([127, 0, 0, 1], 1667)),
-        SocketAddr::from(([127, 0, 0, 1], 1668)),
-        // can add backup client IPs
-    ];
+use crate::characters::registry::{self, CharacterId};
+use crate::networking::protocol::{self, Channel, ClientHello, Cookie, JoinRequest, MatchStart, MessageType, ProtocolError, RoomChoice, RoomId, RoomInfo, ServerInfo, SessionToken};
+use crate::networking::transmit::{self, Transport};
 
-    let server_addresses: [SocketAddr; 1] = [
-        SocketAddr::from(([127, 0, 0, 1], 1666)),
-        // can add backup server IPs
-    ];
+pub const DEFAULT_SERVER_BIND: &str = "0.0.0.0:1666";
+pub const DEFAULT_SERVER_NAME: &str = "Street Code Fighter";
+pub const DEFAULT_PLAYER_NAME: &str = "Player";
+// clients with no server address probe this port on the LAN, the default server port
+pub const DISCOVERY_PORT: u16 = 1666;
+// how long to collect answers to a probe
+pub const DISCOVERY_WAIT: Duration = Duration::from_secs(1);
+pub const DEFAULT_CLIENT_BIND: &str = "0.0.0.0:0"; // any interface, OS picks the port
+pub const CONFIG_FILE: &str = "street_code_fighter.cfg";
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5); // silence before a peer counts as disconnected
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(20);  // how long a dropped player has to rejoin
 
+// where to bind and who to talk to
+// defaults < config file < environment (SCF_*) < command line flags
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetConfig {
+    pub bind: String,
+    pub server: Option<String>, // None looks for one on the LAN
+    pub server_name: String,    // what a server calls itself in LAN discovery
+    pub name: String,           // what a client calls itself when it says hello
+    pub timeout: Duration,
+    pub grace: Duration,
+    pub room: RoomChoice, // which room to join or watch, command line only
+    pub record: Option<String>, // save a replay of the match here, command line only
+    pub character: Option<CharacterId>, // who to play as, None takes the seat's usual one. command line only
+}
+
+impl NetConfig {
+    pub fn server_defaults() -> NetConfig {
+        NetConfig {
+            bind: DEFAULT_SERVER_BIND.to_string(),
+            server: None,
+            server_name: DEFAULT_SERVER_NAME.to_string(),
+            name: DEFAULT_PLAYER_NAME.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            grace: DEFAULT_GRACE,
+            room: RoomChoice::Any,
+            record: None,
+            character: None,
+        }
+    }
+
+    pub fn client_defaults() -> NetConfig {
+        NetConfig {
+            bind: DEFAULT_CLIENT_BIND.to_string(),
+            server: None,
+            server_name: DEFAULT_SERVER_NAME.to_string(),
+            name: DEFAULT_PLAYER_NAME.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            grace: DEFAULT_GRACE,
+            room: RoomChoice::Any,
+            record: None,
+            character: None,
+        }
+    }
+
+    // build the config for `server`/`client`, args are whatever follows the mode
+    pub fn load(is_server: bool, args: &[String]) -> Result<NetConfig, String> {
+        let mut config = if is_server { NetConfig::server_defaults() } else { NetConfig::client_defaults() };
+
+        let path = env::var("SCF_CONFIG").unwrap_or(CONFIG_FILE.to_string());
+        match fs::read_to_string(&path) {
+            Ok(contents) => config.apply_file(&contents).map_err(|e| format!("{}: {}", path, e))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(format!("couldn't read {}: {}", path, e)),
+        }
+
+        config.apply_env(|key| env::var(key).ok())?;
+        config.apply_args(args)?;
+        Ok(config)
+    }
+
+    // `key = value` lines, # starts a comment
+    pub fn apply_file(&mut self, contents: &str) -> Result<(), String> {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i+1..].trim()),
+                None => return Err(format!("line {}: expected key = value", number + 1)),
+            };
+            match key {
+                "bind" => self.bind = value.to_string(),
+                "server" => self.server = Some(value.to_string()),
+                "server_name" => self.server_name = value.to_string(),
+                "name" => self.name = value.to_string(),
+                "timeout" => self.timeout = parse_timeout(value).map_err(|e| format!("line {}: {}", number + 1, e))?,
+                "grace" => self.grace = parse_timeout(value).map_err(|e| format!("line {}: {}", number + 1, e))?,
+                _ => return Err(format!("line {}: unknown setting `{}`", number + 1, key)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, var: F) -> Result<(), String> {
+        if let Some(bind) = var("SCF_BIND") { self.bind = bind; }
+        if let Some(server) = var("SCF_SERVER") { self.server = Some(server); }
+        if let Some(name) = var("SCF_SERVER_NAME") { self.server_name = name; }
+        if let Some(name) = var("SCF_NAME") { self.name = name; }
+        if let Some(timeout) = var("SCF_TIMEOUT") { self.timeout = parse_timeout(&timeout).map_err(|e| format!("SCF_TIMEOUT: {}", e))?; }
+        if let Some(grace) = var("SCF_GRACE") { self.grace = parse_timeout(&grace).map_err(|e| format!("SCF_GRACE: {}", e))?; }
+        Ok(())
+    }
+
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = match flag.as_str() {
+                "--bind" | "--server" => args.next().ok_or(format!("{} needs an address, e.g. {} 0.0.0.0:1666", flag, flag))?,
+                "--timeout" | "--grace" => args.next().ok_or(format!("{} needs a number of seconds, e.g. {} 5", flag, flag))?,
+                "--room" => args.next().ok_or("--room needs a room number from `rooms`, e.g. --room 3".to_string())?,
+                "--new-room" => args.next().ok_or("--new-room needs a name, e.g. --new-room dojo".to_string())?,
+                "--server-name" => args.next().ok_or("--server-name needs a name, e.g. --server-name basement".to_string())?,
+                "--name" => args.next().ok_or("--name needs a player name, e.g. --name ryu".to_string())?,
+                "--record" => args.next().ok_or("--record needs a file to save the replay to, e.g. --record match.scfr".to_string())?,
+                "--character" => args.next().ok_or("--character needs a name from `characters`, e.g. --character java".to_string())?,
+                _ => return Err(format!("unknown option `{}`", flag)),
+            };
+            match flag.as_str() {
+                "--bind" => self.bind = value.clone(),
+                "--server" => self.server = Some(value.clone()),
+                "--server-name" => self.server_name = value.clone(),
+                "--name" => self.name = value.clone(),
+                "--timeout" => self.timeout = parse_timeout(value)?,
+                "--grace" => self.grace = parse_timeout(value)?,
+                "--record" => self.record = Some(value.clone()),
+                "--character" => self.character = Some(registry::named(value)?),
+                "--room" => self.room = RoomChoice::Room(value.parse::<RoomId>().map_err(|_| format!("`{}` is not a room number", value))?),
+                _ => self.room = RoomChoice::Create(value.clone()),
+            }
+        }
+        Ok(())
+    }
+
+    // the server to talk to, once it has been given or picked from the LAN
+    pub fn server_address(&self) -> Result<&str, String> {
+        self.server.as_deref().ok_or("no server given, use --server host:port".to_string())
+    }
+}
+
+// seconds, fractions allowed
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("timeout must be a positive number of seconds, got `{}`", value)),
+    }
+}
+
+// resolve host:port, host names are allowed
+pub fn resolve(address: &str) -> Result<Vec<SocketAddr>, String> {
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs()
+        .map_err(|e| format!("couldn't resolve `{}`: {}", address, e))?
+        .collect();
+    if addresses.is_empty() {
+        return Err(format!("`{}` did not resolve to any address", address));
+    }
+    Ok(addresses)
+}
+
+pub fn bind(address: &str) -> Result<UdpSocket, String> {
+    let addresses = resolve(address)?;
+    UdpSocket::bind(&addresses[..]).map_err(|e| {
+        if e.kind() == io::ErrorKind::AddrInUse {
+            format!("couldn't bind to {}: the port is already in use (is another server running?)", address)
+        } else {
+            format!("couldn't bind to {}: {}", address, e)
+        }
+    })
+}
+
+// returns the socket, our player number, the token to rejoin with and the channel to the server
+pub fn client_setup(config: &NetConfig) -> Result<(UdpSocket, u8, SessionToken, Channel), String>{
     // BINDING & CONNECTING
-    let mut socket = UdpSocket::bind(&client_addresses[..]).expect("couldn't bind to address");
-    socket.connect(&server_addresses[..]).expect("couldn't bind to address");
+    let server = config.server_address()?;
+    let socket = bind(&config.bind)?;
+    let server_addresses = resolve(server)?;
+    socket.connect(&server_addresses[..]).map_err(|e| format!("couldn't connect to {}: {}", server, e))?;
 
-    println!("CONNECTED");
-    
-    socket.send(&[9]); // send initial message, b/c why not
+    println!("CONNECTED to {}", server);
+    socket.set_read_timeout(Some(protocol::REJOIN_INTERVAL / 4)).map_err(|e| e.to_string())?;
 
-    let mut player_number = {
-      let mut buffer = [0u8; 100]; // a buffer than accepts 100 
-      let (number_of_bytes, src_addr) = socket.recv_from(&mut buffer).expect("Didn't receive data");
-      *(&buffer[0]) // gets the first integer
+    // say hello, then ask the lobby for a player slot with the cookie it answered
+    // either can be lost, so whichever we are on is sent again until the answer comes
+    let hello = ClientHello::new(&config.name, registry::global()?.hash(), config.character);
+    let mut channel = Channel::new();
+    let mut cookie: Option<Cookie> = None;
+    let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+    let mut last_sent: Option<Instant> = None;
+    let asked = Instant::now();
+    let welcome = loop {
+        if asked.elapsed() > config.timeout {
+            return Err(format!("no reply from {}", server));
+        }
+        if last_sent.is_none_or(|t| t.elapsed() >= protocol::REJOIN_INTERVAL) {
+            let packet = match cookie {
+                None => Channel::new().encode(MessageType::Hello, 0, &hello),
+                Some(cookie) => channel.encode(MessageType::Join, 0, &JoinRequest{ hello: hello.clone(), cookie, room: config.room.clone() }),
+            };
+            socket.send(&packet).map_err(|e| format!("couldn't send join request: {}", e))?;
+            last_sent = Some(Instant::now());
+        }
+
+        let number_of_bytes = match transmit::receive_packet(&socket, &mut buffer).map_err(|e| e.to_string())? {
+            Some((n, _)) => n,
+            None => continue,
+        };
+        let bytes = &buffer[..number_of_bytes];
+        let msg_type = match protocol::read_header(bytes) {
+            Ok(header) => header.msg_type,
+            Err(e @ ProtocolError::VersionMismatch{..}) => return Err(format!("Server rejected us: {}", e)),
+            Err(_) => continue,
+        };
+        // challenges come from no channel in particular, the seat's channel starts with the Join
+        let mut unseated = Channel::new();
+        let reply = if cookie.is_none() { &mut unseated } else { &mut channel };
+        match msg_type {
+            MessageType::Challenge if cookie.is_none() => {
+                if let Ok((_, challenge)) = reply.decode::<Cookie>(bytes) {
+                    cookie = Some(challenge);
+                    last_sent = None; // Join straight away
+                }
+            },
+            MessageType::Welcome => {
+                if let Ok((_, welcome)) = reply.decode::<protocol::Welcome>(bytes) {
+                    break welcome;
+                }
+            },
+            MessageType::Rejected => {
+                if let Ok((_, reason)) = reply.decode::<String>(bytes) {
+                    return Err(format!("Server rejected us: {}", reason));
+                }
+            },
+            _ => {},
+        }
     };
-    println!("Player number: {:?}",player_number);
+    // from here on the server drops anything from us without it
+    channel.set_session(welcome.session);
+    println!("Player number: {:?} as {}", welcome.player, hello.name);
     
-    (socket, player_number)
+    Ok((socket, welcome.player, welcome.token, channel))
 }
 
-pub fn server_setup() -> UdpSocket{
-    // ADDRESSING
-    let server_addresses: [SocketAddr; 1] = [
-        SocketAddr::from(([127, 0, 0, 1], 1666)),
-        // can add backup IPs
-    ];
+// spectators skip the Join handshake, they keep sending Spectate until a snapshot arrives
+pub fn spectator_setup(config: &NetConfig) -> Result<(UdpSocket, Channel), String>{
+    let server = config.server_address()?;
+    let socket = bind(&config.bind)?;
+    let server_addresses = resolve(server)?;
+    socket.connect(&server_addresses[..]).map_err(|e| format!("couldn't connect to {}: {}", server, e))?;
+
+    println!("WATCHING {}", server);
 
+    Ok((socket, Channel::new()))
+}
+
+pub fn server_setup(config: &NetConfig) -> Result<UdpSocket, String>{
     // BINDING
-    let socket = UdpSocket::bind(&server_addresses[..]).expect("couldn't bind to address");
+    let socket = bind(&config.bind)?;
     
-    println!("CONNECTED");
+    println!("LISTENING on {}", config.bind);
 
-    socket
+    Ok(socket)
 }
 
-pub fn client_connect(socket: &UdpSocket, 
-                  client_addresses: &mut HashMap<SocketAddr,u8>,
-                  player_count: u8) -> u8 {
-    let mut buffer = [0u8; 100]; // a buffer than accepts 100
-    let (number_of_bytes, src_addr) = {
-        match socket.recv_from(&mut buffer){
-            Ok(t) => t,
-            Err(e) => panic!("recv_from function failed: {:?}",e),
+// ask the lobby what rooms it has, gives up after the disconnect timeout
+pub fn list_rooms(config: &NetConfig) -> Result<Vec<RoomInfo>, String>{
+    let server = config.server_address()?;
+    let socket = bind(&config.bind)?;
+    let server_addresses = resolve(server)?;
+    socket.connect(&server_addresses[..]).map_err(|e| format!("couldn't connect to {}: {}", server, e))?;
+    socket.set_read_timeout(Some(protocol::REJOIN_INTERVAL)).map_err(|e| e.to_string())?;
+
+    let mut channel = Channel::new();
+    let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+    let asked = Instant::now();
+    while asked.elapsed() < config.timeout {
+        // a fresh request every time in case the last one or its answer was lost
+        let _ = socket.send(&Channel::new().encode(MessageType::ListRooms, 0, &()));
+        let number_of_bytes = match transmit::receive_packet(&socket, &mut buffer).map_err(|e| e.to_string())? {
+            Some((n, _)) => n,
+            None => continue,
+        };
+        let bytes = &buffer[..number_of_bytes];
+        match protocol::read_header(bytes).map(|header| header.msg_type) {
+            Ok(MessageType::Rooms) => {
+                if let Ok((_, rooms)) = channel.decode::<Vec<RoomInfo>>(bytes) {
+                    return Ok(rooms);
+                }
+            },
+            Err(e @ ProtocolError::VersionMismatch{..}) => return Err(format!("Server rejected us: {}", e)),
+            _ => {},
         }
-    };
+    }
+    Err(format!("no reply from {}", server))
+}
+
+// every server on the LAN listening on `port` that answered within `wait`, including our own machine's
+// probes go out a few times in case one is lost, the caller joins one of them as usual
+pub fn discover(bind_address: &str, port: u16, wait: Duration) -> Result<Vec<(SocketAddr, ServerInfo)>, String>{
+    let socket = bind(bind_address)?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(wait / 8)).map_err(|e| e.to_string())?;
+    let targets: Vec<SocketAddr> = vec![([255, 255, 255, 255], port).into(), ([127, 0, 0, 1], port).into()];
 
-    // Client IPs and player #
-    if !client_addresses.contains_key(&src_addr) { // for first time
-        println!("First time connection to: {:?} > {:?}", src_addr, &buffer[0]); // test to print IP and initial info sent 
-        client_addresses.insert(src_addr, player_count); // add to set
-        socket.send_to(&[player_count], src_addr); // send player # 
-        return player_count + 1; // increment player #
-    } 
+    let mut found: Vec<(SocketAddr, ServerInfo)> = Vec::new();
+    let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+    let start = Instant::now();
+    let mut probes = 0;
+    while start.elapsed() < wait {
+        if probes < 3 && start.elapsed() >= wait * probes / 4 {
+            for target in targets.iter() {
+                let _ = socket.send_to(&protocol::discovery_probe(), target); // no network, no broadcast, we still try the others
+            }
+            probes += 1;
+        }
+        if let Some((number_of_bytes, address)) = transmit::receive_packet(&socket, &mut buffer).map_err(|e| e.to_string())? {
+            if let Some(info) = protocol::read_discovery_reply(&buffer[..number_of_bytes]) {
+                if !found.iter().any(|(a, _)| *a == address) {
+                    found.push((address, info));
+                }
+            }
+        }
+    }
 
-    return player_count;
-}
\ No newline at end of file
+    // a server on this machine answers both the broadcast and the loopback probe, keep its LAN address
+    let on_lan: Vec<(u16, String)> = found.iter()
+        .filter(|(address, _)| !address.ip().is_loopback())
+        .map(|(address, info)| (address.port(), info.name.clone()))
+        .collect();
+    found.retain(|(address, info)| !address.ip().is_loopback() || !on_lan.contains(&(address.port(), info.name.clone())));
+    Ok(found)
+}
+
+// tell every player the match is starting, how many frames their inputs are delayed and the match's seed
+pub fn start_match(socket: &dyn Transport,
+                   client_addresses: &HashMap<SocketAddr,u8>,
+                   channels: &mut HashMap<SocketAddr,Channel>,
+                   start: &MatchStart) {
+    for address in client_addresses.keys(){
+        if let Some(channel) = channels.get_mut(address){
+            let _ = socket.send_to(&channel.encode(MessageType::Start, 0, start), address);
+        }
+    }
+}
+
+// block until the lobby finds us an opponent, returns how the match starts
+// heartbeat meanwhile so our seat isn't given away
+pub fn wait_for_start(socket: &UdpSocket, channel: &mut Channel) -> Result<MatchStart, String> {
+    socket.set_read_timeout(Some(protocol::HEARTBEAT_INTERVAL)).map_err(|e| e.to_string())?;
+    let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+    loop {
+        if channel.needs_heartbeat(Instant::now()) {
+            transmit::send_heartbeat(socket, channel);
+        }
+        let number_of_bytes = match transmit::receive_packet(socket, &mut buffer) {
+            Ok(Some((n, _))) => n,
+            Ok(None) => continue,
+            Err(e) => return Err(format!("lost the server while waiting: {}", e)),
+        };
+        let bytes = &buffer[..number_of_bytes];
+        match protocol::read_header(bytes).map(|header| header.msg_type) {
+            Ok(MessageType::Start) => {
+                if let Ok((_, start)) = channel.decode::<MatchStart>(bytes) {
+                    return Ok(start);
+                }
+            },
+            Err(e @ ProtocolError::VersionMismatch{..}) => return Err(format!("Server rejected us: {}", e)),
+            // anything else still shows the server is there
+            _ => { let _ = channel.decode::<()>(bytes); },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(a: &[&str]) -> Vec<String> { a.iter().map(|s| s.to_string()).collect() }
+
+    #[test]
+    pub fn testPrecedence() {
+        let mut config = NetConfig::client_defaults();
+        config.apply_file("# lan box\nserver = 10.0.0.5:1666\nbind = 0.0.0.0:4000\n").unwrap();
+        assert_eq!(config.server, Some("10.0.0.5:1666".to_string()));
+
+        config.apply_env(|key| if key == "SCF_SERVER" { Some("10.0.0.6:1666".to_string()) } else { None }).unwrap();
+        assert_eq!(config.server, Some("10.0.0.6:1666".to_string()));
+        assert_eq!(config.bind, "0.0.0.0:4000");
+
+        config.apply_args(&args(&["--server", "host:1700", "--timeout", "2.5"])).unwrap();
+        assert_eq!(config.server, Some("host:1700".to_string()));
+        assert_eq!(config.timeout, Duration::from_millis(2500));
+        assert_eq!(config.grace, DEFAULT_GRACE);
+        assert_eq!(config.name, DEFAULT_PLAYER_NAME);
+        config.apply_args(&args(&["--name", "ryu"])).unwrap();
+        assert_eq!(config.name, "ryu");
+
+        config.apply_args(&args(&["--room", "7"])).unwrap();
+        assert_eq!(config.room, RoomChoice::Room(7));
+        config.apply_args(&args(&["--new-room", "dojo"])).unwrap();
+        assert_eq!(config.room, RoomChoice::Create("dojo".to_string()));
+        assert_eq!(config.record, None);
+        config.apply_args(&args(&["--record", "match.scfr"])).unwrap();
+        assert_eq!(config.record, Some("match.scfr".to_string()));
+        config.apply_args(&args(&["--character", "java"])).unwrap();
+        assert_eq!(config.character, Some(CharacterId(2)));
+    }
+
+    #[test]
+    pub fn testBadInput() {
+        let mut config = NetConfig::server_defaults();
+        assert!(config.apply_file("port 1666").is_err());
+        assert!(config.apply_file("colour = red").is_err());
+        assert!(config.apply_args(&args(&["--bind"])).is_err());
+        assert!(config.apply_args(&args(&["--fast"])).is_err());
+        assert!(config.apply_args(&args(&["--timeout", "-1"])).is_err());
+        assert!(config.apply_file("timeout = soon").is_err());
+        assert!(config.apply_args(&args(&["--room", "lobby"])).is_err());
+        assert!(config.apply_args(&args(&["--character", "cobol"])).is_err());
+    }
+
+    #[test]
+    pub fn testPortInUse() {
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = taken.local_addr().unwrap().to_string();
+        let config = NetConfig { bind: address, ..NetConfig::server_defaults() };
+
+        let error = server_setup(&config).err().unwrap();
+        assert!(error.contains("already in use"));
+    }
+
+    #[test]
+    pub fn testDiscoverLocalServer() {
+        use std::thread;
+        use crate::networking::server::Lobby;
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let lobby = thread::spawn(move || {
+            let mut lobby = Lobby::new("upstairs").unwrap();
+            server.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+            let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+            let start = Instant::now();
+            while start.elapsed() < DISCOVERY_WAIT {
+                if let Ok(Some((n, address))) = transmit::receive_packet(&server, &mut buffer) {
+                    lobby.route(&server, address, &buffer[..n]);
+                }
+            }
+        });
+
+        let found = discover("127.0.0.1:0", port, DISCOVERY_WAIT / 2).unwrap();
+        lobby.join().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.port(), port);
+        assert_eq!(found[0].1, ServerInfo::new("upstairs", 0));
+    }
+}
@@ -0,0 +1,395 @@
+use bincode::{serialize, deserialize};
+use serde_derive::{Serialize, Deserialize};
+
+use crate::animation::sprites::State;
+use crate::characters::characterAbstract::Fighter;
+use crate::networking::protocol::Channel;
+use crate::physics::hazard::Hazard;
+use crate::physics::rect::Point;
+
+// positions are sent in eighths of a pixel
+pub const POSITION_SCALE: f32 = 8.0;
+// a sprite state and its animation frame fit in this many bits
+pub const STATE_BITS: u32 = 4;
+pub const FRAME_BITS: u32 = 6;
+// how many sent states a peer's history remembers, older acks are ignored
+pub const DELTA_HISTORY: usize = 32;
+
+// writes values of any width into a byte buffer, lowest bit first
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    used: u32, // bits used in the last byte
+}
+
+impl Default for BitWriter {
+    fn default() -> BitWriter {
+        BitWriter::new()
+    }
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter { bytes: Vec::new(), used: 8 }
+    }
+
+    pub fn write(&mut self, value: u32, bits: u32) {
+        for bit in 0..bits {
+            if self.used == 8 {
+                self.bytes.push(0);
+                self.used = 0;
+            }
+            if value >> bit & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << self.used;
+            }
+            self.used += 1;
+        }
+    }
+
+    pub fn write_flag(&mut self, flag: bool) { self.write(flag as u32, 1); }
+
+    // zigzag so small negative numbers stay small, then 2 bits say how wide it is
+    pub fn write_int(&mut self, value: i32) {
+        let zigzag = ((value << 1) ^ (value >> 31)) as u32;
+        let width = VARINT_WIDTHS.iter().position(|w| *w == 32 || zigzag < 1 << *w).unwrap();
+        self.write(width as u32, 2);
+        self.write(zigzag, VARINT_WIDTHS[width]);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> { self.bytes }
+}
+
+const VARINT_WIDTHS: [u32; 4] = [4, 8, 16, 32];
+
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize, // in bits
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, position: 0 }
+    }
+
+    pub fn read(&mut self, bits: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for bit in 0..bits {
+            let byte = self.bytes.get(self.position / 8)?;
+            if byte >> (self.position % 8) & 1 == 1 {
+                value |= 1 << bit;
+            }
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    pub fn read_flag(&mut self) -> Option<bool> { self.read(1).map(|bit| bit == 1) }
+
+    pub fn read_int(&mut self) -> Option<i32> {
+        let width = *VARINT_WIDTHS.get(self.read(2)? as usize)?;
+        let zigzag = self.read(width)?;
+        Some((zigzag >> 1) as i32 ^ -((zigzag & 1) as i32))
+    }
+}
+
+// the variant number bincode uses for a sprite state
+fn state_index(state: &State) -> u32 {
+    serialize(state).ok().and_then(|bytes| deserialize::<u32>(&bytes).ok()).unwrap_or(0)
+}
+
+fn state_from_index(index: u32) -> Option<State> {
+    serialize(&index).ok().and_then(|bytes| deserialize::<State>(&bytes).ok())
+}
+
+fn quantize(value: f32) -> i32 { (value * POSITION_SCALE).round() as i32 }
+fn unquantize(value: i32) -> f32 { value as f32 / POSITION_SCALE }
+
+// what a spectator needs to draw one fighter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PackedFighter {
+    pub x: i32, // quantized, see POSITION_SCALE
+    pub y: i32,
+    pub health: i32,
+    pub state: State,
+    pub frame: u32,
+}
+
+impl PackedFighter {
+    pub fn new(fighter: &Fighter) -> PackedFighter {
+        let particle = fighter.char_state.particle.borrow();
+        PackedFighter {
+            x: quantize(particle.position.x),
+            y: quantize(particle.position.y),
+            health: particle.health,
+            state: fighter.char_state.state,
+            frame: fighter.char_state.current_frame.max(0) as u32,
+        }
+    }
+
+    pub fn apply(&self, fighter: &mut Fighter) {
+        {
+            let mut particle = fighter.char_state.particle.borrow_mut();
+            particle.position.x = unquantize(self.x);
+            particle.position.y = unquantize(self.y);
+            particle.health = self.health;
+        }
+        fighter.char_state.state = self.state;
+        fighter.char_state.current_frame = self.frame as i32;
+    }
+}
+
+// everything a spectator is shown for one frame, quantized
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PackedState {
+    pub p1: PackedFighter,
+    pub p2: PackedFighter,
+    pub hazard: [i32; 6], // position x/y, sprite x/y/w/h
+}
+
+impl PackedState {
+    pub fn new(p1: &Fighter, p2: &Fighter, hazard: &Hazard) -> PackedState {
+        PackedState {
+            p1: PackedFighter::new(p1),
+            p2: PackedFighter::new(p2),
+            hazard: [hazard.position.x, hazard.position.y,
+                     hazard.sprite.x, hazard.sprite.y, hazard.sprite.w, hazard.sprite.h],
+        }
+    }
+
+    pub fn apply(&self, p1: &mut Fighter, p2: &mut Fighter, hazard: &mut Hazard) {
+        self.p1.apply(p1);
+        self.p2.apply(p2);
+        hazard.position = Point::new(self.hazard[0], self.hazard[1]);
+        hazard.sprite.set_x(self.hazard[2]);
+        hazard.sprite.set_y(self.hazard[3]);
+        hazard.sprite.set_width(self.hazard[4] as u32);
+        hazard.sprite.set_height(self.hazard[5] as u32);
+    }
+
+    // the integer fields, in wire order
+    fn ints(&self) -> [i32; 12] {
+        let h = &self.hazard;
+        [self.p1.x, self.p1.y, self.p1.health, self.p2.x, self.p2.y, self.p2.health, h[0], h[1], h[2], h[3], h[4], h[5]]
+    }
+
+    // every field gets a changed bit when there is a baseline, unchanged ones cost nothing more
+    // ints are sent as the difference from the baseline, sprite state and frame as they are
+    pub fn encode(&self, baseline: Option<&PackedState>) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        let base_ints = baseline.map(|b| b.ints());
+        for (i, value) in self.ints().iter().enumerate() {
+            match base_ints {
+                Some(base) if base[i] == *value => writer.write_flag(false),
+                Some(base) => { writer.write_flag(true); writer.write_int(value.wrapping_sub(base[i])); },
+                None => writer.write_int(*value),
+            }
+        }
+        let base_fighters = baseline.map(|b| [b.p1, b.p2]);
+        for (i, fighter) in [self.p1, self.p2].iter().enumerate() {
+            let unchanged = base_fighters.is_some_and(|b| b[i].state == fighter.state && b[i].frame == fighter.frame);
+            if baseline.is_some() { writer.write_flag(!unchanged); }
+            if !unchanged {
+                writer.write(state_index(&fighter.state), STATE_BITS);
+                writer.write(fighter.frame.min((1 << FRAME_BITS) - 1), FRAME_BITS);
+            }
+        }
+        writer.into_bytes()
+    }
+
+    // None if the bits are cut short or name a state we don't know
+    pub fn decode(bytes: &[u8], baseline: Option<&PackedState>) -> Option<PackedState> {
+        let mut reader = BitReader::new(bytes);
+        let base_ints = baseline.map(|b| b.ints());
+        let mut ints = [0i32; 12];
+        for i in 0..ints.len() {
+            ints[i] = match base_ints {
+                Some(base) => if reader.read_flag()? { base[i].wrapping_add(reader.read_int()?) } else { base[i] },
+                None => reader.read_int()?,
+            };
+        }
+        let base_fighters = baseline.map(|b| [b.p1, b.p2]);
+        let mut sprites = [(State::Idle, 0u32); 2];
+        for (i, sprite) in sprites.iter_mut().enumerate() {
+            let changed = if baseline.is_some() { reader.read_flag()? } else { true };
+            *sprite = if changed {
+                (state_from_index(reader.read(STATE_BITS)?)?, reader.read(FRAME_BITS)?)
+            } else {
+                let base = base_fighters?[i];
+                (base.state, base.frame)
+            };
+        }
+
+        Some(PackedState {
+            p1: PackedFighter { x: ints[0], y: ints[1], health: ints[2], state: sprites[0].0, frame: sprites[0].1 },
+            p2: PackedFighter { x: ints[3], y: ints[4], health: ints[5], state: sprites[1].0, frame: sprites[1].1 },
+            hazard: [ints[6], ints[7], ints[8], ints[9], ints[10], ints[11]],
+        })
+    }
+}
+
+// GameState payload, frame is in the packet header
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeltaPacket {
+    pub baseline: Option<u32>, // frame the bits are relative to, None for a full state
+    pub bits: Vec<u8>,
+}
+
+// server side, per peer: what we sent under which sequence, to find the newest state they acked
+#[derive(Default)]
+pub struct DeltaHistory {
+    sent: Vec<(u32, u32, PackedState)>, // sequence, frame, state
+}
+
+impl DeltaHistory {
+    pub fn new() -> DeltaHistory {
+        DeltaHistory::default()
+    }
+
+    // delta against the newest state the peer has acked, a full state if there is none
+    pub fn packet(&self, channel: &Channel, state: &PackedState) -> DeltaPacket {
+        let acked = self.sent.iter().rev().find(|(sequence, _, _)| channel.is_acked(*sequence));
+        DeltaPacket {
+            baseline: acked.map(|(_, frame, _)| *frame),
+            bits: state.encode(acked.map(|(_, _, base)| base)),
+        }
+    }
+
+    pub fn sent(&mut self, sequence: u32, frame: u32, state: PackedState) {
+        self.sent.push((sequence, frame, state));
+        if self.sent.len() > DELTA_HISTORY { self.sent.remove(0); }
+    }
+}
+
+// client side: the states we rebuilt, any of them may be a baseline later
+#[derive(Default)]
+pub struct DeltaReceiver {
+    received: Vec<(u32, PackedState)>,
+}
+
+impl DeltaReceiver {
+    pub fn new() -> DeltaReceiver {
+        DeltaReceiver::default()
+    }
+
+    pub fn receive(&mut self, frame: u32, packet: &DeltaPacket) -> Option<PackedState> {
+        let baseline = match packet.baseline {
+            Some(base_frame) => Some(self.received.iter().find(|(f, _)| *f == base_frame).map(|(_, s)| *s)?),
+            None => None,
+        };
+        let state = PackedState::decode(&packet.bits, baseline.as_ref())?;
+        self.received.push((frame, state));
+        // the server never goes further back than its own history
+        if self.received.len() > 2 * DELTA_HISTORY { self.received.remove(0); }
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::characters::characterAbstract::CharacterState;
+    use crate::networking::protocol::{MessageType, MAX_PACKET_SIZE};
+    use crate::simulation::{SimConfig, Simulation};
+    use crate::physics::hazard::HazardVar;
+    use crate::physics::particle::Particle;
+
+    #[test]
+    pub fn testBitsRoundTrip() {
+        let mut writer = BitWriter::new();
+        writer.write(5, 3);
+        writer.write_flag(true);
+        for value in [0, -1, 7, -300, 70000, i32::MIN, i32::MAX].iter() {
+            writer.write_int(*value);
+        }
+        let bytes = writer.into_bytes();
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read(3), Some(5));
+        assert_eq!(reader.read_flag(), Some(true));
+        for value in [0, -1, 7, -300, 70000, i32::MIN, i32::MAX].iter() {
+            assert_eq!(reader.read_int(), Some(*value));
+        }
+    }
+
+    #[test]
+    pub fn testDeltaRoundTrip() {
+        let p1 = Fighter::new(CharacterState::new());
+        let mut p2 = Fighter::new(CharacterState::new());
+        let hazard = Hazard::new();
+        let before = PackedState::new(&p1, &p2, &hazard);
+
+        p2.char_state.particle.borrow_mut().position.x = 300.4;
+        p2.char_state.state = State::HKick;
+        p2.char_state.current_frame = 3;
+        let after = PackedState::new(&p1, &p2, &hazard);
+
+        assert_eq!(PackedState::decode(&after.encode(None), None), Some(after));
+        assert_eq!(PackedState::decode(&after.encode(Some(&before)), Some(&before)), Some(after));
+        assert!(after.encode(Some(&before)).len() < after.encode(None).len());
+        assert_eq!(unquantize(after.p2.x), 300.375);
+    }
+
+    #[test]
+    pub fn testMissingBaseline() {
+        let p1 = Fighter::new(CharacterState::new());
+        let p2 = Fighter::new(CharacterState::new());
+        let state = PackedState::new(&p1, &p2, &Hazard::new());
+        let mut receiver = DeltaReceiver::new();
+
+        assert!(receiver.receive(5, &DeltaPacket { baseline: Some(4), bits: state.encode(Some(&state)) }).is_none());
+        assert!(receiver.receive(5, &DeltaPacket { baseline: None, bits: state.encode(None) }).is_some());
+        assert!(receiver.receive(6, &DeltaPacket { baseline: Some(5), bits: state.encode(Some(&state)) }).is_some());
+    }
+
+    // what GameState used to put on the wire every frame
+    fn legacy_size(p1: &Fighter, p2: &Fighter, hazard: &Hazard) -> usize {
+        let legacy: (Particle, State, i32, Particle, State, i32, HazardVar) = (
+            p1.char_state.position(), p1.char_state.state, p1.char_state.current_frame,
+            p2.char_state.position(), p2.char_state.state, p2.char_state.current_frame,
+            HazardVar::new(hazard));
+        Channel::new().encode(MessageType::GameState, 0, &legacy).len()
+    }
+
+    // size benchmark: ten seconds of two players walking, jumping and kicking
+    // with one in ten acks lost, run with --nocapture to see the numbers
+    #[test]
+    pub fn testSnapshotSize() {
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+
+        let mut server = Channel::new();
+        let mut spectator = Channel::new();
+        let mut history = DeltaHistory::new();
+        let mut receiver = DeltaReceiver::new();
+        let (mut legacy_total, mut delta_total, mut largest) = (0, 0, 0);
+        let frames = 600;
+
+        for frame in 0..frames {
+            // a made up but busy input pattern
+            let input_1: HashSet<u8> = [(frame / 20 % 4) as u8].iter().cloned().collect();
+            let input_2: HashSet<u8> = [(frame / 15 % 6) as u8].iter().cloned().collect();
+            sim.step(&input_1, &input_2);
+
+            let state = PackedState::new(&sim.fighter1, &sim.fighter2, &sim.hazard);
+            let packet = history.packet(&server, &state);
+            let sequence = server.local_sequence();
+            let bytes = server.encode(MessageType::GameState, frame, &packet);
+            history.sent(sequence, frame, state);
+
+            legacy_total += legacy_size(&sim.fighter1, &sim.fighter2, &sim.hazard);
+            delta_total += bytes.len();
+            largest = largest.max(bytes.len());
+
+            let (_, packet) = spectator.decode::<DeltaPacket>(&bytes).unwrap();
+            assert_eq!(receiver.receive(frame, &packet), Some(state));
+            // the spectator's heartbeat carries the ack, now and then it is lost
+            let ack = spectator.encode(MessageType::Heartbeat, 0, &());
+            if frame % 10 != 9 { server.decode::<()>(&ack).unwrap(); }
+        }
+
+        println!("legacy GameState: {} bytes/frame, delta: {} bytes/frame, largest delta packet: {} bytes",
+            legacy_total / frames as usize, delta_total / frames as usize, largest);
+        assert!(delta_total * 3 < legacy_total);
+        // well under a 576 byte minimum IPv4 MTU, never mind MAX_PACKET_SIZE
+        assert!(largest < 576 - 28);
+        assert!(largest < MAX_PACKET_SIZE);
+    }
+}
@@ -1,2 +1,12 @@
+pub mod chat;
+pub mod checksum;
 pub mod config;
-pub mod transmit;
\ No newline at end of file
+pub mod delta;
+pub mod netsim;
+pub mod transmit;
+pub mod rollback;
+pub mod protocol;
+pub mod room;
+pub mod server;
+pub mod stats;
+pub mod timing;
\ No newline at end of file
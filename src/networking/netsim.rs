@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::networking::transmit::Transport;
+
+// what a SimSocket does to everything it sends
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conditions {
+    pub latency: Duration,       // every packet is held at least this long
+    pub jitter: Duration,        // plus up to this much more, picked per packet
+    pub loss: f64,               // chance a packet is never sent
+    pub duplicate: f64,          // chance it is sent twice
+    pub reorder: f64,            // chance it is held back a further reorder_delay so later ones overtake it
+    pub reorder_delay: Duration,
+}
+
+impl Conditions {
+    pub fn perfect() -> Conditions {
+        Conditions {
+            latency: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::from_millis(0),
+        }
+    }
+}
+
+// what happened to the packets sent so far
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimStats {
+    pub sent: u32,
+    pub lost: u32,
+    pub duplicated: u32,
+    pub reordered: u32,
+}
+
+struct Delayed {
+    due: Instant,
+    to: SocketAddr,
+    bytes: Vec<u8>,
+}
+
+// a loopback UdpSocket that makes the network worse on the way out
+// there is no thread behind it, held packets leave when the owner next sends, receives or flushes,
+// so it suits game and test loops that poll their socket all the time
+pub struct SimSocket {
+    socket: UdpSocket,
+    conditions: RefCell<Conditions>,
+    rng: RefCell<StdRng>, // seeded so a failing test fails the same way again
+    held: RefCell<Vec<Delayed>>,
+    stats: RefCell<SimStats>,
+}
+
+impl SimSocket {
+    // always nonblocking
+    pub fn bind<A: ToSocketAddrs>(address: A, conditions: Conditions, seed: u64) -> io::Result<SimSocket> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(SimSocket {
+            socket,
+            conditions: RefCell::new(conditions),
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+            held: RefCell::new(Vec::new()),
+            stats: RefCell::new(SimStats::default()),
+        })
+    }
+
+    pub fn connect<A: ToSocketAddrs>(&self, address: A) -> io::Result<()> { self.socket.connect(address) }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> { self.socket.local_addr() }
+
+    // applies to packets sent from now on
+    pub fn set_conditions(&self, conditions: Conditions) { *self.conditions.borrow_mut() = conditions; }
+    pub fn stats(&self) -> SimStats { *self.stats.borrow() }
+
+    // send everything that is due, oldest due first
+    pub fn flush(&self) {
+        let now = Instant::now();
+        let mut held = self.held.borrow_mut();
+        held.sort_by_key(|packet| packet.due);
+        let due = held.iter().take_while(|packet| packet.due <= now).count();
+        for packet in held.drain(..due) {
+            let _ = self.socket.send_to(&packet.bytes, packet.to); // a real socket would lose it too
+        }
+    }
+
+    fn hold(&self, buf: &[u8], to: SocketAddr) -> io::Result<usize> {
+        {
+            let conditions = self.conditions.borrow();
+            let mut rng = self.rng.borrow_mut();
+            let mut stats = self.stats.borrow_mut();
+            let mut held = self.held.borrow_mut();
+            stats.sent += 1;
+
+            if rng.gen_bool(conditions.loss.min(1.0)) {
+                stats.lost += 1;
+            } else {
+                let copies = if rng.gen_bool(conditions.duplicate.min(1.0)) { stats.duplicated += 1; 2 } else { 1 };
+                for _ in 0..copies {
+                    let mut delay = conditions.latency + conditions.jitter.mul_f64(rng.gen::<f64>());
+                    if rng.gen_bool(conditions.reorder.min(1.0)) {
+                        stats.reordered += 1;
+                        delay += conditions.reorder_delay;
+                    }
+                    held.push(Delayed { due: Instant::now() + delay, to, bytes: buf.to_vec() });
+                }
+            }
+        }
+        self.flush();
+        // like UDP, the sender never finds out
+        Ok(buf.len())
+    }
+}
+
+impl Transport for SimSocket {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let peer = self.socket.peer_addr()?;
+        self.hold(buf, peer)
+    }
+
+    fn send_to(&self, buf: &[u8], address: &SocketAddr) -> io::Result<usize> { self.hold(buf, *address) }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.flush();
+        self.socket.recv_from(buf)
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush();
+        self.socket.peek(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    fn pair(conditions: Conditions) -> (SimSocket, SimSocket) {
+        let a = SimSocket::bind("127.0.0.1:0", conditions, 1).unwrap();
+        let b = SimSocket::bind("127.0.0.1:0", Conditions::perfect(), 2).unwrap();
+        a.connect(b.local_addr().unwrap()).unwrap();
+        (a, b)
+    }
+
+    // everything b can read within a little while
+    fn drain(a: &SimSocket, b: &SimSocket) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 16];
+        let until = Instant::now() + Duration::from_millis(200);
+        while Instant::now() < until {
+            a.flush();
+            while let Ok((n, _)) = b.recv_from(&mut buffer) {
+                received.extend_from_slice(&buffer[..n]);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        received
+    }
+
+    #[test]
+    pub fn testPerfectLink() {
+        let (a, b) = pair(Conditions::perfect());
+        for i in 0..20u8 {
+            a.send(&[i]).unwrap();
+        }
+        assert_eq!(drain(&a, &b), (0..20u8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    pub fn testBadLink() {
+        let (a, b) = pair(Conditions {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            loss: 0.2,
+            duplicate: 0.1,
+            reorder: 0.2,
+            reorder_delay: Duration::from_millis(30),
+        });
+        for i in 0..100u8 {
+            a.send(&[i]).unwrap();
+        }
+        // nothing arrives before the latency is up
+        let mut buffer = [0u8; 16];
+        assert!(b.recv_from(&mut buffer).is_err());
+
+        let received = drain(&a, &b);
+        let stats = a.stats();
+        assert_eq!(stats.sent, 100);
+        assert!(stats.lost > 0 && stats.duplicated > 0 && stats.reordered > 0);
+        assert_eq!(received.len() as u32, stats.sent - stats.lost + stats.duplicated);
+        assert!(received.windows(2).any(|w| w[0] > w[1]));
+    }
+}
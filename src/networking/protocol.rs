@@ -0,0 +1,644 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+use bincode::{serialize, deserialize, serialized_size};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::characters::registry::{self, CharacterId};
+use crate::simulation::SimConfig;
+
+// every packet starts with these so stray datagrams and old builds are rejected
+pub const PROTOCOL_MAGIC: u32 = 0x5343_4631; // "SCF1"
+// LAN discovery probes and replies start with this instead and have no header,
+// so a build can find servers running any protocol version
+pub const DISCOVERY_MAGIC: u32 = 0x5343_4644; // "SCFD"
+pub const PROTOCOL_VERSION: u16 = 17;
+
+// largest datagram we ever expect to receive
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+// send something at least this often so the peer knows we are still here
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+// how often a client that lost the server asks to rejoin
+pub const REJOIN_INTERVAL: Duration = Duration::from_secs(1);
+// how long the server waits for a player to ack a Resync before sending it again
+pub const RESYNC_INTERVAL: Duration = Duration::from_millis(200);
+
+// a Challenge cookie is only good for this long
+pub const COOKIE_LIFETIME: Duration = Duration::from_secs(10);
+
+// room, server and player names are cut to this many bytes
+pub const MAX_ROOM_NAME: usize = 24;
+pub const MAX_SERVER_NAME: usize = 32;
+pub const MAX_PLAYER_NAME: usize = 16;
+// a room list never gets bigger than one packet
+pub const MAX_LISTED_ROOMS: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageType {
+    Hello,           // client -> server, payload is a ClientHello, answered with a Challenge
+    Challenge,       // server -> client, payload is the Cookie to send back in the Join
+    Join,            // client -> server, asking for a player slot, payload is a JoinRequest
+    Welcome,         // server -> client, payload is a Welcome
+    Start,           // server -> clients, both players connected, payload is a MatchStart
+    Input,           // InputStruct for one frame and the few before it
+    GameState,       // delta::DeltaPacket, relative to a state the peer acked
+    VersionMismatch, // reply to a peer speaking another protocol version
+    Heartbeat,       // keeps the connection alive while nothing else is being sent
+    Quit,            // client -> server, the player closed the game
+    Forfeit,         // server -> client, payload is why the opponent left the match
+    Rejoin,          // client -> server, payload is the session token from Welcome
+    Pause,           // server -> client, the opponent dropped and may rejoin
+    Resync,          // server -> clients, full match state after a rejoin or for a new spectator
+    Spectate,        // client -> server, asking to watch a match, payload is an Option<RoomId>
+    ListRooms,       // client -> server, what rooms are there
+    Rooms,           // server -> client, payload is a Vec<RoomInfo>
+    Rejected,        // server -> client, a Join that couldn't be honoured, payload is why
+    Chat,            // client -> server the text typed, server -> clients a chat::ChatLine
+    Checksum,        // client -> server, (epoch, hash) of a confirmed frame, see checksum::StateDigest
+    Desync,          // server -> client, the server's digest values for a frame whose checksum didn't match
+    Ping,            // either way, payload is an id the other side sends straight back in a Pong
+    Pong,
+}
+
+// handed out in Welcome, proves who a rejoining client is
+pub type SessionToken = u64;
+
+// handed out in Welcome and carried in the header of everything sent on that player's channel,
+// so packets spoofed from their address are dropped. 0 until a seat is taken
+pub type SessionId = u32;
+
+// proves a Join comes from the address that got the Challenge, the server keeps no state between the two
+// how long it took to come back is the first round trip time the server has for the player
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cookie {
+    pub issued: u32, // server's clock, milliseconds
+    pub mac: u64,
+}
+
+// the first thing a client says
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClientHello {
+    pub version: u16,
+    pub name: String,
+    pub characters: u64, // Registry::hash of the client's character files
+    pub character: Option<CharacterId>, // who they want to play as, None for the seat's usual one
+}
+
+impl ClientHello {
+    pub fn new(name: &str, characters: u64, character: Option<CharacterId>) -> ClientHello {
+        ClientHello { version: PROTOCOL_VERSION, name: player_name(name), characters, character }
+    }
+}
+
+// a Join repeats the hello the cookie was issued for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JoinRequest {
+    pub hello: ClientHello,
+    pub cookie: Cookie,
+    pub room: RoomChoice,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Welcome {
+    pub player: u8,
+    pub token: SessionToken,
+    pub session: SessionId,
+}
+
+// everything both clients need to play the same match, sent with Start
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchStart {
+    pub input_delay: u32,  // frames between a key going down and the frame it is played on
+    pub config: SimConfig, // the characters by id and the seed
+    pub characters: u64,   // Registry::hash of the server's character files
+}
+
+impl MatchStart {
+    // whether this client can play the match, the ids mean nothing if the files behind them differ
+    pub fn check(&self) -> Result<(), String> {
+        if self.characters != registry::global()?.hash() {
+            return Err(format!("the server's character files are not the same as ours in {}", registry::CHARACTERS_DIR));
+        }
+        self.config.check()
+    }
+}
+
+// rooms are numbered by the lobby, each one holds a single match
+pub type RoomId = u32;
+
+// which room a Join asks for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RoomChoice {
+    Any,            // pair with whoever is waiting, or open a room if nobody is
+    Room(RoomId),   // a particular room from the list
+    Create(String), // open a new room with this name
+}
+
+// one line of the lobby's room list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub id: RoomId,
+    pub name: String,
+    pub players: u8,
+    pub spectators: u32,
+    pub started: bool,
+}
+
+// what's left of a name once it has been cut to fit the room list
+pub fn room_name(name: &str) -> String { cut_name(name, MAX_ROOM_NAME) }
+pub fn player_name(name: &str) -> String { cut_name(name, MAX_PLAYER_NAME) }
+
+pub fn cut_name(name: &str, max: usize) -> String {
+    let name = name.trim();
+    let mut end = name.len().min(max);
+    while !name.is_char_boundary(end) { end -= 1; }
+    name[..end].to_string()
+}
+
+// a server's answer to a discovery probe
+// every build has to be able to read it, so fields are never changed or removed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub name: String,
+    pub players: u32, // in a room, waiting or playing
+    pub version: u16, // PROTOCOL_VERSION of the server
+}
+
+impl ServerInfo {
+    pub fn new(name: &str, players: u32) -> ServerInfo {
+        ServerInfo { name: cut_name(name, MAX_SERVER_NAME), players, version: PROTOCOL_VERSION }
+    }
+
+    pub fn compatible(&self) -> bool { self.version == PROTOCOL_VERSION }
+}
+
+impl fmt::Display for ServerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<32}  {} players", self.name, self.players)?;
+        if !self.compatible() {
+            write!(f, "  (version {}, we are {})", self.version, PROTOCOL_VERSION)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn discovery_probe() -> Vec<u8> {
+    serialize(&DISCOVERY_MAGIC).expect("magic always encodes")
+}
+
+pub fn is_discovery_probe(bytes: &[u8]) -> bool {
+    bytes == &discovery_probe()[..]
+}
+
+pub fn discovery_reply(info: &ServerInfo) -> Vec<u8> {
+    serialize(&(DISCOVERY_MAGIC, info)).expect("server info always encodes")
+}
+
+// None for anything that isn't a discovery reply
+pub fn read_discovery_reply(bytes: &[u8]) -> Option<ServerInfo> {
+    match deserialize::<(u32, ServerInfo)>(bytes) {
+        Ok((DISCOVERY_MAGIC, info)) => Some(info),
+        _ => None,
+    }
+}
+
+impl fmt::Display for RoomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.started { "playing" } else { "open" };
+        write!(f, "{:>4}  {:<24}  {}/2 players  {} watching  {}", self.id, self.name, self.players, self.spectators, status)
+    }
+}
+
+// why a player left a match, carried by Forfeit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Quit,     // closed the game
+    TimedOut, // went silent for longer than the disconnect timeout
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisconnectReason::Quit => write!(f, "opponent quit"),
+            DisconnectReason::TimedOut => write!(f, "opponent disconnected"),
+        }
+    }
+}
+
+impl MessageType {
+    // snapshots replace each other, so anything older than the newest one is useless
+    // inputs are keyed by frame and still useful when they arrive late
+    pub fn is_snapshot(&self) -> bool {
+        matches!(self, MessageType::GameState)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PacketHeader {
+    pub magic: u32,
+    pub version: u16,
+    pub msg_type: MessageType,
+    pub session: SessionId,
+    pub frame: u32,
+    pub sequence: u32,
+    pub ack: u32,      // newest sequence received from the peer
+    pub ack_bits: u32, // bit n set = sequence (ack - 1 - n) was received
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    BadMagic,
+    VersionMismatch { ours: u16, theirs: u16 },
+    Duplicate(u32),
+    Stale(u32),
+    WrongSession(SessionId), // spoofed, or meant for whoever had the seat before
+    Malformed(String),
+    Io(String), // the socket itself failed
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::BadMagic => write!(f, "not a Street Code Fighter packet"),
+            ProtocolError::VersionMismatch { ours, theirs } =>
+                write!(f, "protocol version mismatch: we speak version {}, peer speaks version {}. Both sides need the same build", ours, theirs),
+            ProtocolError::Duplicate(seq) => write!(f, "duplicate packet {}", seq),
+            ProtocolError::Stale(seq) => write!(f, "stale packet {}", seq),
+            ProtocolError::WrongSession(session) => write!(f, "packet for session {}, not ours", session),
+            ProtocolError::Malformed(e) => write!(f, "malformed packet: {}", e),
+            ProtocolError::Io(e) => write!(f, "socket error: {}", e),
+        }
+    }
+}
+
+// true if sequence a is newer than b, allowing for wrap around
+pub fn sequence_greater_than(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+// check magic and version without touching any sequencing state
+pub fn read_header(bytes: &[u8]) -> Result<PacketHeader, ProtocolError> {
+    if bytes.len() < 4 || deserialize::<u32>(&bytes[..4]).ok() != Some(PROTOCOL_MAGIC) {
+        return Err(ProtocolError::BadMagic);
+    }
+    // magic and version sit at a fixed offset in every protocol version
+    let version = deserialize::<u16>(&bytes[4..]).map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+    if version != PROTOCOL_VERSION {
+        return Err(ProtocolError::VersionMismatch { ours: PROTOCOL_VERSION, theirs: version });
+    }
+    deserialize::<PacketHeader>(bytes).map_err(|e| ProtocolError::Malformed(e.to_string()))
+}
+
+// unsequenced reply telling a peer which version we speak
+pub fn version_mismatch_reply() -> Vec<u8> {
+    let header = PacketHeader {
+        magic: PROTOCOL_MAGIC,
+        version: PROTOCOL_VERSION,
+        msg_type: MessageType::VersionMismatch,
+        session: 0,
+        frame: 0,
+        sequence: 0,
+        ack: 0,
+        ack_bits: 0,
+    };
+    serialize(&header).expect("header always encodes")
+}
+
+pub fn decode_payload<T: DeserializeOwned>(payload: &[u8]) -> Result<T, ProtocolError> {
+    deserialize::<T>(payload).map_err(|e| ProtocolError::Malformed(e.to_string()))
+}
+
+// ack value sent before anything has been received from the peer
+const NO_ACK: u32 = u32::MAX;
+
+// sequencing and acks for the packets exchanged with one peer
+pub struct Channel {
+    session: SessionId,
+    local_sequence: u32,
+    remote_sequence: u32,
+    received_bits: u32,
+    received_any: bool,
+    peer_ack: u32,
+    peer_ack_bits: u32,
+    newest_snapshot: Option<u32>,
+    last_received: Instant,
+    last_sent: Instant,
+}
+
+impl Default for Channel {
+    fn default() -> Channel { Channel::new() }
+}
+
+impl Channel {
+    pub fn new() -> Channel { Channel::for_session(0) }
+
+    // a channel that only accepts packets carrying this session, and stamps it on everything it sends
+    pub fn for_session(session: SessionId) -> Channel {
+        Channel {
+            session,
+            local_sequence: 0,
+            remote_sequence: 0,
+            received_bits: 0,
+            received_any: false,
+            peer_ack: NO_ACK,
+            peer_ack_bits: 0,
+            newest_snapshot: None,
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+        }
+    }
+
+    pub fn encode<T: Serialize>(&mut self, msg_type: MessageType, frame: u32, payload: &T) -> Vec<u8> {
+        let header = PacketHeader {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            msg_type,
+            session: self.session,
+            frame,
+            sequence: self.local_sequence,
+            ack: if self.received_any { self.remote_sequence } else { NO_ACK },
+            ack_bits: self.received_bits,
+        };
+        self.local_sequence = self.local_sequence.wrapping_add(1);
+        self.last_sent = Instant::now();
+
+        let mut packet = serialize(&header).expect("header always encodes");
+        match serialize(payload) {
+            Ok(body) => packet.extend_from_slice(&body),
+            Err(e) => panic!("Encoding Failed: {:?}", e),
+        }
+        packet
+    }
+
+    // validate a packet, drop duplicates and stale snapshots, and return the header and payload
+    // the payload is read before the sequence counts as received, so one that arrived mangled isn't acked
+    // and the peer sends the real thing again. read_header tells what to read it as
+    pub fn decode<T: DeserializeOwned>(&mut self, bytes: &[u8]) -> Result<(PacketHeader, T), ProtocolError> {
+        let header = read_header(bytes)?;
+        let offset = serialized_size(&header).map_err(|e| ProtocolError::Malformed(e.to_string()))? as usize;
+
+        // before sequencing, so a spoofed packet can't push the real ones out of the window
+        if self.session != 0 && header.session != self.session {
+            return Err(ProtocolError::WrongSession(header.session));
+        }
+        self.check_sequence(header.sequence)?;
+        let payload = decode_payload::<T>(&bytes[offset..])?;
+        self.record_received(header.sequence);
+        if header.msg_type.is_snapshot() {
+            if self.newest_snapshot.is_some_and(|newest| header.frame <= newest) {
+                return Err(ProtocolError::Stale(header.sequence));
+            }
+            self.newest_snapshot = Some(header.frame);
+        }
+
+        if header.ack != NO_ACK && (self.peer_ack == NO_ACK || sequence_greater_than(header.ack, self.peer_ack)) {
+            self.peer_ack = header.ack;
+            self.peer_ack_bits = header.ack_bits;
+        }
+        self.received_any = true;
+        self.last_received = Instant::now();
+
+        Ok((header, payload))
+    }
+
+    // a sequence we already have, or one too old to tell
+    fn check_sequence(&self, sequence: u32) -> Result<(), ProtocolError> {
+        if !self.received_any || sequence_greater_than(sequence, self.remote_sequence) {
+            return Ok(());
+        }
+        if sequence == self.remote_sequence {
+            return Err(ProtocolError::Duplicate(sequence));
+        }
+        let age = self.remote_sequence.wrapping_sub(sequence);
+        if age > 32 {
+            return Err(ProtocolError::Stale(sequence));
+        }
+        if self.received_bits & (1u32 << (age - 1)) != 0 {
+            return Err(ProtocolError::Duplicate(sequence));
+        }
+        Ok(())
+    }
+
+    // only once check_sequence passed
+    fn record_received(&mut self, sequence: u32) {
+        if !self.received_any {
+            self.remote_sequence = sequence;
+            self.received_bits = 0;
+        }
+        else if sequence_greater_than(sequence, self.remote_sequence) {
+            let shift = sequence.wrapping_sub(self.remote_sequence);
+            self.received_bits = if shift > 32 { 0 } else {
+                // the old newest sequence becomes bit (shift - 1)
+                ((self.received_bits as u64) << shift | 1u64 << (shift - 1)) as u32
+            };
+            self.remote_sequence = sequence;
+        }
+        else {
+            self.received_bits |= 1u32 << (self.remote_sequence.wrapping_sub(sequence) - 1);
+        }
+    }
+
+    // has the peer told us it received one of our packets
+    pub fn is_acked(&self, sequence: u32) -> bool {
+        if self.peer_ack == NO_ACK || sequence_greater_than(sequence, self.peer_ack) { return false; }
+        if sequence == self.peer_ack { return true; }
+        let age = self.peer_ack.wrapping_sub(sequence);
+        age <= 32 && self.peer_ack_bits & (1u32 << (age - 1)) != 0
+    }
+
+    pub fn local_sequence(&self) -> u32 { self.local_sequence }
+
+    // (sent, received) share of the last 33 packets each way that were lost, as far as the acks tell
+    pub fn loss(&self) -> (f64, f64) {
+        let sent = if self.peer_ack == NO_ACK { 0.0 } else { window_loss(self.peer_ack, self.peer_ack_bits) };
+        let received = if self.received_any { window_loss(self.remote_sequence, self.received_bits) } else { 0.0 };
+        (sent, received)
+    }
+    pub fn session(&self) -> SessionId { self.session }
+    pub fn set_session(&mut self, session: SessionId) { self.session = session; }
+
+    // time since the last valid packet from the peer
+    pub fn silent_for(&self, now: Instant) -> Duration { now.saturating_duration_since(self.last_received) }
+    pub fn timed_out(&self, now: Instant, timeout: Duration) -> bool { self.silent_for(now) > timeout }
+
+    // nothing sent for a while, the caller should send a Heartbeat
+    pub fn needs_heartbeat(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_sent) >= HEARTBEAT_INTERVAL
+    }
+}
+
+// the newest sequence always arrived, bit n says whether the one n + 1 before it did
+fn window_loss(newest: u32, bits: u32) -> f64 {
+    let older = newest.min(32);
+    let mask = if older == 32 { u32::MAX } else { (1u32 << older) - 1 };
+    let missing = older - (bits & mask).count_ones();
+    missing as f64 / (older + 1) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn testRoundTrip() {
+        let mut sender = Channel::new();
+        let mut receiver = Channel::new();
+        let packet = sender.encode(MessageType::Welcome, 3, &2u8);
+        let (header, payload) = receiver.decode::<u8>(&packet).unwrap();
+
+        assert_eq!(header.msg_type, MessageType::Welcome);
+        assert_eq!(header.frame, 3);
+        assert_eq!(payload, 2);
+    }
+
+    #[test]
+    pub fn testRoomListFitsPacket() {
+        let name = room_name(&"\u{1F94A}".repeat(40));
+        assert!(name.len() <= MAX_ROOM_NAME);
+        assert_eq!(room_name("  dojo "), "dojo");
+
+        let rooms: Vec<RoomInfo> = (0..MAX_LISTED_ROOMS as u32)
+            .map(|id| RoomInfo { id, name: name.clone(), players: 2, spectators: 100, started: true })
+            .collect();
+        let packet = Channel::new().encode(MessageType::Rooms, 0, &rooms);
+        assert!(packet.len() <= MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    pub fn testDiscovery() {
+        let probe = discovery_probe();
+        assert!(is_discovery_probe(&probe));
+        // probes are never mistaken for game traffic and the other way round
+        assert_eq!(read_header(&probe).err(), Some(ProtocolError::BadMagic));
+        assert!(!is_discovery_probe(&Channel::new().encode(MessageType::ListRooms, 0, &())));
+
+        let info = ServerInfo::new("  the basement, second box from the left  ", 3);
+        assert_eq!(info.name, "the basement, second box from th");
+        let reply = discovery_reply(&info);
+        assert!(reply.len() <= MAX_PACKET_SIZE);
+        assert_eq!(read_discovery_reply(&reply), Some(info));
+        assert_eq!(read_discovery_reply(&probe), None);
+
+        // a server on another version still answers in a way we can read
+        let newer = ServerInfo { version: PROTOCOL_VERSION + 1, ..ServerInfo::new("lan", 0) };
+        assert!(!read_discovery_reply(&discovery_reply(&newer)).unwrap().compatible());
+    }
+
+    #[test]
+    pub fn testDropDuplicate() {
+        let mut sender = Channel::new();
+        let mut receiver = Channel::new();
+        let first = sender.encode(MessageType::Input, 0, &0u8);
+        assert!(!receiver.is_acked(0));
+        let second = sender.encode(MessageType::Input, 1, &0u8);
+
+        assert!(receiver.decode::<u8>(&second).is_ok());
+        assert!(receiver.decode::<u8>(&first).is_ok()); // late but new
+        assert_eq!(receiver.decode::<u8>(&first).err(), Some(ProtocolError::Duplicate(0)));
+        assert_eq!(receiver.decode::<u8>(&second).err(), Some(ProtocolError::Duplicate(1)));
+    }
+
+    #[test]
+    pub fn testCorruptPayload() {
+        let mut sender = Channel::new();
+        let mut receiver = Channel::new();
+        receiver.decode::<u8>(&sender.encode(MessageType::Input, 0, &0u8)).unwrap();
+        let input = sender.encode(MessageType::Input, 1, &7u32);
+
+        // the header made it, the end of the input didn't
+        let mangled = &input[..input.len() - 1];
+        assert!(matches!(receiver.decode::<u32>(mangled), Err(ProtocolError::Malformed(_))));
+        // so it isn't acked and the sender knows to send it again
+        sender.decode::<u8>(&receiver.encode(MessageType::Input, 0, &0u8)).unwrap();
+        assert!(sender.is_acked(0));
+        assert!(!sender.is_acked(1));
+        // and the real one still gets through
+        assert_eq!(receiver.decode::<u32>(&input).unwrap().1, 7);
+    }
+
+    #[test]
+    pub fn testDropStaleSnapshot() {
+        let mut sender = Channel::new();
+        let mut receiver = Channel::new();
+        let old = sender.encode(MessageType::GameState, 10, &0u8);
+        let new = sender.encode(MessageType::GameState, 11, &0u8);
+
+        assert!(receiver.decode::<u8>(&new).is_ok());
+        assert_eq!(receiver.decode::<u8>(&old).err(), Some(ProtocolError::Stale(0)));
+    }
+
+    #[test]
+    pub fn testVersionMismatch() {
+        let mut packet = Channel::new().encode(MessageType::Join, 0, &0u8);
+        packet[4..6].copy_from_slice(&serialize(&(PROTOCOL_VERSION + 1)).unwrap());
+
+        assert_eq!(read_header(&packet).err(),
+            Some(ProtocolError::VersionMismatch { ours: PROTOCOL_VERSION, theirs: PROTOCOL_VERSION + 1 }));
+        assert_eq!(read_header(&[1, 2, 3]).err(), Some(ProtocolError::BadMagic));
+    }
+
+    #[test]
+    pub fn testSession() {
+        let mut seat = Channel::for_session(7);
+        let mut player = Channel::new();
+        let mut spoofer = Channel::new();
+
+        // the Welcome is readable before the player knows their session
+        let (header, _) = player.decode::<()>(&seat.encode(MessageType::Welcome, 0, &())).unwrap();
+        assert_eq!(header.session, 7);
+        player.set_session(7);
+
+        let forged = spoofer.encode(MessageType::Input, 100, &0u8);
+        assert_eq!(seat.decode::<u8>(&forged).err(), Some(ProtocolError::WrongSession(0)));
+        // and it didn't use up the sequence number the real packet has
+        assert!(seat.decode::<u8>(&player.encode(MessageType::Input, 0, &0u8)).is_ok());
+
+        let name = player_name("  a name far too long for anyone's health bar ");
+        assert_eq!(name, "a name far too l");
+        assert_eq!(ClientHello::new("\u{1F94A}".repeat(5).as_str(), 0, None).name.len(), 16);
+    }
+
+    #[test]
+    pub fn testAcks() {
+        let mut a = Channel::new();
+        let mut b = Channel::new();
+        let p0 = a.encode(MessageType::Input, 0, &0u8);
+        let _lost = a.encode(MessageType::Input, 1, &0u8);
+        let p2 = a.encode(MessageType::Input, 2, &0u8);
+        b.decode::<u8>(&p0).unwrap();
+        b.decode::<u8>(&p2).unwrap();
+
+        let reply = b.encode(MessageType::Input, 0, &0u8);
+        a.decode::<u8>(&reply).unwrap();
+        assert!(a.is_acked(0));
+        assert!(!a.is_acked(1));
+        assert!(a.is_acked(2));
+        assert_eq!(a.loss().0, 1.0 / 3.0);
+        assert_eq!(b.loss().1, 1.0 / 3.0);
+    }
+
+    #[test]
+    pub fn testTimeout() {
+        let mut a = Channel::new();
+        let mut b = Channel::new();
+        let timeout = Duration::from_secs(5);
+        let later = Instant::now() + Duration::from_secs(6);
+
+        assert!(a.timed_out(later, timeout));
+        assert!(a.needs_heartbeat(later));
+
+        a.decode::<()>(&b.encode(MessageType::Heartbeat, 0, &())).unwrap();
+        assert!(!a.timed_out(Instant::now(), timeout));
+        assert!(!b.needs_heartbeat(Instant::now()));
+    }
+
+    #[test]
+    pub fn testMatchStartCharacters() {
+        let ours = registry::global().unwrap().hash();
+        let start = MatchStart { input_delay: 2, config: SimConfig::default(), characters: ours };
+        assert!(start.check().is_ok());
+        assert!(MatchStart { characters: ours ^ 1, ..start }.check().unwrap_err().starts_with("the server's character files"));
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use serde_derive::{Serialize, Deserialize};
+
+use crate::characters::characterAbstract::{CharacterSnapshot, Fighter};
+use crate::physics::collisions::BVHierarchy;
+use crate::physics::hazard::{Hazard, HazardSnapshot};
+use crate::physics::particle::Particle;
+use crate::simulation::MatchRng;
+use crate::networking::checksum::{StateDigest, CHECKSUM_INTERVAL};
+
+// how far a peer may simulate past the last confirmed remote input before it stalls
+pub const MAX_ROLLBACK_FRAMES: u32 = 8;
+
+// everything needed to rewind the match to the start of a frame
+// also sent whole to resync a player that rejoined
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FrameSnapshot {
+    pub frame: u32,
+    pub p1: CharacterSnapshot,
+    pub p2: CharacterSnapshot,
+    pub hazard: HazardSnapshot,
+    pub rng: MatchRng,
+    pub stage: Vec<Particle>,
+}
+
+impl FrameSnapshot {
+    pub fn save(frame: u32,
+                p1: &Fighter,
+                p2: &Fighter,
+                hazard: &Hazard,
+                rng: &MatchRng,
+                bvh: &BVHierarchy,
+                ) -> FrameSnapshot{
+        FrameSnapshot {
+            frame,
+            p1:     p1.char_state.snapshot(),
+            p2:     p2.char_state.snapshot(),
+            hazard: hazard.snapshot(),
+            rng:    rng.clone(),
+            stage:  bvh.snapshot(),
+        }
+    }
+
+    pub fn load(&self,
+                p1: &mut Fighter,
+                p2: &mut Fighter,
+                hazard: &mut Hazard,
+                rng: &mut MatchRng,
+                bvh: &BVHierarchy,
+                ){
+        p1.char_state.restore(&self.p1);
+        p2.char_state.restore(&self.p2);
+        hazard.restore(&self.hazard);
+        *rng = self.rng.clone();
+        bvh.restore(&self.stage);
+    }
+}
+
+// converted input sets (see inputHandler::convert_input) keyed by frame number
+#[derive(Default)]
+pub struct InputHistory {
+    inputs: HashMap<u32, HashSet<u8>>,
+    latest: Option<u32>,
+}
+
+impl InputHistory {
+    pub fn new() -> InputHistory { InputHistory::default() }
+
+    // returns false if the frame was already recorded
+    pub fn insert(&mut self, frame: u32, inputs: HashSet<u8>) -> bool {
+        if self.inputs.contains_key(&frame) { return false; }
+        self.inputs.insert(frame, inputs);
+        if self.latest.is_none_or(|latest| frame > latest) {
+            self.latest = Some(frame);
+        }
+        true
+    }
+
+    pub fn get(&self, frame: u32) -> Option<&HashSet<u8>> { self.inputs.get(&frame) }
+    pub fn contains(&self, frame: u32) -> bool { self.inputs.contains_key(&frame) }
+    pub fn latest(&self) -> Option<u32> { self.latest }
+
+    // most recent inputs recorded at or before a frame
+    pub fn latest_before(&self, frame: u32) -> Option<&HashSet<u8>> {
+        self.inputs.iter()
+            .filter(|(f, _)| **f <= frame)
+            .max_by_key(|(f, _)| **f)
+            .map(|(_, inputs)| inputs)
+    }
+
+    pub fn discard_before(&mut self, frame: u32) {
+        self.inputs.retain(|f, _| *f >= frame);
+    }
+}
+
+// GGPO style rollback bookkeeping for one peer
+// the game loop owns the fighters and steps them, the session decides which inputs
+// to use for each frame and when the loop has to rewind and re-simulate
+pub struct RollbackSession {
+    pub local_player: u8,
+    local_inputs: InputHistory,
+    remote_inputs: InputHistory,
+    predicted: HashMap<u32, HashSet<u8>>,
+    snapshots: VecDeque<FrameSnapshot>,
+    rollback_to: Option<u32>,
+    next_remote: u32, // first frame we have not received a remote input for
+    confirmed: Vec<StateDigest>, // digests of confirmed frames the game loop hasn't sent yet
+    final_inputs: Vec<(u32, HashSet<u8>, HashSet<u8>)>, // the inputs those frames were played with, for replays
+    input_delay: u32, // frames between a key going down and the frame it is played on
+    next_local: u32,  // first frame we have no local input for
+}
+
+impl RollbackSession {
+    pub fn new(local_player: u8) -> RollbackSession {
+        RollbackSession {
+            local_player,
+            local_inputs: InputHistory::new(),
+            remote_inputs: InputHistory::new(),
+            predicted: HashMap::new(),
+            snapshots: VecDeque::new(),
+            rollback_to: None,
+            next_remote: 0,
+            confirmed: Vec::new(),
+            final_inputs: Vec::new(),
+            input_delay: 0,
+            next_local: 0,
+        }
+    }
+
+    pub fn set_input_delay(&mut self, input_delay: u32) { self.input_delay = input_delay; }
+    pub fn input_delay(&self) -> u32 { self.input_delay }
+
+    // what was pressed while `frame` was on screen, played input_delay frames later
+    // returns every frame that got an input and needs sending, the first call after a start or a resync
+    // also fills the frames the delay skips over with nothing pressed
+    pub fn add_delayed_input(&mut self, frame: u32, inputs: HashSet<u8>) -> Vec<(u32, HashSet<u8>)> {
+        let target = frame + self.input_delay;
+        let mut added = Vec::new();
+        while self.next_local < target {
+            self.local_inputs.insert(self.next_local, HashSet::new());
+            added.push((self.next_local, HashSet::new()));
+            self.next_local += 1;
+        }
+        if target == self.next_local {
+            self.local_inputs.insert(target, inputs.clone());
+            added.push((target, inputs));
+            self.next_local += 1;
+        }
+        added
+    }
+
+    pub fn add_local_input(&mut self, frame: u32, inputs: HashSet<u8>) {
+        self.local_inputs.insert(frame, inputs);
+    }
+
+    // record a remote input, if we guessed wrong for that frame schedule a rollback
+    pub fn add_remote_input(&mut self, frame: u32, inputs: HashSet<u8>) {
+        if frame < self.next_remote || self.remote_inputs.contains(frame) { return; }
+        if let Some(guess) = self.predicted.remove(&frame) {
+            if guess != inputs {
+                self.rollback_to = Some(self.rollback_to.map_or(frame, |f| f.min(frame)));
+            }
+        }
+        self.remote_inputs.insert(frame, inputs);
+        while self.remote_inputs.contains(self.next_remote) {
+            self.next_remote += 1;
+        }
+    }
+
+    // inputs for (player 1, player 2), predicting the remote player by repeating their last input
+    pub fn inputs_for(&mut self, frame: u32) -> (HashSet<u8>, HashSet<u8>) {
+        let local = self.local_inputs.get(frame).cloned().unwrap_or_default();
+        let remote = match self.remote_inputs.get(frame) {
+            Some(inputs) => inputs.clone(),
+            None => {
+                let guess = self.remote_inputs.latest_before(frame).cloned().unwrap_or_default();
+                self.predicted.insert(frame, guess.clone());
+                guess
+            }
+        };
+        self.by_player(local, remote)
+    }
+
+    fn by_player(&self, local: HashSet<u8>, remote: HashSet<u8>) -> (HashSet<u8>, HashSet<u8>) {
+        if self.local_player == 1 { (local, remote) } else { (remote, local) }
+    }
+
+    // stall instead of predicting too far ahead of the remote player
+    pub fn can_advance(&self, frame: u32) -> bool {
+        frame < self.next_remote + MAX_ROLLBACK_FRAMES
+    }
+
+    pub fn confirmed_frame(&self) -> u32 { self.next_remote }
+    pub fn latest_remote(&self) -> Option<u32> { self.remote_inputs.latest() }
+
+    // forget everything and carry on from a state the server sent us, with the same input delay
+    pub fn resync(&mut self, frame: u32) {
+        *self = RollbackSession { input_delay: self.input_delay, ..RollbackSession::new(self.local_player) };
+        self.next_remote = frame;
+        self.next_local = frame;
+    }
+
+    // earliest frame that was simulated with a wrong prediction, if it is before `current`
+    pub fn take_rollback(&mut self, current: u32) -> Option<u32> {
+        match self.rollback_to.take() {
+            Some(frame) if frame < current && self.snapshot(frame).is_some() => Some(frame),
+            _ => None,
+        }
+    }
+
+    pub fn save_snapshot(&mut self, snapshot: FrameSnapshot) {
+        // re-simulated frames replace what was saved before
+        while self.snapshots.back().is_some_and(|s| s.frame >= snapshot.frame) {
+            self.snapshots.pop_back();
+        }
+        let frame = snapshot.frame;
+        self.snapshots.push_back(snapshot);
+
+        // frames before the first unconfirmed one can never be rolled back to
+        // nor can anything we are yet to (re)simulate, its inputs are still needed
+        let keep_from = self.next_remote.min(self.rollback_to.unwrap_or(u32::MAX)).min(frame);
+        // they are final now, so this is when they get checksummed
+        while self.snapshots.front().is_some_and(|s| s.frame < keep_from) {
+            if let Some(confirmed) = self.snapshots.pop_front() {
+                if confirmed.frame > 0 && confirmed.frame.is_multiple_of(CHECKSUM_INTERVAL) {
+                    self.confirmed.push(StateDigest::of(&confirmed));
+                }
+                let local = self.local_inputs.get(confirmed.frame).cloned().unwrap_or_default();
+                let remote = self.remote_inputs.get(confirmed.frame).cloned().unwrap_or_default();
+                let (input_1, input_2) = self.by_player(local, remote);
+                self.final_inputs.push((confirmed.frame, input_1, input_2));
+            }
+        }
+        self.local_inputs.discard_before(keep_from);
+        // keep the last confirmed remote input around for predictions
+        self.remote_inputs.discard_before(keep_from.saturating_sub(1));
+        self.predicted.retain(|f, _| *f >= keep_from);
+    }
+
+    // digests of frames that can't be rolled back any more, to send to the server
+    pub fn take_confirmed(&mut self) -> Vec<StateDigest> {
+        std::mem::take(&mut self.confirmed)
+    }
+
+    // (frame, player 1's input, player 2's input) for every frame that became final since the last call, in order
+    // the game loop takes them every frame whether or not it is recording
+    pub fn take_final_inputs(&mut self) -> Vec<(u32, HashSet<u8>, HashSet<u8>)> {
+        std::mem::take(&mut self.final_inputs)
+    }
+
+    pub fn snapshot(&self, frame: u32) -> Option<&FrameSnapshot> {
+        self.snapshots.iter().find(|s| s.frame == frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(k: &[u8]) -> HashSet<u8> { k.iter().cloned().collect() }
+
+    #[test]
+    pub fn testPredictLastInput() {
+        let mut session = RollbackSession::new(1);
+        session.add_local_input(0, keys(&[1]));
+        session.add_remote_input(0, keys(&[2]));
+
+        assert_eq!(session.inputs_for(0), (keys(&[1]), keys(&[2])));
+        // nothing received for frame 1, repeat frame 0
+        assert_eq!(session.inputs_for(1), (keys(&[]), keys(&[2])));
+    }
+
+    #[test]
+    pub fn testRollbackOnMisprediction() {
+        let mut session = RollbackSession::new(2);
+        session.add_remote_input(0, keys(&[]));
+        session.inputs_for(1);
+        session.inputs_for(2);
+
+        // correct guess, no rollback
+        session.add_remote_input(1, keys(&[]));
+        assert_eq!(session.rollback_to, None);
+
+        // wrong guess for frame 2
+        session.add_remote_input(2, keys(&[7]));
+        assert_eq!(session.rollback_to, Some(2));
+        assert_eq!(session.confirmed_frame(), 3);
+    }
+
+    #[test]
+    pub fn testResimulateKeepsInputs() {
+        use crate::characters::characterAbstract::CharacterState;
+
+        let p1 = Fighter::new(CharacterState::new());
+        let p2 = Fighter::new(CharacterState::new());
+        let hazard = Hazard::new();
+        let stage = crate::physics::collisions::build_stage();
+        let mut session = RollbackSession::new(1);
+
+        for frame in 0..5 {
+            session.add_local_input(frame, keys(&[1]));
+            session.save_snapshot(FrameSnapshot::save(frame, &p1, &p2, &hazard, &MatchRng::new(0), &stage));
+            session.inputs_for(frame);
+        }
+        // everything up to frame 4 arrives at once, frame 2 was guessed wrong
+        for frame in 0..5 {
+            session.add_remote_input(frame, keys(if frame == 2 { &[5] } else { &[] }));
+        }
+        // and the remote player is ahead of us
+        session.add_remote_input(5, keys(&[4]));
+        assert_eq!(session.take_rollback(5), Some(2));
+
+        // re-simulating frame 2 still has our input for it, and theirs for later
+        session.save_snapshot(FrameSnapshot::save(2, &p1, &p2, &hazard, &MatchRng::new(0), &stage));
+        assert_eq!(session.inputs_for(2), (keys(&[1]), keys(&[5])));
+        assert_eq!(session.inputs_for(4), (keys(&[1]), keys(&[])));
+        assert_eq!(session.remote_inputs.get(5), Some(&keys(&[4])));
+    }
+
+    #[test]
+    pub fn testResync() {
+        let mut session = RollbackSession::new(1);
+        session.add_remote_input(0, keys(&[3]));
+        session.inputs_for(1);
+        session.resync(40);
+
+        assert_eq!(session.confirmed_frame(), 40);
+        assert_eq!(session.inputs_for(40), (keys(&[]), keys(&[])));
+        assert!(session.can_advance(40));
+        // inputs from before the resync are ignored
+        session.add_remote_input(39, keys(&[3]));
+        assert_eq!(session.confirmed_frame(), 40);
+    }
+
+    #[test]
+    pub fn testInputDelay() {
+        let mut session = RollbackSession::new(1);
+        session.set_input_delay(2);
+
+        // the first two frames are played with nothing pressed, for both players
+        let added = session.add_delayed_input(0, keys(&[1]));
+        assert_eq!(added, vec![(0, keys(&[])), (1, keys(&[])), (2, keys(&[1]))]);
+        assert_eq!(session.add_delayed_input(1, keys(&[2])), vec![(3, keys(&[2]))]);
+        // a frame we were stalled on doesn't get a second input
+        assert!(session.add_delayed_input(1, keys(&[3])).is_empty());
+        session.add_remote_input(2, keys(&[6]));
+        assert_eq!(session.inputs_for(0), (keys(&[]), keys(&[])));
+        assert_eq!(session.inputs_for(2), (keys(&[1]), keys(&[6])));
+        assert_eq!(session.latest_remote(), Some(2));
+
+        session.resync(40);
+        assert_eq!(session.input_delay(), 2);
+        assert_eq!(session.add_delayed_input(40, keys(&[])).len(), 3);
+    }
+
+    #[test]
+    pub fn testSnapshotFitsPacket() {
+        use crate::characters::characterAbstract::CharacterState;
+        use crate::networking::protocol::MAX_PACKET_SIZE;
+
+        let p1 = Fighter::new(CharacterState::new());
+        let p2 = Fighter::new(CharacterState::new());
+        let snapshot = FrameSnapshot::save(0, &p1, &p2, &Hazard::new(), &MatchRng::new(0), &crate::physics::collisions::build_stage());
+
+        // a resync is sent as a single datagram, leave room for the header
+        assert!((bincode::serialized_size(&snapshot).unwrap() as usize) < MAX_PACKET_SIZE - 64);
+    }
+
+    #[test]
+    pub fn testChecksumConfirmedFrames() {
+        use crate::simulation::{SimConfig, Simulation};
+
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+        let mut session = RollbackSession::new(1);
+
+        for frame in 0..CHECKSUM_INTERVAL + 2 {
+            session.add_local_input(frame, keys(&[]));
+            // the remote player is a frame behind
+            if frame > 0 { session.add_remote_input(frame - 1, keys(&[])); }
+            session.save_snapshot(sim.save());
+            let (input_1, input_2) = session.inputs_for(frame);
+            sim.step(&input_1, &input_2);
+        }
+
+        // frame 30 is only final once frame 30's remote input is in
+        let confirmed = session.take_confirmed();
+        assert_eq!(confirmed.iter().map(|d| d.frame).collect::<Vec<u32>>(), vec![CHECKSUM_INTERVAL]);
+        assert!(session.take_confirmed().is_empty());
+    }
+
+    #[test]
+    pub fn testFinalInputs() {
+        let p1 = Fighter::new(crate::characters::characterAbstract::CharacterState::new());
+        let p2 = Fighter::new(crate::characters::characterAbstract::CharacterState::new());
+        let stage = crate::physics::collisions::build_stage();
+        let mut session = RollbackSession::new(2);
+
+        for frame in 0..5 {
+            session.add_local_input(frame, keys(&[1]));
+            session.add_remote_input(frame, keys(&[7]));
+            session.save_snapshot(FrameSnapshot::save(frame, &p1, &p2, &Hazard::new(), &MatchRng::new(0), &stage));
+            session.inputs_for(frame);
+        }
+
+        // frame 4 is the newest snapshot and could still be rewound to, the rest are final and in player order
+        let inputs = session.take_final_inputs();
+        assert_eq!(inputs.iter().map(|(f, _, _)| *f).collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+        assert_eq!((inputs[0].1.clone(), inputs[0].2.clone()), (keys(&[7]), keys(&[1])));
+        assert!(session.take_final_inputs().is_empty());
+    }
+
+    #[test]
+    pub fn testStallWindow() {
+        let session = RollbackSession::new(1);
+        assert!(session.can_advance(MAX_ROLLBACK_FRAMES - 1));
+        assert!(!session.can_advance(MAX_ROLLBACK_FRAMES));
+    }
+}
@@ -0,0 +1,779 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::characters::registry::{self, CharacterId};
+use crate::networking::{chat, checksum, config, rollback, timing, transmit};
+use crate::networking::protocol::{self, Channel, DisconnectReason, MatchStart, RoomId, RoomInfo, SessionId, SessionToken};
+use crate::networking::delta::{DeltaHistory, PackedState};
+use crate::networking::transmit::{MatchMessage, Resync, Transport};
+use crate::simulation::{SimConfig, Simulation};
+
+// who has a seat, handed out when they sit down
+struct Seat {
+    name: String,
+    token: SessionToken, // to rejoin with
+    session: SessionId,  // what their channel checks every packet for
+    character: Option<CharacterId>, // None plays the seat's usual character
+    pinger: timing::Pinger,
+}
+
+// one match hosted by the lobby, it owns its own simulation of it
+// so any number of them can run side by side on the lobby's socket
+pub struct Room {
+    pub id: RoomId,
+    pub name: String,
+    client_addresses: HashMap<SocketAddr, u8>,
+    channels: HashMap<SocketAddr, Channel>,
+    seats: HashMap<u8, Seat>,
+    // watchers get the server's GameState every frame, anything else they send is ignored
+    spectators: HashMap<SocketAddr, Channel>,
+    // what each spectator was sent, so a GameState only carries what changed since one they got
+    deltas: HashMap<SocketAddr, DeltaHistory>,
+    // inputs are relayed to the other player and the room steps its own copy
+    // of the match once it has both players' inputs for a frame
+    sim: Simulation,
+    history_1: rollback::InputHistory,
+    history_2: rollback::InputHistory,
+    // bumped on every resync so inputs sent before it are dropped
+    epoch: u32,
+    // the input delay is picked from the players' round trip times when the match starts,
+    // the characters are the ones the players asked for and the seed is random
+    start: MatchStart,
+    // a player who went silent and when, the match is paused until they rejoin
+    dropped: Option<(u8, Instant)>,
+    // players who haven't had the last Resync yet: the sequence it went out with and when
+    // it is sent again until they ack it, or both sides would wait on each other for good
+    unsynced: HashMap<SocketAddr, (u32, Instant)>,
+    started: bool,
+    // who left and why, the lobby closes the room once this is set
+    result: Option<(u8, DisconnectReason)>,
+    // per player, so one of them can't flood the room
+    chat_limits: HashMap<u8, chat::RateLimiter>,
+    // our digests of recent checksum frames, and checksums from players who got there first
+    digests: Vec<checksum::StateDigest>,
+    early_checksums: Vec<(u8, u32, u64)>,
+}
+
+impl Room {
+    pub fn new(id: RoomId, name: &str) -> Result<Room, String> {
+        Ok(Room {
+            id,
+            name: protocol::room_name(name),
+            client_addresses: HashMap::new(),
+            channels: HashMap::new(),
+            seats: HashMap::new(),
+            spectators: HashMap::new(),
+            deltas: HashMap::new(),
+            // stand-ins until the second player sits down and add_player sets up the match they picked
+            sim: Simulation::new(SimConfig::default())?,
+            history_1: rollback::InputHistory::new(),
+            history_2: rollback::InputHistory::new(),
+            epoch: 0,
+            start: MatchStart { input_delay: 0, config: SimConfig::default(), characters: registry::global()?.hash() },
+            dropped: None,
+            unsynced: HashMap::new(),
+            started: false,
+            result: None,
+            chat_limits: HashMap::new(),
+            digests: Vec::new(),
+            early_checksums: Vec::new(),
+        })
+    }
+
+    // waiting for a second player
+    pub fn is_open(&self) -> bool { !self.started && self.result.is_none() && self.client_addresses.len() < 2 }
+    pub fn is_started(&self) -> bool { self.started }
+    pub fn result(&self) -> Option<(u8, DisconnectReason)> { self.result }
+
+    // nobody left to play or watch
+    pub fn is_empty(&self) -> bool { self.client_addresses.is_empty() && self.spectators.is_empty() }
+
+    // should packets from this address come here
+    pub fn owns(&self, address: &SocketAddr) -> bool {
+        self.channels.contains_key(address) || self.spectators.contains_key(address)
+    }
+
+    pub fn has_token(&self, token: SessionToken) -> bool { self.seats.values().any(|s| s.token == token) }
+    pub fn has_player(&self, address: &SocketAddr) -> bool { self.client_addresses.contains_key(address) }
+
+    // chat is for the lobby and between rounds, not while both players are still standing
+    pub fn is_fighting(&self) -> bool {
+        self.started && self.dropped.is_none()
+            && self.sim.knocked_out().is_none()
+    }
+
+    pub fn info(&self) -> RoomInfo {
+        RoomInfo {
+            id: self.id,
+            name: self.name.clone(),
+            players: self.client_addresses.len() as u8,
+            spectators: self.spectators.len() as u32,
+            started: self.started,
+        }
+    }
+
+    // seat a player whose Join brought back a good cookie, the channel is the one their Join arrived on
+    // and rtt is how long the cookie took to come back
+    // the match starts as soon as the second one sits down
+    pub fn add_player(&mut self, socket: &dyn Transport, address: SocketAddr, mut channel: Channel, name: &str, character: Option<CharacterId>, rtt: Duration){
+        let player_number = match (1..=2).find(|n| !self.client_addresses.values().any(|p| p == n)) {
+            Some(n) => n,
+            None => return, // the lobby only sends players to open rooms
+        };
+        let name = protocol::player_name(name);
+        println!("Room {}: {} ({:?}) is player {}", self.id, name, address, player_number);
+        let mut pinger = timing::Pinger::new();
+        pinger.sample(rtt);
+        let seat = Seat { name, token: rand::random(), session: rand::random::<SessionId>().max(1), character, pinger };
+        channel.set_session(seat.session);
+        self.seats.insert(player_number, seat);
+        self.client_addresses.insert(address, player_number);
+        self.channels.insert(address, channel);
+        self.welcome_again(socket, address);
+
+        if self.client_addresses.len() == 2 {
+            let seats = &self.seats;
+            let rtt = |player| seats.get(&player).and_then(|s| s.pinger.rtt()).unwrap_or_default();
+            // whoever the players picked, and Python against Java for anyone who didn't
+            let usual = SimConfig::default();
+            let character = |player, usual| seats.get(&player).and_then(|s| s.character).unwrap_or(usual);
+            let config = SimConfig { p1: character(1, usual.p1), p2: character(2, usual.p2), seed: rand::random() };
+            self.sim = match Simulation::new(config) {
+                Ok(sim) => sim,
+                Err(e) => {
+                    println!("Room {}: can't start the match: {}", self.id, e);
+                    return;
+                },
+            };
+            self.start = MatchStart { input_delay: timing::input_delay(rtt(1), rtt(2)), config, characters: self.start.characters };
+            println!("Room {}: two players found! {:?} and {:?} away, input delay {} frames, seed {:x}",
+                     self.id, rtt(1), rtt(2), self.start.input_delay, config.seed);
+            config::start_match(socket, &self.client_addresses, &mut self.channels, &self.start);
+            self.started = true;
+        }
+    }
+
+    // tell a player their seat, again if they asked again because the first Welcome was lost
+    // a Start they missed on the way is repeated too
+    pub fn welcome_again(&mut self, socket: &dyn Transport, address: SocketAddr){
+        let player = match self.client_addresses.get(&address) {
+            Some(player) => *player,
+            None => return,
+        };
+        let (seat, channel) = match (self.seats.get(&player), self.channels.get_mut(&address)) {
+            (Some(seat), Some(channel)) => (seat, channel),
+            _ => return,
+        };
+        let welcome = protocol::Welcome { player, token: seat.token, session: seat.session };
+        let _ = socket.send_to(&channel.encode(protocol::MessageType::Welcome, 0, &welcome), &address);
+        if self.started {
+            let _ = socket.send_to(&channel.encode(protocol::MessageType::Start, 0, &self.start), &address);
+        }
+    }
+
+    // someone asked to watch, they get a snapshot once the match is running
+    pub fn add_spectator(&mut self, socket: &dyn Transport, address: SocketAddr, channel: Channel){
+        self.spectators.insert(address, channel);
+        self.deltas.remove(&address);
+        self.send_snapshot(socket, address);
+    }
+
+    fn send_snapshot(&mut self, socket: &dyn Transport, address: SocketAddr){
+        if !self.started { return; }
+        if let Some(channel) = self.spectators.get_mut(&address) {
+            println!("Room {}: spectator {:?} joined at frame {}", self.id, address, self.sim.frame());
+            let snapshot = self.sim.save();
+            transmit::send_snapshot(socket, &address, channel, &Resync{ epoch: self.epoch, start: self.start, snapshot });
+        }
+    }
+
+    // point a player's slot at the address they rejoined from
+    // the lobby found this room by the token, so it is one of ours
+    pub fn rejoin(&mut self, address: SocketAddr, mut channel: Channel, token: SessionToken){
+        let (player, seat) = match self.seats.iter().find(|(_, s)| s.token == token){
+            Some((player, seat)) => (*player, seat),
+            None => return,
+        };
+        channel.set_session(seat.session);
+        println!("Room {}: player {} ({}) rejoined from {:?}", self.id, player, seat.name, address);
+
+        let old: Vec<SocketAddr> = self.client_addresses.iter()
+            .filter(|(a, p)| **p == player && **a != address)
+            .map(|(a, _)| *a)
+            .collect();
+        for a in old.iter(){
+            self.client_addresses.remove(a);
+            self.channels.remove(a);
+        }
+        self.client_addresses.insert(address, player);
+        self.channels.insert(address, channel);
+    }
+
+    // a datagram from one of our players or spectators
+    pub fn handle_packet(&mut self, socket: &dyn Transport, address: SocketAddr, bytes: &[u8]){
+        let message = transmit::parse_client_message(bytes, &address, &self.client_addresses, &mut self.channels, &mut self.spectators);
+        match message{
+            Some(MatchMessage::Input(input)) => {
+                let player = self.client_addresses[&address];
+                if self.started && self.dropped.is_none() && input.epoch == self.epoch {
+                    // playing on from the resync, so they have it
+                    self.unsynced.remove(&address);
+                    transmit::relay_input(socket, &self.client_addresses, &mut self.channels, player, &input);
+                    // repeats of frames we already have or already simulated are dropped
+                    for (frame, keys) in input.frames() {
+                        if frame < self.sim.frame() { continue; }
+                        match player{
+                            1 => { self.history_1.insert(frame, keys.clone()); },
+                            2 => { self.history_2.insert(frame, keys.clone()); },
+                            _ => {},
+                        }
+                    }
+                }
+            },
+            Some(MatchMessage::Quit) => {
+                if self.spectators.remove(&address).is_some() {
+                    println!("Room {}: spectator {:?} left", self.id, address);
+                } else if !self.started {
+                    // left before the match started, the seat is free again
+                    println!("Room {}: {:?} left before the match started", self.id, address);
+                    self.remove_player(&address);
+                } else {
+                    let player = self.client_addresses[&address];
+                    self.end_match(socket, player, DisconnectReason::Quit);
+                }
+            },
+            // joining mid-match, start them off with everything
+            Some(MatchMessage::Spectate) => self.send_snapshot(socket, address),
+            Some(MatchMessage::Chat(text)) => self.chat(socket, address, &text),
+            Some(MatchMessage::Ping(id)) => {
+                if let Some(channel) = self.channels.get_mut(&address) {
+                    transmit::pong_player(socket, &address, channel, id);
+                }
+            },
+            Some(MatchMessage::Pong(id)) => {
+                let player = self.client_addresses[&address];
+                if let Some(seat) = self.seats.get_mut(&player) {
+                    seat.pinger.pong(id, Instant::now());
+                }
+            },
+            Some(MatchMessage::Checksum{ frame, epoch, hash }) => {
+                let player = self.client_addresses[&address];
+                if self.started && epoch == self.epoch {
+                    self.check(socket, player, frame, hash);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // pass a player's message on to everyone in the room, or tell them why not
+    fn chat(&mut self, socket: &dyn Transport, address: SocketAddr, text: &str){
+        let player = match self.client_addresses.get(&address) {
+            Some(player) => *player,
+            None => return,
+        };
+        let text = match chat::clean(text) {
+            Some(text) => text,
+            None => return,
+        };
+
+        let refusal = if self.is_fighting() {
+            Some("chat opens again after the round")
+        } else if !self.chat_limits.entry(player).or_default().allow(Instant::now()) {
+            Some("slow down")
+        } else {
+            None
+        };
+        if let Some(refusal) = refusal {
+            if let Some(channel) = self.channels.get_mut(&address) {
+                transmit::send_chat_line(socket, &address, channel, &chat::ChatLine::notice(refusal));
+            }
+            return;
+        }
+
+        let line = chat::ChatLine::new(&format!("P{}", player), &text);
+        for (address, channel) in self.channels.iter_mut().chain(self.spectators.iter_mut()){
+            transmit::send_chat_line(socket, address, channel, &line);
+        }
+    }
+
+    fn remove_player(&mut self, address: &SocketAddr){
+        if let Some(player) = self.client_addresses.remove(address) {
+            self.seats.remove(&player);
+        }
+        self.channels.remove(address);
+    }
+
+    // everything that doesn't wait on a packet: timeouts, heartbeats and stepping the match
+    pub fn tick(&mut self, socket: &dyn Transport, timeout: Duration, grace: Duration, now: Instant){
+        if self.result.is_some() { return; }
+
+        if !self.started {
+            // players waiting for an opponent heartbeat too, a dead one gives up their seat
+            let silent: Vec<SocketAddr> = self.channels.iter()
+                .filter(|(_, channel)| channel.timed_out(now, timeout))
+                .map(|(address, _)| *address)
+                .collect();
+            for address in silent.iter(){
+                println!("Room {}: {:?} went silent before the match started", self.id, address);
+                self.remove_player(address);
+            }
+        }
+        else {
+            self.check_dropped(socket, timeout, grace, now);
+            self.resend_resyncs(socket, now);
+        }
+        self.spectators.retain(|_, channel| !channel.timed_out(now, timeout));
+        let spectators = &self.spectators;
+        self.deltas.retain(|address, _| spectators.contains_key(address));
+        // keep measuring how far away the players are
+        for (address, player) in self.client_addresses.iter() {
+            if let (Some(seat), Some(channel)) = (self.seats.get_mut(player), self.channels.get_mut(address)) {
+                if seat.pinger.due(now) {
+                    transmit::ping_player(socket, address, channel, seat.pinger.ping(now));
+                }
+            }
+        }
+        transmit::send_heartbeats(socket, &mut self.channels, now);
+        transmit::send_heartbeats(socket, &mut self.spectators, now);
+
+        self.step(socket);
+    }
+
+    fn check_dropped(&mut self, socket: &dyn Transport, timeout: Duration, grace: Duration, now: Instant){
+        match self.dropped{
+            None => {
+                let channels = &self.channels;
+                let silent = self.client_addresses.iter()
+                    .find(|(address, _)| channels.get(address).is_none_or(|c| c.timed_out(now, timeout)))
+                    .map(|(_, player)| *player);
+                if let Some(player) = silent {
+                    println!("Room {}: player {} dropped, pausing for up to {:?}", self.id, player, grace);
+                    self.dropped = Some((player, now));
+                    transmit::send_pause(socket, &self.client_addresses, &mut self.channels, player);
+                }
+            },
+            Some((player, since)) => {
+                let channels = &self.channels;
+                let back = self.client_addresses.iter()
+                    .filter(|(_, p)| **p == player)
+                    .any(|(address, _)| channels.get(address).is_some_and(|c| !c.timed_out(now, timeout)));
+                if back {
+                    println!("Room {}: player {} is back, resyncing at frame {}", self.id, player, self.sim.frame());
+                    self.dropped = None;
+                    self.resync(socket);
+                }
+                else if now.duration_since(since) > grace {
+                    self.end_match(socket, player, DisconnectReason::TimedOut);
+                }
+            },
+        }
+    }
+
+    // everyone restarts from the room's copy of the match
+    fn resync(&mut self, socket: &dyn Transport){
+        self.epoch += 1;
+        self.history_1 = rollback::InputHistory::new();
+        self.history_2 = rollback::InputHistory::new();
+        self.digests.clear();
+        self.early_checksums.clear();
+        self.unsynced.clear();
+        let addresses: Vec<SocketAddr> = self.client_addresses.keys().cloned().collect();
+        for address in addresses {
+            self.send_resync(socket, address, Instant::now());
+        }
+    }
+
+    fn send_resync(&mut self, socket: &dyn Transport, address: SocketAddr, now: Instant){
+        if let Some(channel) = self.channels.get_mut(&address) {
+            let sequence = transmit::send_snapshot(socket, &address, channel, &Resync{ epoch: self.epoch, start: self.start, snapshot: self.sim.save() });
+            self.unsynced.insert(address, (sequence, now));
+        }
+    }
+
+    // a Resync that wasn't acked in time is sent again
+    // the match can't have moved on without it, so it is still the same state
+    fn resend_resyncs(&mut self, socket: &dyn Transport, now: Instant){
+        if self.dropped.is_some() { return; }
+        let channels = &self.channels;
+        self.unsynced.retain(|address, (sequence, _)| channels.get(address).is_some_and(|c| !c.is_acked(*sequence)));
+        let due: Vec<SocketAddr> = self.unsynced.iter()
+            .filter(|(_, (_, sent))| now.saturating_duration_since(*sent) >= protocol::RESYNC_INTERVAL)
+            .map(|(address, _)| *address)
+            .collect();
+        for address in due {
+            println!("Room {}: {:?} hasn't acked the resync, sending it again", self.id, address);
+            self.send_resync(socket, address, now);
+        }
+    }
+
+    // compare a player's checksum with our own copy of that frame
+    // a player ahead of us is checked once we get there
+    fn check(&mut self, socket: &dyn Transport, player: u8, frame: u32, hash: u64){
+        let digest = match self.digests.iter().find(|d| d.frame == frame) {
+            Some(digest) => digest,
+            None => {
+                if frame > self.sim.frame() && self.early_checksums.len() < 2 * checksum::CHECKSUM_HISTORY {
+                    self.early_checksums.push((player, frame, hash));
+                }
+                return;
+            },
+        };
+        if digest.hash() == hash { return; }
+
+        println!("Room {}: player {} desynced at frame {}, resyncing", self.id, player, frame);
+        let digest = digest.clone();
+        for (address, p) in self.client_addresses.iter() {
+            if *p != player { continue; }
+            if let Some(channel) = self.channels.get_mut(address) {
+                transmit::send_desync(socket, address, channel, &digest);
+            }
+        }
+        self.resync(socket);
+    }
+
+    // run every frame both players have sent inputs for
+    fn step(&mut self, socket: &dyn Transport){
+        if !self.started || self.dropped.is_some() || self.result.is_some() { return; }
+        loop{
+            let frame = self.sim.frame();
+            let (input_1, input_2) = match (self.history_1.get(frame), self.history_2.get(frame)){
+                (Some(input_1), Some(input_2)) => (input_1.clone(), input_2.clone()),
+                _ => return,
+            };
+
+            self.sim.step(&input_1, &input_2);
+
+            self.history_1.discard_before(frame);
+            self.history_2.discard_before(frame);
+
+            if self.sim.frame().is_multiple_of(checksum::CHECKSUM_INTERVAL) {
+                let snapshot = self.sim.save();
+                self.digests.push(checksum::StateDigest::of(&snapshot));
+                if self.digests.len() > checksum::CHECKSUM_HISTORY { self.digests.remove(0); }
+
+                let frame = self.sim.frame();
+                let (due, later): (Vec<_>, Vec<_>) = self.early_checksums.iter().partition(|(_, f, _)| *f <= frame);
+                self.early_checksums = later;
+                // a resync throws away the inputs, which ends this loop
+                for (player, f, hash) in due {
+                    self.check(socket, player, f, hash);
+                }
+            }
+
+            if !self.spectators.is_empty() {
+                let state = PackedState::new(&self.sim.fighter1, &self.sim.fighter2, &self.sim.hazard);
+                transmit::send_game_state(socket, &mut self.spectators, &mut self.deltas, self.sim.frame(), &state);
+            }
+        }
+    }
+
+    // let everyone still connected know the match is over
+    fn end_match(&mut self, socket: &dyn Transport, leaver: u8, reason: DisconnectReason){
+        for (address, player) in self.client_addresses.iter(){
+            if *player == leaver { continue; }
+            if let Some(channel) = self.channels.get_mut(address){
+                transmit::send_forfeit(socket, address, channel, reason);
+            }
+        }
+        for (address, channel) in self.spectators.iter_mut(){
+            transmit::send_forfeit(socket, address, channel, reason);
+        }
+        self.result = Some((leaver, reason));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+    use crate::simulation::MatchRng;
+
+    fn peer() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        (socket, address)
+    }
+
+    // what the player at this address talks to the room with once they have their Welcome
+    fn seated(room: &Room, address: SocketAddr) -> Channel {
+        Channel::for_session(room.channels[&address].session())
+    }
+
+    #[test]
+    pub fn testRoomsAreIndependent() {
+        let (server, _) = peer();
+        let (_a, a) = peer();
+        let (_b, b) = peer();
+        let (_c, c) = peer();
+
+        let mut first = Room::new(1, "first").unwrap();
+        let mut second = Room::new(2, "second").unwrap();
+        first.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(0));
+        second.add_player(&server, c, Channel::new(), "c", None, Duration::from_millis(0));
+        assert!(first.is_open() && second.is_open());
+
+        first.add_player(&server, b, Channel::new(), "b", None, Duration::from_millis(0));
+        assert!(first.is_started() && !first.is_open());
+        assert!(!second.is_started());
+        assert!(first.owns(&b) && !second.owns(&b));
+        assert_eq!(first.info().players, 2);
+        assert_eq!(second.info().players, 1);
+    }
+
+    // the next chat line waiting on a peer's socket
+    fn next_chat(socket: &UdpSocket, channel: &mut Channel) -> chat::ChatLine {
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        loop {
+            let n = socket.recv(&mut buffer).unwrap();
+            if protocol::read_header(&buffer[..n]).map(|header| header.msg_type) == Ok(protocol::MessageType::Chat) {
+                return channel.decode::<chat::ChatLine>(&buffer[..n]).unwrap().1;
+            }
+            let _ = channel.decode::<()>(&buffer[..n]);
+        }
+    }
+
+    #[test]
+    pub fn testSpectatorToldCharacters() {
+        let (server, _) = peer();
+        let (_a, a) = peer();
+        let (_b, b) = peer();
+        let (c_socket, c) = peer();
+        let mut room = Room::new(1, "mirror").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", Some(CharacterId(2)), Duration::from_millis(0));
+        room.add_player(&server, b, Channel::new(), "b", Some(CharacterId(2)), Duration::from_millis(0));
+        assert_eq!((room.sim.fighter1.character, room.sim.fighter2.character), (CharacterId(2), CharacterId(2)));
+
+        room.add_spectator(&server, c, Channel::new());
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        let n = c_socket.recv(&mut buffer).unwrap();
+        let (header, resync) = Channel::new().decode::<Resync>(&buffer[..n]).unwrap();
+        assert_eq!(header.msg_type, protocol::MessageType::Resync);
+        assert_eq!(resync.start, room.start);
+        assert_eq!((resync.start.config.p1, resync.start.config.p2), (CharacterId(2), CharacterId(2)));
+    }
+
+    #[test]
+    pub fn testChatOnlyBetweenRounds() {
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let (_b, b) = peer();
+        let mut a_server = Channel::new();
+        let mut room = Room::new(1, "chatty").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(0));
+        let mut a_channel = seated(&room, a);
+
+        // waiting for an opponent, we hear ourselves
+        room.handle_packet(&server, a, &a_channel.encode(protocol::MessageType::Chat, 0, &"anyone?\n"));
+        assert_eq!(next_chat(&a_socket, &mut a_server), chat::ChatLine::new("P1", "anyone?"));
+
+        room.add_player(&server, b, Channel::new(), "b", None, Duration::from_millis(0));
+        assert!(room.is_fighting());
+        room.handle_packet(&server, a, &a_channel.encode(protocol::MessageType::Chat, 0, &"gl"));
+        assert_eq!(next_chat(&a_socket, &mut a_server).from, chat::SERVER_NAME);
+
+        // knocked out, the round is over
+        room.sim.fighter2.char_state.particle.borrow_mut().health = 0;
+        for _ in 0..chat::CHAT_BURST - 1 {
+            room.handle_packet(&server, a, &a_channel.encode(protocol::MessageType::Chat, 0, &"gg"));
+            assert_eq!(next_chat(&a_socket, &mut a_server), chat::ChatLine::new("P1", "gg"));
+        }
+        room.handle_packet(&server, a, &a_channel.encode(protocol::MessageType::Chat, 0, &"gg"));
+        assert_eq!(next_chat(&a_socket, &mut a_server), chat::ChatLine::notice("slow down"));
+    }
+
+    #[test]
+    pub fn testDesyncResyncs() {
+        use std::collections::HashSet;
+        use crate::networking::protocol::MessageType;
+
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let (_b, b) = peer();
+        let mut room = Room::new(1, "drift").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(0));
+        room.add_player(&server, b, Channel::new(), "b", None, Duration::from_millis(0));
+        let mut a_channel = seated(&room, a);
+        let mut b_channel = seated(&room, b);
+
+        for frame in 0..checksum::CHECKSUM_INTERVAL {
+            let input = transmit::InputStruct::new(frame, 0, HashSet::new());
+            room.handle_packet(&server, a, &a_channel.encode(MessageType::Input, frame, &input));
+            room.handle_packet(&server, b, &b_channel.encode(MessageType::Input, frame, &input));
+            room.step(&server);
+        }
+        let ours = room.digests[0].hash();
+
+        room.handle_packet(&server, b, &b_channel.encode(MessageType::Checksum, checksum::CHECKSUM_INTERVAL, &(0u32, ours)));
+        assert_eq!(room.epoch, 0);
+        room.handle_packet(&server, a, &a_channel.encode(MessageType::Checksum, checksum::CHECKSUM_INTERVAL, &(0u32, ours ^ 1)));
+        assert_eq!(room.epoch, 1);
+
+        // a hears what the server had, then gets the match back
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        let mut from_server = Channel::new();
+        let mut seen = Vec::new();
+        while !seen.contains(&MessageType::Resync) {
+            let n = a_socket.recv(&mut buffer).unwrap();
+            if let Ok((header, _)) = from_server.decode::<()>(&buffer[..n]) { seen.push(header.msg_type); }
+        }
+        assert!(seen.contains(&MessageType::Desync));
+    }
+
+    #[test]
+    pub fn testSilentWaitingPlayerLosesSeat() {
+        let (server, _) = peer();
+        let (_a, a) = peer();
+        let mut room = Room::new(1, "lonely").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(0));
+
+        let later = Instant::now() + Duration::from_secs(10);
+        room.tick(&server, Duration::from_secs(5), Duration::from_secs(20), later);
+        assert!(room.is_empty());
+        assert!(room.is_open());
+    }
+
+    #[test]
+    pub fn testLostResyncSentAgain() {
+        use std::collections::HashSet;
+        use crate::networking::protocol::MessageType;
+
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let (_b, b) = peer();
+        a_socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let mut room = Room::new(1, "unlucky").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(0));
+        room.add_player(&server, b, Channel::new(), "b", None, Duration::from_millis(0));
+        let mut a_channel = seated(&room, a);
+        let mut b_channel = seated(&room, b);
+
+        // the message types a got, until nothing more arrives
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        let mut receive = |channel: &mut Channel| {
+            let mut seen = Vec::new();
+            while let Ok(n) = a_socket.recv(&mut buffer) {
+                if let Ok((header, _)) = channel.decode::<()>(&buffer[..n]) { seen.push(header.msg_type); }
+            }
+            seen
+        };
+        receive(&mut a_channel);
+
+        // b is back, and the first Resync never reaches a
+        room.dropped = Some((2, Instant::now()));
+        room.tick(&server, Duration::from_secs(5), Duration::from_secs(20), Instant::now());
+        assert_eq!(room.epoch, 1);
+        receive(&mut Channel::new());
+        let now = Instant::now();
+
+        // so it goes out again until a acks it, with anything
+        room.tick(&server, Duration::from_secs(5), Duration::from_secs(20), now + protocol::RESYNC_INTERVAL);
+        assert!(receive(&mut a_channel).contains(&MessageType::Resync));
+        room.handle_packet(&server, a, &a_channel.encode(MessageType::Heartbeat, 0, &()));
+        room.tick(&server, Duration::from_secs(5), Duration::from_secs(20), now + protocol::RESYNC_INTERVAL * 2);
+        assert!(!room.unsynced.contains_key(&a) && room.unsynced.contains_key(&b));
+
+        // or by playing on from it
+        let input = transmit::InputStruct::new(room.sim.frame(), room.epoch, HashSet::new());
+        room.handle_packet(&server, b, &b_channel.encode(MessageType::Input, input.frame, &input));
+        assert!(room.unsynced.is_empty());
+    }
+
+    #[test]
+    pub fn testLostInputsRepeated() {
+        use std::collections::HashSet;
+        use crate::networking::protocol::MessageType;
+
+        let (server, _) = peer();
+        let (_a, a) = peer();
+        let (_b, b) = peer();
+        let mut a_inputs = transmit::RecentInputs::new();
+        let mut b_inputs = transmit::RecentInputs::new();
+        let mut room = Room::new(1, "lossy").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(0));
+        room.add_player(&server, b, Channel::new(), "b", None, Duration::from_millis(0));
+        let mut a_channel = seated(&room, a);
+        let mut b_channel = seated(&room, b);
+
+        for frame in 0..10 {
+            let keys: HashSet<u8> = [frame as u8 % 4].iter().cloned().collect();
+            let a_packet = a_channel.encode(MessageType::Input, frame, &a_inputs.next(frame, 0, keys.clone()));
+            let b_packet = b_channel.encode(MessageType::Input, frame, &b_inputs.next(frame, 0, keys));
+            // most of a's packets are lost, the ones that arrive fill the gaps
+            // and repeat frames the room already has
+            if frame % 4 == 0 || frame == 9 {
+                room.handle_packet(&server, a, &a_packet);
+            }
+            room.handle_packet(&server, b, &b_packet);
+        }
+        room.step(&server);
+        assert_eq!(room.sim.frame(), 10);
+    }
+
+    #[test]
+    pub fn testStalledClientRepeats() {
+        use std::collections::HashSet;
+        use crate::networking::protocol::MessageType;
+
+        let (server, _) = peer();
+        let (_a, a) = peer();
+        let (_b, b) = peer();
+        let mut a_inputs = transmit::RecentInputs::new();
+        let mut room = Room::new(1, "stalled").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(0));
+        room.add_player(&server, b, Channel::new(), "b", None, Duration::from_millis(0));
+        let mut a_channel = seated(&room, a);
+        let mut b_channel = seated(&room, b);
+
+        // a's last packets are lost and a stalls, waiting on inputs of b's that only come through the room
+        for frame in 0..5 {
+            let input = a_inputs.next(frame, 0, HashSet::new());
+            if frame < 2 { room.handle_packet(&server, a, &a_channel.encode(MessageType::Input, frame, &input)); }
+            let input = transmit::InputStruct::new(frame, 0, HashSet::new());
+            room.handle_packet(&server, b, &b_channel.encode(MessageType::Input, frame, &input));
+        }
+        room.step(&server);
+        assert_eq!(room.sim.frame(), 2);
+
+        // with nothing new to send it repeats its newest packet, which carries the lost ones
+        let input = a_inputs.repeat().unwrap();
+        room.handle_packet(&server, a, &a_channel.encode(MessageType::Input, input.frame, &input));
+        room.step(&server);
+        assert_eq!(room.sim.frame(), 5);
+    }
+
+    #[test]
+    pub fn testInputDelayNegotiated() {
+        use crate::networking::protocol::MessageType;
+
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let (_b, b) = peer();
+        let mut room = Room::new(1, "far apart").unwrap();
+        room.add_player(&server, a, Channel::new(), "a", None, Duration::from_millis(30));
+        room.add_player(&server, b, Channel::new(), "b", None, Duration::from_millis(50));
+        assert_eq!(room.start.input_delay, timing::input_delay(Duration::from_millis(30), Duration::from_millis(50)));
+
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        let mut from_server = Channel::new();
+        let mut a_channel = seated(&room, a);
+        let mut next = || loop {
+            let n = a_socket.recv(&mut buffer).unwrap();
+            let header = protocol::read_header(&buffer[..n]).unwrap();
+            if header.msg_type != MessageType::Welcome { return (header.msg_type, buffer[..n].to_vec()); }
+        };
+        let (msg_type, packet) = next();
+        assert_eq!(msg_type, MessageType::Start);
+        assert_eq!(from_server.decode::<MatchStart>(&packet).unwrap().1, room.start);
+        assert_eq!(room.sim.rng, MatchRng::new(room.start.config.seed));
+
+        // the room keeps pinging, answering brings a's estimate down from the handshake's
+        room.tick(&server, Duration::from_secs(5), Duration::from_secs(20), Instant::now());
+        let (msg_type, packet) = next();
+        assert_eq!(msg_type, MessageType::Ping);
+        let (_, id) = from_server.decode::<u32>(&packet).unwrap();
+        room.handle_packet(&server, a, &a_channel.encode(MessageType::Pong, 0, &id));
+        assert!(room.seats[&1].pinger.rtt().unwrap() < Duration::from_millis(30));
+    }
+}
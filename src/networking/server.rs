@@ -0,0 +1,492 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::characters::registry;
+use crate::networking::{config, transmit};
+use crate::networking::protocol::{self, Channel, ClientHello, Cookie, JoinRequest, MessageType, ProtocolError, RoomChoice, RoomId, RoomInfo, ServerInfo, SessionToken};
+use crate::networking::room::Room;
+use crate::networking::transmit::Transport;
+
+// each room holds its own simulation, so a flood of Creates can't grow the lobby without end
+pub const MAX_ROOMS: usize = 64;
+
+// the lobby, needs no window or audio so it runs on a headless box
+// every packet arrives on one socket and is handed to the room that owns the sender,
+// anyone we don't know yet can list rooms, say hello and join one, watch one or rejoin their match
+// and anyone on the LAN can find it with a discovery probe
+pub fn run_server(net_config: &config::NetConfig) -> Result<(), String>{
+    let socket = config::server_setup(net_config)?;
+    // wake up at least once per heartbeat so silent players are noticed
+    socket.set_read_timeout(Some(protocol::HEARTBEAT_INTERVAL)).expect("set_read_timeout call failed");
+
+    let mut lobby = Lobby::new(&net_config.server_name)?;
+    println!("Answering LAN discovery as \"{}\"", lobby.name);
+    let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+
+  //################################################-LOBBY-LOOP##############################################
+    loop{
+        // one packet per pass so a chatty player can't hide a silent one
+        if let Some((number_of_bytes, src_addr)) = transmit::receive_packet(&socket, &mut buffer).map_err(|e| e.to_string())? {
+            lobby.route(&socket, src_addr, &buffer[..number_of_bytes]);
+        }
+
+        let now = Instant::now();
+        for room in lobby.rooms.iter_mut(){
+            room.tick(&socket, net_config.timeout, net_config.grace, now);
+        }
+        lobby.close_finished_rooms();
+    }
+}
+
+// hands out Challenge cookies and checks the ones that come back
+// a cookie is a keyed hash of who asked, from where and when, so the lobby remembers nothing about
+// a Hello and a flood of them from spoofed addresses costs it nothing
+pub struct Cookies {
+    key: RandomState, // a fresh random key every run
+    since: Instant,
+}
+
+impl Default for Cookies {
+    fn default() -> Cookies { Cookies::new() }
+}
+
+impl Cookies {
+    pub fn new() -> Cookies {
+        Cookies { key: RandomState::new(), since: Instant::now() }
+    }
+
+    fn clock(&self, now: Instant) -> u32 {
+        now.saturating_duration_since(self.since).as_millis() as u32
+    }
+
+    fn mac(&self, address: &SocketAddr, hello: &ClientHello, issued: u32) -> u64 {
+        self.key.hash_one((address, &hello.name, hello.version, hello.characters, hello.character, issued))
+    }
+
+    pub fn issue(&self, address: &SocketAddr, hello: &ClientHello, now: Instant) -> Cookie {
+        let issued = self.clock(now);
+        Cookie { issued, mac: self.mac(address, hello, issued) }
+    }
+
+    // how long ago a cookie we made for this address and hello was issued, None if we didn't or it expired
+    pub fn check(&self, address: &SocketAddr, hello: &ClientHello, cookie: Cookie, now: Instant) -> Option<Duration> {
+        if cookie.mac != self.mac(address, hello, cookie.issued) { return None; }
+        let age = Duration::from_millis(self.clock(now).saturating_sub(cookie.issued) as u64);
+        if age > protocol::COOKIE_LIFETIME { None } else { Some(age) }
+    }
+}
+
+pub struct Lobby {
+    pub name: String, // what LAN discovery shows
+    pub rooms: Vec<Room>,
+    next_room: RoomId,
+    cookies: Cookies,
+    characters: u64, // Registry::hash of our character files, players need the same
+}
+
+impl Lobby {
+    pub fn new(name: &str) -> Result<Lobby, String> {
+        Ok(Lobby {
+            name: protocol::cut_name(name, protocol::MAX_SERVER_NAME),
+            rooms: Vec::new(),
+            next_room: 1,
+            cookies: Cookies::new(),
+            characters: registry::global()?.hash(),
+        })
+    }
+
+    // hand a datagram to its room, or treat it as a lobby request
+    pub fn route(&mut self, socket: &dyn Transport, src_addr: SocketAddr, bytes: &[u8]){
+        if protocol::is_discovery_probe(bytes) {
+            transmit::send_discovery_reply(socket, &src_addr, &self.announce());
+            return;
+        }
+
+        if let Ok(header) = protocol::read_header(bytes){
+            match header.msg_type {
+                // a rejoining client starts a fresh channel, possibly from a new address
+                MessageType::Rejoin => {
+                    let mut channel = Channel::new();
+                    if let Ok((_, token)) = channel.decode::<SessionToken>(bytes){
+                        if let Some(room) = self.rooms.iter_mut().find(|r| r.has_token(token)){
+                            room.rejoin(src_addr, channel, token);
+                        }
+                    }
+                    return;
+                },
+                // the handshake comes before there is a session to check,
+                // a seated player sending it again never got their Welcome
+                MessageType::Hello | MessageType::Join => {
+                    self.request(socket, src_addr, bytes);
+                    return;
+                },
+                _ => {},
+            }
+        }
+
+        match self.rooms.iter_mut().find(|r| r.owns(&src_addr)){
+            Some(room) => room.handle_packet(socket, src_addr, bytes),
+            None => self.request(socket, src_addr, bytes),
+        }
+    }
+
+    // someone who isn't in a room yet
+    fn request(&mut self, socket: &dyn Transport, src_addr: SocketAddr, bytes: &[u8]){
+        let mut channel = Channel::new();
+        let msg_type = match protocol::read_header(bytes){
+            Ok(header) => header.msg_type,
+            Err(e @ ProtocolError::VersionMismatch{..}) => {
+                transmit::reject_version(socket, &src_addr, &e);
+                return;
+            },
+            Err(_) => return,
+        };
+        match msg_type {
+            MessageType::ListRooms => {
+                if channel.decode::<()>(bytes).is_err() { return; }
+                let rooms = self.list();
+                transmit::send_rooms(socket, &src_addr, &mut channel, &rooms);
+            },
+            MessageType::Hello => {
+                let hello = match channel.decode::<ClientHello>(bytes){
+                    Ok((_, hello)) => hello,
+                    Err(_) => return,
+                };
+                if hello.version != protocol::PROTOCOL_VERSION {
+                    let e = ProtocolError::VersionMismatch{ ours: protocol::PROTOCOL_VERSION, theirs: hello.version };
+                    transmit::reject_version(socket, &src_addr, &e);
+                } else if hello.characters != self.characters {
+                    let reason = format!("your character files are not the same as the server's, both need the same {}", registry::CHARACTERS_DIR);
+                    transmit::send_rejected(socket, &src_addr, &mut channel, &reason);
+                } else if let Some(Err(reason)) = hello.character.map(registry::character) {
+                    transmit::send_rejected(socket, &src_addr, &mut channel, &reason);
+                } else if protocol::player_name(&hello.name).is_empty() {
+                    transmit::send_rejected(socket, &src_addr, &mut channel, "player names can't be empty");
+                } else {
+                    let cookie = self.cookies.issue(&src_addr, &hello, Instant::now());
+                    transmit::send_challenge(socket, &src_addr, &mut channel, cookie);
+                }
+            },
+            MessageType::Join => {
+                let join = match channel.decode::<JoinRequest>(bytes){
+                    Ok((_, join)) => join,
+                    Err(_) => return,
+                };
+                // no cookie, or one sent to some other address: a spoofed Join, or a very slow one
+                let rtt = match self.cookies.check(&src_addr, &join.hello, join.cookie, Instant::now()) {
+                    Some(rtt) => rtt,
+                    None => return,
+                };
+                if let Some(room) = self.rooms.iter_mut().find(|r| r.has_player(&src_addr)){
+                    room.welcome_again(socket, src_addr);
+                    return;
+                }
+                match self.find_seat(&join.room){
+                    Ok(index) => self.rooms[index].add_player(socket, src_addr, channel, &join.hello.name, join.hello.character, rtt),
+                    Err(reason) => transmit::send_rejected(socket, &src_addr, &mut channel, &reason),
+                }
+            },
+            MessageType::Spectate => {
+                let wanted = match channel.decode::<Option<RoomId>>(bytes){
+                    Ok((_, wanted)) => wanted,
+                    Err(_) => return,
+                };
+                let index = match wanted {
+                    Some(id) => self.rooms.iter().position(|r| r.id == id),
+                    // the first match that is actually being played, otherwise anything
+                    None => self.rooms.iter().position(|r| r.is_started()).or(if self.rooms.is_empty() { None } else { Some(0) }),
+                };
+                // nowhere to watch yet, they keep asking
+                if let Some(index) = index {
+                    self.rooms[index].add_spectator(socket, src_addr, channel);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // index of the room a Join should go to, opening one if needed
+    fn find_seat(&mut self, choice: &RoomChoice) -> Result<usize, String>{
+        match choice {
+            RoomChoice::Any => match self.rooms.iter().position(|r| r.is_open()) {
+                Some(index) => Ok(index),
+                None => {
+                    let name = format!("Room {}", self.next_room);
+                    self.open_room(&name)
+                },
+            },
+            RoomChoice::Room(id) => match self.rooms.iter().position(|r| r.id == *id) {
+                Some(index) if self.rooms[index].is_open() => Ok(index),
+                Some(_) => Err(format!("room {} is full", id)),
+                None => Err(format!("there is no room {}", id)),
+            },
+            RoomChoice::Create(name) => {
+                if protocol::room_name(name).is_empty() {
+                    return Err("room names can't be empty".to_string());
+                }
+                self.open_room(name)
+            },
+        }
+    }
+
+    fn open_room(&mut self, name: &str) -> Result<usize, String> {
+        if self.rooms.len() >= MAX_ROOMS {
+            return Err("the server has no room for another match, try again later".to_string());
+        }
+        let room = Room::new(self.next_room, name)?;
+        println!("Opened room {} \"{}\"", room.id, room.name);
+        self.next_room += 1;
+        self.rooms.push(room);
+        Ok(self.rooms.len() - 1)
+    }
+
+    // open rooms first so someone looking for a game sees them, cut to one packet
+    pub fn list(&self) -> Vec<RoomInfo> {
+        let mut rooms: Vec<RoomInfo> = self.rooms.iter().map(|r| r.info()).collect();
+        rooms.sort_by_key(|r| (r.started, r.id));
+        rooms.truncate(protocol::MAX_LISTED_ROOMS);
+        rooms
+    }
+
+    // the answer to a discovery probe
+    pub fn announce(&self) -> ServerInfo {
+        ServerInfo::new(&self.name, self.rooms.iter().map(|r| r.info().players as u32).sum())
+    }
+
+    // finished matches and rooms everyone walked out of
+    pub fn close_finished_rooms(&mut self){
+        self.rooms.retain(|room| {
+            if let Some((player, reason)) = room.result() {
+                println!("Room {}: player {} left the match ({:?}), closing", room.id, player, reason);
+                false
+            } else if room.is_empty() {
+                println!("Room {}: everyone left, closing", room.id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+    use crate::characters::registry::CharacterId;
+    use crate::simulation::SimConfig;
+
+    fn peer() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        (socket, address)
+    }
+
+    // a hello from someone with the same character files as the lobby
+    fn client(name: &str) -> ClientHello { ClientHello::new(name, registry::global().unwrap().hash(), None) }
+
+    // the cookie the lobby answers a hello with
+    fn hello(lobby: &mut Lobby, server: &UdpSocket, socket: &UdpSocket, hello: &ClientHello) -> Cookie {
+        let address = socket.local_addr().unwrap();
+        lobby.route(server, address, &Channel::new().encode(MessageType::Hello, 0, hello));
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        let n = socket.recv(&mut buffer).unwrap();
+        let (header, cookie) = Channel::new().decode::<Cookie>(&buffer[..n]).unwrap();
+        assert_eq!(header.msg_type, MessageType::Challenge);
+        cookie
+    }
+
+    fn join(lobby: &mut Lobby, server: &UdpSocket, socket: &UdpSocket, choice: RoomChoice) {
+        join_as(lobby, server, socket, client("tester"), choice);
+    }
+
+    fn join_as(lobby: &mut Lobby, server: &UdpSocket, socket: &UdpSocket, hello_: ClientHello, choice: RoomChoice) {
+        let cookie = hello(lobby, server, socket, &hello_);
+        let packet = Channel::new().encode(MessageType::Join, 0, &JoinRequest{ hello: hello_, cookie, room: choice });
+        lobby.route(server, socket.local_addr().unwrap(), &packet);
+    }
+
+    #[test]
+    pub fn testMatchmaking() {
+        let (server, _) = peer();
+        let peers: Vec<(UdpSocket, SocketAddr)> = (0..5).map(|_| peer()).collect();
+        let mut lobby = Lobby::new("test").unwrap();
+
+        // quick join pairs players two by two
+        for (socket, _) in peers.iter().take(4) {
+            join(&mut lobby, &server, socket, RoomChoice::Any);
+        }
+        assert_eq!(lobby.rooms.len(), 2);
+        assert!(lobby.rooms.iter().all(|r| r.is_started()));
+
+        join(&mut lobby, &server, &peers[4].0, RoomChoice::Create("  grudge match ".to_string()));
+        let rooms = lobby.list();
+        assert_eq!(rooms.len(), 3);
+        assert_eq!(rooms[0].name, "grudge match");
+        assert!(!rooms[0].started);
+    }
+
+    #[test]
+    pub fn testJoinFullRoom() {
+        let (server, _) = peer();
+        let (a_socket, _) = peer();
+        let (b_socket, _) = peer();
+        let (c_socket, c) = peer();
+        let mut lobby = Lobby::new("test").unwrap();
+
+        join(&mut lobby, &server, &a_socket, RoomChoice::Create("dojo".to_string()));
+        let id = lobby.rooms[0].id;
+        join(&mut lobby, &server, &b_socket, RoomChoice::Room(id));
+        join(&mut lobby, &server, &c_socket, RoomChoice::Room(id));
+        assert!(!lobby.rooms[0].owns(&c));
+
+        // c hears why, a heard Welcome then Start
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        let mut channel = Channel::new();
+        let n = c_socket.recv(&mut buffer).unwrap();
+        let (header, reason) = channel.decode::<String>(&buffer[..n]).unwrap();
+        assert_eq!(header.msg_type, MessageType::Rejected);
+        assert_eq!(reason, format!("room {} is full", id));
+
+        let mut channel = Channel::new();
+        let n = a_socket.recv(&mut buffer).unwrap();
+        assert_eq!(channel.decode::<()>(&buffer[..n]).unwrap().0.msg_type, MessageType::Welcome);
+        let n = a_socket.recv(&mut buffer).unwrap();
+        assert_eq!(channel.decode::<()>(&buffer[..n]).unwrap().0.msg_type, MessageType::Start);
+    }
+
+    #[test]
+    pub fn testRoomLimit() {
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let mut lobby = Lobby::new("test").unwrap();
+        for _ in 0..MAX_ROOMS {
+            lobby.open_room("filler").unwrap();
+        }
+
+        // one more is turned down and nothing is allocated for it
+        join(&mut lobby, &server, &a_socket, RoomChoice::Create("one too many".to_string()));
+        assert_eq!(lobby.rooms.len(), MAX_ROOMS);
+        assert!(!lobby.rooms.iter().any(|r| r.owns(&a)));
+        let (_, reason) = next::<String>(&a_socket, &mut Channel::new(), MessageType::Rejected);
+        assert_eq!(reason, "the server has no room for another match, try again later");
+
+        // until one closes
+        lobby.close_finished_rooms();
+        join(&mut lobby, &server, &a_socket, RoomChoice::Create("next".to_string()));
+        assert_eq!(lobby.rooms.len(), 1);
+    }
+
+    #[test]
+    pub fn testCharacterMismatch() {
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let mut lobby = Lobby::new("test").unwrap();
+
+        // turned away at the hello, before a cookie or a seat
+        let hello = ClientHello::new("modder", lobby.characters.wrapping_add(1), None);
+        lobby.route(&server, a, &Channel::new().encode(MessageType::Hello, 0, &hello));
+        let (_, reason) = next::<String>(&a_socket, &mut Channel::new(), MessageType::Rejected);
+        assert!(reason.starts_with("your character files are not the same"));
+        assert!(lobby.rooms.is_empty());
+    }
+
+    #[test]
+    pub fn testCharacterChoice() {
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let (b_socket, _) = peer();
+        let mut lobby = Lobby::new("test").unwrap();
+
+        let hello = ClientHello { character: Some(CharacterId(999)), ..client("cheater") };
+        lobby.route(&server, a, &Channel::new().encode(MessageType::Hello, 0, &hello));
+        let (_, reason) = next::<String>(&a_socket, &mut Channel::new(), MessageType::Rejected);
+        assert!(reason.starts_with("no character has id 999"));
+
+        // player 1 picked Java, player 2 didn't pick and gets their usual one
+        join_as(&mut lobby, &server, &a_socket, ClientHello { character: Some(CharacterId(2)), ..client("a") }, RoomChoice::Any);
+        join_as(&mut lobby, &server, &b_socket, client("b"), RoomChoice::Any);
+        for socket in [&a_socket, &b_socket].iter() {
+            let (_, start) = next::<protocol::MatchStart>(socket, &mut Channel::new(), MessageType::Start);
+            assert_eq!((start.config.p1, start.config.p2), (CharacterId(2), SimConfig::default().p2));
+        }
+    }
+
+    // the next packet of this type waiting on a peer's socket
+    fn next<T: serde::de::DeserializeOwned>(socket: &UdpSocket, channel: &mut Channel, msg_type: MessageType) -> (protocol::PacketHeader, T) {
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        loop {
+            let n = socket.recv(&mut buffer).unwrap();
+            if protocol::read_header(&buffer[..n]).map(|header| header.msg_type) == Ok(msg_type) {
+                return channel.decode::<T>(&buffer[..n]).unwrap();
+            }
+            let _ = channel.decode::<()>(&buffer[..n]);
+        }
+    }
+
+    #[test]
+    pub fn testHandshake() {
+        use std::collections::HashSet;
+        use crate::networking::transmit::InputStruct;
+
+        let (server, _) = peer();
+        let (a_socket, a) = peer();
+        let (b_socket, _) = peer();
+        let (_mallory, mallory) = peer();
+        let mut lobby = Lobby::new("test").unwrap();
+
+        // a Join needs a cookie, and one sent to a's address doesn't work from anywhere else
+        let a_hello = client("alice");
+        let guess = JoinRequest{ hello: a_hello.clone(), cookie: Cookie{ issued: 0, mac: 1234 }, room: RoomChoice::Any };
+        lobby.route(&server, a, &Channel::new().encode(MessageType::Join, 0, &guess));
+        let cookie = hello(&mut lobby, &server, &a_socket, &a_hello);
+        let stolen = JoinRequest{ hello: a_hello.clone(), cookie, room: RoomChoice::Any };
+        lobby.route(&server, mallory, &Channel::new().encode(MessageType::Join, 0, &stolen));
+        assert!(lobby.rooms.is_empty());
+
+        // a's Welcome is lost, asking again gets the same seat
+        let mut a_channel = Channel::new();
+        lobby.route(&server, a, &a_channel.encode(MessageType::Join, 0, &stolen));
+        join(&mut lobby, &server, &b_socket, RoomChoice::Any);
+        lobby.route(&server, a, &a_channel.encode(MessageType::Join, 0, &stolen));
+        assert_eq!(lobby.rooms.len(), 1);
+        assert_eq!(lobby.rooms[0].info().players, 2);
+
+        let mut from_server = Channel::new();
+        let (_, welcome) = next::<protocol::Welcome>(&a_socket, &mut from_server, MessageType::Welcome);
+        let (_, again) = next::<protocol::Welcome>(&a_socket, &mut from_server, MessageType::Welcome);
+        next::<()>(&a_socket, &mut from_server, MessageType::Start);
+        assert_eq!(welcome, again);
+        assert_eq!(welcome.player, 1);
+        a_channel.set_session(welcome.session);
+
+        // inputs claiming to be from a only reach b if they carry a's session
+        let input = InputStruct::new(0, 0, HashSet::new());
+        lobby.route(&server, a, &Channel::new().encode(MessageType::Input, 99, &input));
+        lobby.route(&server, a, &a_channel.encode(MessageType::Input, 0, &input));
+        let (header, _) = next::<()>(&b_socket, &mut Channel::new(), MessageType::Input);
+        assert_eq!(header.frame, 0);
+    }
+
+    #[test]
+    pub fn testCookies() {
+        let cookies = Cookies::new();
+        let address: SocketAddr = "10.0.0.5:4000".parse().unwrap();
+        let hello = client("alice");
+        let now = Instant::now();
+        let cookie = cookies.issue(&address, &hello, now);
+
+        // coming back tells the lobby how far away the player is
+        assert_eq!(cookies.check(&address, &hello, cookie, now + Duration::from_millis(80)), Some(Duration::from_millis(80)));
+        assert_eq!(cookies.check(&address, &hello, cookie, now + protocol::COOKIE_LIFETIME * 2), None);
+        assert_eq!(cookies.check(&"10.0.0.5:4001".parse().unwrap(), &hello, cookie, now), None);
+        assert_eq!(cookies.check(&address, &client("mallory"), cookie, now), None);
+        assert_eq!(cookies.check(&address, &ClientHello{ characters: 1, ..hello.clone() }, cookie, now), None);
+        // claiming it was sent later doesn't work either
+        assert_eq!(cookies.check(&address, &hello, Cookie{ issued: cookie.issued + 50, ..cookie }, now), None);
+        // another server, or this one restarted, never made it
+        assert_eq!(Cookies::new().check(&address, &hello, cookie, now), None);
+    }
+}
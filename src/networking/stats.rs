@@ -0,0 +1,168 @@
+use std::cell::Cell;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::networking::protocol::Channel;
+use crate::networking::timing::Pinger;
+use crate::networking::transmit::Transport;
+
+// the stats HUD changes this often, any faster and the numbers can't be read
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+// a Transport that counts the bytes going through it
+pub struct Metered<T: Transport> {
+    inner: T,
+    sent: Cell<u64>,
+    received: Cell<u64>,
+}
+
+impl<T: Transport> Metered<T> {
+    pub fn new(inner: T) -> Metered<T> {
+        Metered { inner, sent: Cell::new(0), received: Cell::new(0) }
+    }
+
+    // (sent, received) since it was made
+    pub fn totals(&self) -> (u64, u64) { (self.sent.get(), self.received.get()) }
+
+    fn count_sent(&self, result: io::Result<usize>) -> io::Result<usize> {
+        if let Ok(n) = result { self.sent.set(self.sent.get() + n as u64); }
+        result
+    }
+}
+
+impl<T: Transport> Transport for Metered<T> {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> { self.count_sent(self.inner.send(buf)) }
+    fn send_to(&self, buf: &[u8], address: &SocketAddr) -> io::Result<usize> { self.count_sent(self.inner.send_to(buf, address)) }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let result = self.inner.recv_from(buf);
+        if let Ok((n, _)) = result { self.received.set(self.received.get() + n as u64); }
+        result
+    }
+
+    // the same bytes are counted when they are actually received
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> { self.inner.peek(buf) }
+}
+
+// what the stats HUD shows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetStats {
+    pub rtt: Option<Duration>,
+    pub jitter: Duration,
+    pub loss_sent: f64,     // 0 to 1, of our recent packets the server didn't ack
+    pub loss_received: f64, // 0 to 1, of the server's recent packets that never came
+    pub sent_per_sec: u64,  // bytes
+    pub received_per_sec: u64,
+    pub input_delay: u32,
+    pub rollback: u32,      // deepest rollback since the last refresh, in frames
+}
+
+impl NetStats {
+    pub fn lines(&self) -> Vec<String> {
+        let rtt = match self.rtt {
+            Some(rtt) => format!("{} ms", rtt.as_millis()),
+            None => "--".to_string(),
+        };
+        vec![
+            format!("ping {}  jitter {} ms", rtt, self.jitter.as_millis()),
+            format!("loss {:.0}% up  {:.0}% down", self.loss_sent * 100.0, self.loss_received * 100.0),
+            format!("{} B/s up  {} B/s down", self.sent_per_sec, self.received_per_sec),
+            format!("input delay {}  rollback {} frames", self.input_delay, self.rollback),
+        ]
+    }
+}
+
+// collects what goes into NetStats between refreshes
+pub struct StatsMeter {
+    since: Instant,
+    totals: (u64, u64), // Metered::totals at the last refresh
+    rollback: u32,
+    shown: NetStats,
+}
+
+impl StatsMeter {
+    pub fn new(now: Instant) -> StatsMeter {
+        StatsMeter { since: now, totals: (0, 0), rollback: 0, shown: NetStats::default() }
+    }
+
+    // the game loop rewound this many frames
+    pub fn rollback(&mut self, frames: u32) {
+        self.rollback = self.rollback.max(frames);
+    }
+
+    // the numbers to show, worked out again once every STATS_INTERVAL
+    pub fn update(&mut self, now: Instant, totals: (u64, u64), pinger: &Pinger, channel: &Channel, input_delay: u32) -> &NetStats {
+        let elapsed = now.saturating_duration_since(self.since);
+        if elapsed >= STATS_INTERVAL {
+            let per_sec = |bytes: u64| (bytes as f64 / elapsed.as_secs_f64()).round() as u64;
+            let (loss_sent, loss_received) = channel.loss();
+            self.shown = NetStats {
+                rtt: pinger.rtt(),
+                jitter: pinger.jitter(),
+                loss_sent,
+                loss_received,
+                sent_per_sec: per_sec(totals.0 - self.totals.0),
+                received_per_sec: per_sec(totals.1 - self.totals.1),
+                input_delay,
+                rollback: self.rollback,
+            };
+            self.since = now;
+            self.totals = totals;
+            self.rollback = 0;
+        }
+        &self.shown
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+    use crate::networking::protocol::MessageType;
+
+    #[test]
+    pub fn testMetered() {
+        let a = Metered::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b_address = b.local_addr().unwrap();
+        a.send_to(&[0; 100], &b_address).unwrap();
+        b.send_to(&[0; 40], a.inner.local_addr().unwrap()).unwrap();
+
+        let mut buffer = [0u8; 100];
+        a.peek(&mut buffer).unwrap();
+        a.recv_from(&mut buffer).unwrap();
+        assert_eq!(a.totals(), (100, 40));
+    }
+
+    #[test]
+    pub fn testStats() {
+        let start = Instant::now();
+        let mut meter = StatsMeter::new(start);
+        let mut pinger = Pinger::new();
+        pinger.sample(Duration::from_millis(48));
+        let mut ours = Channel::new();
+        let mut theirs = Channel::new();
+        // a quarter of what they send us is lost
+        for frame in 0..16 {
+            let packet = theirs.encode(MessageType::Input, frame, &());
+            if frame % 4 != 1 { ours.decode::<()>(&packet).unwrap(); }
+        }
+
+        meter.rollback(2);
+        meter.rollback(5);
+        assert_eq!(meter.update(start + Duration::from_millis(500), (1000, 1000), &pinger, &ours, 2), &NetStats::default());
+
+        let stats = meter.update(start + Duration::from_secs(2), (3000, 1000), &pinger, &ours, 2).clone();
+        assert_eq!(stats.sent_per_sec, 1500);
+        assert_eq!(stats.received_per_sec, 500);
+        assert_eq!(stats.rollback, 5);
+        assert_eq!(stats.loss_received, 0.25);
+        assert_eq!(stats.loss_sent, 0.0); // nothing to go on yet
+        assert_eq!(stats.lines()[0], "ping 48 ms  jitter 24 ms");
+        assert_eq!(stats.lines()[3], "input delay 2  rollback 5 frames");
+
+        // the rollback is only for the last interval
+        assert_eq!(meter.update(start + Duration::from_secs(3), (3000, 1000), &pinger, &ours, 2).rollback, 0);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::view::globals::FRAME_RATE;
+
+// how often each side pings the other
+pub const PING_INTERVAL: Duration = Duration::from_millis(500);
+// pings older than the last few are given up on
+const MAX_OUTSTANDING_PINGS: usize = 8;
+
+// the delay never hides more than this, rollback covers the rest
+pub const MAX_INPUT_DELAY: u32 = 3;
+
+// frame advantages are averaged over this many frames so one late packet doesn't change the pace
+const TIMESYNC_WINDOW: usize = 32;
+// how much longer a frame lasts while we are ahead
+const SLOWDOWN: f64 = 0.1;
+
+// round trip time to one peer, smoothed the way TCP does it (RFC 6298)
+#[derive(Default)]
+pub struct Pinger {
+    next_id: u32,
+    sent: VecDeque<(u32, Instant)>, // unanswered, oldest first
+    last_sent: Option<Instant>,
+    rtt: Option<Duration>,
+    jitter: Duration, // mean deviation of the samples from rtt
+}
+
+impl Pinger {
+    pub fn new() -> Pinger {
+        Pinger::default()
+    }
+
+    pub fn due(&self, now: Instant) -> bool {
+        self.last_sent.is_none_or(|t| now.saturating_duration_since(t) >= PING_INTERVAL)
+    }
+
+    // the id to put in the next Ping
+    pub fn ping(&mut self, now: Instant) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.sent.push_back((id, now));
+        if self.sent.len() > MAX_OUTSTANDING_PINGS { self.sent.pop_front(); }
+        self.last_sent = Some(now);
+        id
+    }
+
+    // a Pong came back, false if it wasn't for one of ours
+    // pings sent before it are given up on, their pongs were lost or are slower than this one
+    pub fn pong(&mut self, id: u32, now: Instant) -> bool {
+        let index = match self.sent.iter().position(|(sent_id, _)| *sent_id == id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let (_, sent) = self.sent[index];
+        self.sent.drain(..=index);
+        self.sample(now.saturating_duration_since(sent));
+        true
+    }
+
+    // a round trip measured some other way, like the join handshake
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.rtt {
+            None => {
+                self.rtt = Some(rtt);
+                self.jitter = rtt / 2;
+            },
+            Some(smoothed) => {
+                let deviation = rtt.abs_diff(smoothed);
+                self.jitter = self.jitter.mul_f64(0.75) + deviation.mul_f64(0.25);
+                self.rtt = Some(smoothed.mul_f64(0.875) + rtt.mul_f64(0.125));
+            },
+        }
+    }
+
+    pub fn rtt(&self) -> Option<Duration> { self.rtt }
+    pub fn jitter(&self) -> Duration { self.jitter }
+}
+
+// whole frames, rounded up, in a duration
+pub fn frames_in(duration: Duration) -> u32 {
+    (duration.as_secs_f64() / FRAME_RATE).ceil() as u32
+}
+
+// the input delay for a match between players this far from the server
+// an input takes half of each round trip to go from one player through the server to the other,
+// delaying it that many frames means it usually arrives in time and nothing is rolled back
+pub fn input_delay(rtt_1: Duration, rtt_2: Duration) -> u32 {
+    frames_in((rtt_1 + rtt_2) / 2).min(MAX_INPUT_DELAY)
+}
+
+// GGPO style time sync: both players work out how many frames ahead of the other they are
+// and send it with their inputs, whoever is further ahead runs a little slower until they meet
+// comparing the two numbers cancels out most of what either side gets wrong about the latency
+#[derive(Default)]
+pub struct TimeSync {
+    local: VecDeque<i32>,
+    remote: VecDeque<i32>,
+}
+
+impl TimeSync {
+    pub fn new() -> TimeSync {
+        TimeSync::default()
+    }
+
+    // once per frame simulated: where we are against where the opponent probably is now,
+    // their newest input was made `input_delay` frames before the frame it is for and took about a round trip to get here
+    pub fn local(&mut self, frame: u32, latest_remote: Option<u32>, input_delay: u32, rtt: Duration) {
+        let latest_remote = match latest_remote {
+            Some(latest_remote) => latest_remote,
+            None => return, // nothing heard yet
+        };
+        let remote_now = latest_remote as i64 - input_delay as i64 + frames_in(rtt) as i64;
+        push(&mut self.local, (frame as i64 - remote_now) as i32);
+    }
+
+    // the advantage the opponent sent with an input
+    pub fn remote(&mut self, advantage: i8) {
+        push(&mut self.remote, advantage as i32);
+    }
+
+    // what to send with our inputs
+    pub fn local_advantage(&self) -> i8 {
+        self.local.back().map_or(0, |a| (*a).max(i8::MIN as i32).min(i8::MAX as i32) as i8)
+    }
+
+    // half the gap between the two views, both sides meet in the middle
+    pub fn frames_ahead(&self) -> f64 {
+        (average(&self.local) - average(&self.remote)) / 2.0
+    }
+
+    // how long the next frame should take
+    pub fn frame_time(&self, frame_time: Duration) -> Duration {
+        if self.frames_ahead() >= 1.0 { frame_time.mul_f64(1.0 + SLOWDOWN) } else { frame_time }
+    }
+}
+
+fn push(window: &mut VecDeque<i32>, value: i32) {
+    window.push_back(value);
+    if window.len() > TIMESYNC_WINDOW { window.pop_front(); }
+}
+
+fn average(window: &VecDeque<i32>) -> f64 {
+    if window.is_empty() { return 0.0; }
+    window.iter().sum::<i32>() as f64 / window.len() as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration { Duration::from_millis(millis) }
+
+    #[test]
+    pub fn testRtt() {
+        let mut pinger = Pinger::new();
+        let start = Instant::now();
+        assert!(pinger.due(start));
+
+        let lost = pinger.ping(start);
+        let answered = pinger.ping(start + ms(10));
+        assert!(!pinger.due(start + ms(10)));
+        assert!(pinger.pong(answered, start + ms(60)));
+        assert_eq!(pinger.rtt(), Some(ms(50)));
+        // the earlier one was given up on, and strangers are ignored
+        assert!(!pinger.pong(lost, start + ms(70)));
+        assert!(!pinger.pong(1234, start + ms(70)));
+
+        // a slow sample moves the average a little and shows up as jitter
+        pinger.sample(ms(130));
+        assert_eq!(pinger.rtt(), Some(ms(60)));
+        assert!(pinger.jitter() > ms(25));
+        assert!(pinger.due(start + ms(10) + PING_INTERVAL));
+    }
+
+    #[test]
+    pub fn testInputDelay() {
+        assert_eq!(input_delay(ms(0), ms(0)), 0);
+        // a LAN still waits for the next frame
+        assert_eq!(input_delay(ms(1), ms(2)), 1);
+        // 40ms of one way latency is two and a bit frames at 60fps
+        assert_eq!(input_delay(ms(30), ms(50)), 3);
+        assert_eq!(input_delay(ms(300), ms(300)), MAX_INPUT_DELAY);
+    }
+
+    #[test]
+    pub fn testFasterSideSlowsDown() {
+        let frame_time = Duration::from_secs_f64(FRAME_RATE);
+        let mut fast = TimeSync::new();
+        let mut slow = TimeSync::new();
+        assert_eq!(fast.frame_time(frame_time), frame_time);
+
+        // fast is on frame 100 and slow on 96, the newest input each has from the other
+        // was sent a frame ago with a delay of two
+        for _ in 0..TIMESYNC_WINDOW {
+            fast.local(100, Some(96 - 1 + 2), 2, ms(10));
+            slow.local(96, Some(100 - 1 + 2), 2, ms(10));
+            fast.remote(slow.local_advantage());
+            slow.remote(fast.local_advantage());
+        }
+        assert_eq!((fast.local_advantage(), slow.local_advantage()), (4, -4));
+        assert_eq!(fast.frames_ahead(), 4.0);
+        assert!(fast.frame_time(frame_time) > frame_time);
+        assert_eq!(slow.frame_time(frame_time), frame_time);
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(non_snake_case)]
-use sdl2::rect::{Rect, Point};
+use crate::physics::rect::{Rect, Point};
 use std::cell::{RefCell, Ref};
 use std::rc::Rc;
 use std::ops::{Deref, DerefMut};
@@ -8,6 +8,7 @@ use crate::view::globals::*;
 use crate::physics::nodes::*;
 use crate::physics::particle::*;
 use crate::physics::vecmath::*;
+use crate::animation::sprites::MoveData;
 
 pub struct BVHierarchy {
 	pub head: NodeRef<CollisionObject>,
@@ -21,16 +22,71 @@ impl BVHierarchy {
 		// println!("inserting {:?}", co);
 		self.head.insert(co)
 	}
+	// take out every leaf that `matches` picks in one walk of the tree
+	// the copy insert hands back keeps the node it was put in, which goes stale once a later insert splits that node,
+	// so the tree is searched instead
+	pub fn remove_where(&self, matches: impl Fn(&CollisionObject) -> bool) {
+		self.head.prune(&matches);
+	}
+	// take out every fighter and hazard box, leaving the stage as it was built
+	// the tree's shape decides the order contacts resolve in, so boxes are rebuilt from here every frame
+	// and a rewound match ends up with the same tree as one that never left that frame
+	pub fn clear_moving(&self) {
+		self.remove_where(|co| co.obj_type != CollisionObjectType::Platform && co.obj_type != CollisionObjectType::Wall);
+	}
+	// save the particles of the stage (platforms and walls) in tree order
+	// fighter and hazard boxes are rebuilt every frame, so only the stage needs saving
+	pub fn snapshot(&self) -> Vec<Particle> {
+		self.stage_objects().iter().map(|co| co.borrow().particle.borrow().snapshot()).collect()
+	}
+	// stage leaves are never removed, so their order in the tree stays the same
+	pub fn restore(&self, snapshot: &[Particle]) {
+		for (co, particle) in self.stage_objects().iter().zip(snapshot.iter()) {
+			co.borrow().particle.borrow_mut().restore(particle);
+		}
+	}
+	fn stage_objects(&self) -> Vec<RefCell<CollisionObject>> {
+		let mut leaves = Vec::new();
+		self.head.getLeaves(&mut leaves);
+		leaves.into_iter().filter(|co| {
+			matches!(co.borrow().obj_type, CollisionObjectType::Platform | CollisionObjectType::Wall)
+		}).collect()
+	}
 	pub fn resolve_collisions(&self) -> (bool, bool) {
 		let mut potential_collisions: Vec<ParticleContact> = Vec::new();
 		let count = self.head.getPotentialCollisions(&mut potential_collisions, 100);
 		// println!("Counted {} collisions\n", count);
 		let mut hazard_reset = false; // bool to reset hazard upon impact
 		let mut hit_audio = false;
+		let mut struck: Vec<Rc<RefCell<Particle>>> = Vec::new(); // fighters the hazard already hit this frame
 		for contact in potential_collisions.iter_mut() {
 			let p0 = contact.objects[0].clone();
 			let p1 = contact.objects[1].clone();
 			if check_collision(p0.borrow().clone(), p1.borrow().clone()) {
+				// attacks go by the move's frame data instead of bouncing the fighters apart
+				let attack = match (p0.borrow().obj_type, p1.borrow().obj_type) {
+					(CollisionObjectType::HitBox, _) => Some((p0.borrow().clone(), p1.borrow().clone())),
+					(_, CollisionObjectType::HitBox) => Some((p1.borrow().clone(), p0.borrow().clone())),
+					_ => None,
+				};
+				if let Some((hitbox, target)) = attack {
+					if land_attack(&hitbox, &target) {
+						hit_audio = true;
+					}
+					continue;
+				}
+				// a fighter has a few hurt boxes, the hazard only hits once
+				let fighter = match (p0.borrow().obj_type, p1.borrow().obj_type) {
+					(CollisionObjectType::Hazard, _) => Some(p1.borrow().particle.clone()),
+					(_, CollisionObjectType::Hazard) => Some(p0.borrow().particle.clone()),
+					_ => None,
+				};
+				if let Some(fighter) = fighter {
+					if struck.iter().any(|p| Rc::ptr_eq(p, &fighter)) {
+						continue;
+					}
+					struck.push(fighter);
+				}
 				// println!("Resolving....");
 				// if contact.resolve_velocity(FRAME_RATE as f32) {hazard_reset = true}
 				if contact.resolve_velocity(FRAME_RATE as f32) {
@@ -40,7 +96,7 @@ impl BVHierarchy {
 				match (p0.borrow().obj_type, p1.borrow().obj_type) {
 					(CollisionObjectType::Hazard, _)  | (_, CollisionObjectType::Hazard) => {
 						hazard_reset = true;
-						println!("\n\nContact between\n {:#?}\nand\n {:#?}", contact.objects[0], contact.objects[1]);
+						// println!("\n\nContact between\n {:#?}\nand\n {:#?}", contact.objects[0], contact.objects[1]);
 					},
 					// {println!("\n\n**********BVH Head: {:#?}\n", self.head);
 					// println!("\n\nContact between\n {:#?}\nand\n {:#?}", contact.objects[0], contact.objects[1])},
@@ -59,6 +115,27 @@ impl BVHierarchy {
 	}
 }
 
+// the platform, walls and arch every match is played on
+pub fn build_stage() -> BVHierarchy {
+	let platform = Rect::new(100, 560, CAM_W-200, 30);
+	let wall_l = Rect::new(WALL_L.0, WALL_L.1, WALL_SIZE.0, WALL_SIZE.1);
+	let wall_r = Rect::new(WALL_R.0, WALL_R.1, WALL_SIZE.0, WALL_SIZE.1);
+	let arch = Rect::new(ARCH.0, ARCH.1, ARCH_SIZE.0, ARCH_SIZE.1);
+
+	let collisions = BVHierarchy::new(CollisionObject::new_from(CollisionObjectType::Platform, platform,
+		Rc::new(RefCell::new(Particle::new(
+			PhysVec::new(((CAM_W-platform.width())/2) as f32, 560f32), 0.5, 2000000000.0, 0, 0)))));
+
+	collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_l,
+		Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_L.0 as f32, WALL_L.1 as f32), 0.5, 20000000000.0, 0, 0)))));
+	collisions.insert(CollisionObject::new_from(CollisionObjectType::Wall, wall_r,
+		Rc::new(RefCell::new(Particle::new(PhysVec::new(WALL_R.0 as f32, WALL_R.1 as f32), 0.5, 20000000000.0, 0, 0)))));
+	collisions.insert(CollisionObject::new_from(CollisionObjectType::Platform, arch,
+		Rc::new(RefCell::new(Particle::new(PhysVec::new(ARCH.0 as f32, ARCH.1 as f32), 0.5, 20000000000.0, 0, 0)))));
+
+	collisions
+}
+
 pub fn boxUp<T>(data: T) -> Option<RefCell<T>>{
 	Some(RefCell::new(data))
 }
@@ -82,18 +159,87 @@ impl<T: fmt::Debug> fmt::Debug for Node<T> {
 }
 
 pub fn check_collision(a: CollisionObject, b: CollisionObject) -> bool {
+	// a fighter's own boxes share its particle and never touch each other
+	if Rc::ptr_eq(&a.particle, &b.particle) {
+		return false;
+	}
 	let types = (&a.obj_type, &b.obj_type);
 	match types {
-		(CollisionObjectType::HurtBox, CollisionObjectType::HurtBox) => false,
+		(CollisionObjectType::PushBox, CollisionObjectType::PushBox) => false,
+		// hitboxes only land on fighters, and hurt and block boxes only take hits and the hazard
+		// the push box does the standing on platforms
+		(CollisionObjectType::HitBox, CollisionObjectType::HurtBox) | (CollisionObjectType::HurtBox, CollisionObjectType::HitBox)
+		| (CollisionObjectType::HitBox, CollisionObjectType::BlockBox) | (CollisionObjectType::BlockBox, CollisionObjectType::HitBox)
+		| (CollisionObjectType::Hazard, CollisionObjectType::HurtBox) | (CollisionObjectType::HurtBox, CollisionObjectType::Hazard)
+		| (CollisionObjectType::Hazard, CollisionObjectType::BlockBox) | (CollisionObjectType::BlockBox, CollisionObjectType::Hazard) => a.rect.has_intersection(b.rect),
+		(CollisionObjectType::HitBox, _) | (_, CollisionObjectType::HitBox)
+		| (CollisionObjectType::HurtBox, _) | (_, CollisionObjectType::HurtBox)
+		| (CollisionObjectType::BlockBox, _) | (_, CollisionObjectType::BlockBox)
+		| (CollisionObjectType::PushBox, CollisionObjectType::Hazard) | (CollisionObjectType::Hazard, CollisionObjectType::PushBox) => false,
 		_ => a.rect.has_intersection(b.rect.clone())
 	}
 }
 
+// a hitbox touching a hurt box or block box, each move connects once
+// returns whether it connected
+fn land_attack(hitbox: &CollisionObject, target: &CollisionObject) -> bool {
+	let attack = match &hitbox.attack {
+		Some(attack) => attack,
+		None => return false,
+	};
+	if hitbox.particle.borrow().move_landed {
+		return false;
+	}
+	hitbox.particle.borrow_mut().move_landed = true;
+	let mut defender = target.particle.borrow_mut();
+	if target.obj_type == CollisionObjectType::BlockBox {
+		defender.update_health(attack.chip_damage);
+		defender.velocity.x = attack.pushback;
+		defender.stun = attack.blockstun;
+		defender.knockdown = false;
+	}
+	else {
+		defender.update_health(attack.damage);
+		defender.velocity.replace(&attack.knockback);
+		defender.stun = attack.hitstun;
+		defender.knockdown = attack.knockdown;
+	}
+	true
+}
+
+// what a hitbox does to whoever it touches, the move's frame data turned the way the attacker faces
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attack {
+	pub damage: i32,
+	pub chip_damage: i32,
+	pub hitstun: i32,
+	pub blockstun: i32,
+	pub pushback: f32,
+	pub knockback: PhysVec,
+	pub knockdown: bool,
+}
+
+impl Attack {
+	pub fn new(data: &MoveData, facing_right: bool) -> Attack {
+		let side = if facing_right { 1.0 } else { -1.0 };
+		Attack {
+			damage: data.damage,
+			chip_damage: data.chip_damage,
+			hitstun: data.hitstun,
+			blockstun: data.blockstun,
+			pushback: side * data.pushback,
+			knockback: PhysVec::new(side * data.knockback.0, data.knockback.1),
+			knockdown: data.knockdown,
+		}
+	}
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CollisionObjectType {
 	HitBox,
 	HurtBox,
 	BlockBox, // for if we want to implement it elsewhere
+	PushBox, // a fighter's whole body, against platforms and walls
     Hazard,
     Platform,
     Wall,
@@ -289,7 +435,8 @@ pub struct CollisionObject {
 	pub area: u32,
     pub rect: Rect,
 	pub noderef: WeakLink<CollisionObject>,
-	pub particle: Rc<RefCell<Particle>>
+	pub particle: Rc<RefCell<Particle>>,
+	pub attack: Option<Attack>, // set on hitboxes
 }
 
 
@@ -316,6 +463,7 @@ impl CollisionObject {
             rect,
 			noderef,
 			particle,
+			attack: None,
         }
     }
     pub fn new_from(obj_type: CollisionObjectType, rect: Rect, particle: Rc<RefCell<Particle>>) -> CollisionObject {
@@ -328,6 +476,7 @@ impl CollisionObject {
             rect,
 			noderef,
 			particle,
+			attack: None,
         }
     }
 	pub fn getNodeRef(&self) -> Option<NodeRef<CollisionObject>> {
@@ -1,14 +1,15 @@
 use std::rc::*;
 use std::cell::*;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use crate::physics::particle::Particle;
 use crate::physics::collisions::*;
 use crate::physics::vecmath::PhysVec;
 use crate::view::globals::*;
-use sdl2::rect::{Point, Rect}; // for hazard hitboxes
+use crate::physics::rect::{Point, Rect}; // for hazard hitboxes
 use bincode::{serialize, deserialize}; 
 use serde_derive::{Serialize, Deserialize};
 // maybe incorporate a
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Types {
 	Stalactites, // <- we can add more as we go if we want
 }
@@ -46,6 +47,20 @@ impl HazardVar{
 }
 
 
+// copy of a Hazard's simulation state (used by rollback and resync)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HazardSnapshot {
+	pub active: bool,
+	pub name: Types,
+	pub falling: bool,
+	pub fell: bool,
+	pub hit: bool,
+	pub fall_speed: f64,
+	pub damage: f64,
+	pub position: Point,
+	pub sprite: Rect,
+	pub particle: Particle,
+}
 
 // Structs
 pub struct Hazard {
@@ -85,9 +100,10 @@ impl Hazard {
 		self.particle.borrow_mut().integrate(FRAME_RATE as f32);
 		self.sprite.reposition(self.particle.borrow().to_point());
 	}
-	pub fn reset(&mut self, ) {
+	// back to the top somewhere new, picked with the match's rng so every peer picks the same place
+	pub fn reset<R: Rng>(&mut self, rng: &mut R) {
 		self.sprite.set_y(0);
-		self.sprite.set_x(thread_rng().gen_range(100..1080));
+		self.sprite.set_x(rng.gen_range(100..1080));
 		self.sprite.set_x(self.sprite.x.clamp(100, 1080));
 		self.particle.borrow_mut().reset_x();
 		self.particle.borrow_mut().reset_y();
@@ -96,15 +112,6 @@ impl Hazard {
 		self.fell = true;
 	}
 	
-	pub fn remove(link: &mut Option<RefCell<CollisionObject>>) {
-		link.take().map(|l| {
-			l.borrow().getNodeRef().map(|n| {
-				// println!("\nremoving {:?}\n", n);
-				n.remove()
-			});
-		});
-	}
-	
 	pub fn insert(&mut self, bvh: &BVHierarchy) {
 		// println!("inserting block box...");
 		self.hitbox = Some(bvh.insert(
@@ -116,7 +123,8 @@ impl Hazard {
 	pub fn update_bounding_box(&mut self, bvh: &BVHierarchy) {
 		// println!("updating...");
 		// println!("\nUpdating Hazard\n {:?}\n", self.hitbox);
-        Hazard::remove(&mut self.hitbox);
+        self.hitbox = None;
+        bvh.remove_where(|co| co.obj_type == CollisionObjectType::Hazard);
         self.insert(&bvh);
     }
     
@@ -127,8 +135,37 @@ impl Hazard {
 		else {Rect::new(0,0,0,0)}
     }
 
+    pub fn snapshot(&self) -> HazardSnapshot {
+		HazardSnapshot {
+			active: self.active,
+			name: self.name,
+			falling: self.falling,
+			fell: self.fell,
+			hit: self.hit,
+			fall_speed: self.fall_speed,
+			damage: self.damage,
+			position: self.position,
+			sprite: self.sprite,
+			particle: self.particle.borrow().snapshot(),
+		}
+	}
+
+	// particle is restored in place since the bvh shares it
+	pub fn restore(&mut self, snapshot: &HazardSnapshot) {
+		self.active = snapshot.active;
+		self.name = snapshot.name;
+		self.falling = snapshot.falling;
+		self.fell = snapshot.fell;
+		self.hit = snapshot.hit;
+		self.fall_speed = snapshot.fall_speed;
+		self.damage = snapshot.damage;
+		self.position = snapshot.position;
+		self.sprite = snapshot.sprite;
+		self.particle.borrow_mut().restore(&snapshot.particle);
+	}
+
     pub fn from_packet(&mut self, packet: &HazardVar){
-    	self.position.offset( (packet.pos_x - self.position.x()), (packet.pos_y - self.position.y()));
+    	self.position = Point::new(packet.pos_x, packet.pos_y);
     	self.sprite.set_x(packet.sprite_x);
     	self.sprite.set_y(packet.sprite_y);
     	self.sprite.set_width(packet.sprite_w);
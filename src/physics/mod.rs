@@ -2,4 +2,5 @@ pub mod collisions;
 pub mod hazard;
 pub mod nodes;
 pub mod vecmath;
-pub mod particle;
\ No newline at end of file
+pub mod particle;
+pub mod rect;
\ No newline at end of file
@@ -1,5 +1,5 @@
 #![allow(non_snake_case)]
-use sdl2::rect::Rect;
+use crate::physics::rect::Rect;
 use std::cell::{self, RefCell};
 use std::fmt;
 use std::rc::{Rc, Weak};
@@ -197,6 +197,39 @@ impl NodeRef<CollisionObject> {
 		}
 	}
 
+	// collect every collision object in the tree, left to right
+	pub fn getLeaves(&self, leaves: &mut Vec<RefCell<CollisionObject>>) {
+		if self.get().isLeaf() {
+			leaves.push(self.borrowbv());
+		}
+		else {
+			self.getLeftChild().getLeaves(leaves);
+			self.getRightChild().getLeaves(leaves);
+		}
+	}
+
+	// take out every leaf under this node that `matches` picks, folding each emptied branch into its sibling
+	// returns true if nothing is left under this node
+	pub fn prune(&self, matches: &dyn Fn(&CollisionObject) -> bool) -> bool {
+		if self.get().isLeaf() {
+			let found = matches(&self.get().bv.as_ref().unwrap().borrow());
+			if found { self.getMut().detatch(); }
+			return found;
+		}
+		let left = self.getLeftChild();
+		let right = self.getRightChild();
+		match (left.prune(matches), right.prune(matches)) {
+			(true, true) => {
+				self.getMut().detatch();
+				return true;
+			}
+			(true, false) => self.replace(&right),
+			(false, true) => self.replace(&left),
+			(false, false) => {}
+		}
+		false
+	}
+
 	pub fn getPotentialCollisions(&self, potential: &mut Vec<ParticleContact>, limit: i32) -> i32{
 		if self.get().isLeaf() || limit == 0 {return 0;}
 		self.getLeftRef().collidingWith(&self.getRightRef(), potential, limit)
@@ -1,6 +1,6 @@
 use crate::physics::vecmath::PhysVec;
 use crate::view::globals::*;
-use sdl2::rect::Point;
+use crate::physics::rect::Point;
 use bincode::{serialize, deserialize}; 
 use serde_derive::{Serialize, Deserialize};
 
@@ -13,8 +13,12 @@ pub struct Particle {
     pub inverse_mass: f32,
     pub force_accumulator: PhysVec,
     pub health: i32,
+    pub max_health: i32,    // what the fighter starts with, health never goes above it
     pub jump_count: i32,
     pub damage: i32,
+    pub stun: i32,          // frames of hitstun or blockstun left, input is locked until it runs out
+    pub move_landed: bool,  // the current attack already hit or was blocked, it can't connect again
+    pub knockdown: bool,    // the hit that stunned the fighter puts it on the ground
 }
 
 impl Particle {
@@ -29,8 +33,12 @@ impl Particle {
             inverse_mass,
             force_accumulator: zero.clone(),
             health: health,
+            max_health: health,
             damage: damage,
             jump_count: 0,
+            stun: 0,
+            move_landed: false,
+            knockdown: false,
         }
     }
 
@@ -56,7 +64,7 @@ impl Particle {
 
     pub fn update_health(&mut self, damage: i32) {
         self.health -= damage;
-        self.health = self.health.clamp(0, 270);
+        self.health = self.health.clamp(0, self.max_health);
         println!("Updating health to {}", self.health);
     }
 
@@ -102,6 +110,26 @@ impl Particle {
     pub fn add_force(&mut self, force: &PhysVec) {
         self.force_accumulator.add_vec(force);
     }
+    // Copy the current state so it can be restored later (used for rollback)
+    pub fn snapshot(&self) -> Particle {
+        self.clone()
+    }
+    // Overwrite state in place so shared Rc<RefCell<Particle>> handles stay valid
+    pub fn restore(&mut self, snapshot: &Particle) {
+        self.position.replace(&snapshot.position);
+        self.velocity.replace(&snapshot.velocity);
+        self.acceleration.replace(&snapshot.acceleration);
+        self.damping = snapshot.damping;
+        self.inverse_mass = snapshot.inverse_mass;
+        self.force_accumulator.replace(&snapshot.force_accumulator);
+        self.health = snapshot.health;
+        self.max_health = snapshot.max_health;
+        self.jump_count = snapshot.jump_count;
+        self.damage = snapshot.damage;
+        self.stun = snapshot.stun;
+        self.move_landed = snapshot.move_landed;
+        self.knockdown = snapshot.knockdown;
+    }
     // Add force to the accumulator
     pub fn add_force_comps(&mut self, x: f32, y: f32) {
         self.force_accumulator.add_vec(&PhysVec::new(x, y));
@@ -0,0 +1,111 @@
+use serde_derive::{Serialize, Deserialize};
+
+// Rect and Point for the simulation
+// mirrors the parts of sdl2::rect the physics uses so the simulation (and the headless
+// server) builds without SDL. The view converts with .into() when drawing.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Point {
+    pub fn new(x: i32, y: i32) -> Point { Point { x, y } }
+    pub fn x(&self) -> i32 { self.x }
+    pub fn y(&self) -> i32 { self.y }
+    // returns a moved copy, like sdl2's Point::offset
+    pub fn offset(&self, x: i32, y: i32) -> Point { Point::new(self.x + x, self.y + y) }
+}
+
+// same layout as SDL_Rect, so `rect.x` works like it did with sdl2
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+// sdl2 never makes a zero sized rect, keep that behaviour
+fn clamp_size(size: u32) -> i32 {
+    if size == 0 { 1 }
+    else if size > i32::MAX as u32 { i32::MAX }
+    else { size as i32 }
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Rect {
+        Rect { x, y, w: clamp_size(width), h: clamp_size(height) }
+    }
+
+    pub fn x(&self) -> i32 { self.x }
+    pub fn y(&self) -> i32 { self.y }
+    pub fn width(&self) -> u32 { self.w as u32 }
+    pub fn height(&self) -> u32 { self.h as u32 }
+    pub fn size(&self) -> (u32, u32) { (self.width(), self.height()) }
+    pub fn left(&self) -> i32 { self.x }
+    pub fn right(&self) -> i32 { self.x + self.w }
+    pub fn top(&self) -> i32 { self.y }
+    pub fn bottom(&self) -> i32 { self.y + self.h }
+    pub fn top_left(&self) -> Point { Point::new(self.x, self.y) }
+
+    pub fn set_x(&mut self, x: i32) { self.x = x; }
+    pub fn set_y(&mut self, y: i32) { self.y = y; }
+    pub fn set_width(&mut self, width: u32) { self.w = clamp_size(width); }
+    pub fn set_height(&mut self, height: u32) { self.h = clamp_size(height); }
+    pub fn reposition(&mut self, p: Point) { self.x = p.x; self.y = p.y; }
+
+    pub fn has_intersection(&self, other: Rect) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    pub fn intersection(&self, other: Rect) -> Option<Rect> {
+        let left = self.left().max(other.left());
+        let right = self.right().min(other.right());
+        let top = self.top().max(other.top());
+        let bottom = self.bottom().min(other.bottom());
+        if right <= left || bottom <= top { return None; }
+        Some(Rect::new(left, top, (right - left) as u32, (bottom - top) as u32))
+    }
+
+    // smallest rect containing both
+    pub fn union(&self, other: Rect) -> Rect {
+        let left = self.left().min(other.left());
+        let right = self.right().max(other.right());
+        let top = self.top().min(other.top());
+        let bottom = self.bottom().max(other.bottom());
+        Rect::new(left, top, (right - left) as u32, (bottom - top) as u32)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Rect> for sdl2::rect::Rect {
+    fn from(r: Rect) -> sdl2::rect::Rect { sdl2::rect::Rect::new(r.x, r.y, r.width(), r.height()) }
+}
+
+#[cfg(feature = "gui")]
+impl From<Point> for sdl2::rect::Point {
+    fn from(p: Point) -> sdl2::rect::Point { sdl2::rect::Point::new(p.x, p.y) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn testIntersection() {
+        let a = Rect::new(0, 0, 10, 10);
+        let b = Rect::new(5, 5, 10, 10);
+
+        assert_eq!(a.intersection(b), Some(Rect::new(5, 5, 5, 5)));
+        assert_eq!(a.union(b), Rect::new(0, 0, 15, 15));
+        // touching edges do not intersect
+        assert!(!a.has_intersection(Rect::new(10, 0, 5, 5)));
+    }
+
+    #[test]
+    pub fn testZeroSize() {
+        assert_eq!(Rect::new(0, 0, 0, 0).size(), (1, 1));
+    }
+}
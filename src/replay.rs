@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::fs;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::simulation::{FrameEvents, SimConfig, Simulation};
+
+// every replay file starts with this, anything else is turned away before bincode sees it
+pub const REPLAY_MAGIC: &[u8; 4] = b"SCFR";
+// bump whenever the file layout or the way a match plays out changes,
+// an old replay played through a different simulation would quietly show a different match
+pub const REPLAY_VERSION: u32 = 6;
+
+// playback speeds, as a multiple of the game's own pace
+pub const SPEEDS: [f64; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const NORMAL_SPEED: usize = 2;
+
+// a whole match: how it was set up and what both players pressed on every frame from the first
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub config: SimConfig,
+    pub frames: Vec<(HashSet<u8>, HashSet<u8>)>, // converted inputs (see inputHandler::convert_input) for player 1 and 2
+}
+
+impl Replay {
+    pub fn new(config: SimConfig) -> Replay {
+        Replay { config, frames: Vec::new() }
+    }
+
+    pub fn len(&self) -> u32 { self.frames.len() as u32 }
+    pub fn is_empty(&self) -> bool { self.frames.is_empty() }
+
+    // the inputs the next frame was played with
+    pub fn record(&mut self, input_1: &HashSet<u8>, input_2: &HashSet<u8>) {
+        self.frames.push((input_1.clone(), input_2.clone()));
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.extend_from_slice(&REPLAY_VERSION.to_le_bytes());
+        bytes.extend(bincode::serialize(self).unwrap());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Replay, String> {
+        if bytes.len() < 8 || &bytes[..4] != REPLAY_MAGIC {
+            return Err("not a replay file".to_string());
+        }
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&bytes[4..8]);
+        let version = u32::from_le_bytes(version);
+        if version != REPLAY_VERSION {
+            return Err(format!("replay is version {}, this build plays version {}", version, REPLAY_VERSION));
+        }
+        let replay: Replay = bincode::deserialize(&bytes[8..]).map_err(|e| format!("replay is damaged: {}", e))?;
+        replay.config.check()?;
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("couldn't write {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let bytes = fs::read(path).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+        Replay::from_bytes(&bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+// a replay being played back through the simulation, the viewer calls tick once a displayed frame
+pub struct Playback {
+    pub replay: Replay,
+    pub sim: Simulation,
+    pub paused: bool,
+    speed: usize, // index into SPEEDS
+    owed: f64,    // frames the speed has built up that haven't been stepped yet
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Result<Playback, String> {
+        let sim = Simulation::new(replay.config)?;
+        Ok(Playback { replay, sim, paused: false, speed: NORMAL_SPEED, owed: 0.0 })
+    }
+
+    pub fn is_finished(&self) -> bool { self.sim.frame() >= self.replay.len() }
+    pub fn speed(&self) -> f64 { SPEEDS[self.speed] }
+
+    pub fn faster(&mut self) { self.speed = (self.speed + 1).min(SPEEDS.len() - 1); }
+    pub fn slower(&mut self) { self.speed = self.speed.saturating_sub(1); }
+    pub fn toggle_pause(&mut self) { self.paused = !self.paused; }
+
+    // back to the first frame, keeping the speed and pause
+    pub fn restart(&mut self) -> Result<(), String> {
+        self.sim = Simulation::new(self.replay.config)?;
+        self.owed = 0.0;
+        Ok(())
+    }
+
+    // one frame forward, None at the end
+    pub fn step(&mut self) -> Option<FrameEvents> {
+        let (input_1, input_2) = self.replay.frames.get(self.sim.frame() as usize)?;
+        Some(self.sim.step(input_1, input_2))
+    }
+
+    // the frames due this displayed frame at the current speed, none while paused
+    pub fn tick(&mut self) -> Vec<FrameEvents> {
+        if self.paused { return Vec::new(); }
+        self.owed += self.speed();
+        let mut events = Vec::new();
+        while self.owed >= 1.0 {
+            self.owed -= 1.0;
+            match self.step() {
+                Some(frame) => events.push(frame),
+                None => { self.owed = 0.0; break; },
+            }
+        }
+        events
+    }
+
+    // the viewer's status line
+    pub fn status(&self) -> String {
+        format!("frame {}/{}  x{}{}", self.sim.frame(), self.replay.len(), self.speed(), if self.paused { "  paused" } else { "" })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::networking::checksum::StateDigest;
+
+    fn keys(keys: &[u8]) -> HashSet<u8> { keys.iter().cloned().collect() }
+
+    // a short match of walking, jumping and kicking
+    fn record(frames: u32) -> (Replay, StateDigest) {
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+        let mut replay = Replay::new(SimConfig::default());
+        for frame in 0..frames {
+            let input_1 = keys(&[[2, 4, 5, 0][(frame / 10 % 4) as usize]]);
+            let input_2 = keys(&[[1, 7, 0][(frame / 15 % 3) as usize]]);
+            replay.record(&input_1, &input_2);
+            sim.step(&input_1, &input_2);
+        }
+        (replay, StateDigest::of(&sim.save()))
+    }
+
+    #[test]
+    pub fn testFileRoundTrip() {
+        let (replay, _) = record(50);
+        assert_eq!(Replay::from_bytes(&replay.to_bytes()), Ok(replay.clone()));
+
+        let mut old = replay.to_bytes();
+        old[4] = old[4].wrapping_add(1);
+        assert!(Replay::from_bytes(&old).unwrap_err().contains("version"));
+        assert_eq!(Replay::from_bytes(b"GIF89a..."), Err("not a replay file".to_string()));
+        assert!(Replay::from_bytes(&replay.to_bytes()[..20]).is_err());
+
+        // a character this install doesn't have
+        let mut stranger = replay.clone();
+        stranger.config.p2 = crate::characters::registry::CharacterId(999);
+        assert!(Replay::from_bytes(&stranger.to_bytes()).unwrap_err().starts_with("no character has id 999"));
+    }
+
+    #[test]
+    pub fn testPlaybackMatchesRecording() {
+        let (replay, recorded) = record(120);
+        let mut playback = Playback::new(Replay::from_bytes(&replay.to_bytes()).unwrap()).unwrap();
+        while !playback.is_finished() {
+            playback.tick();
+        }
+        assert_eq!(playback.sim.frame(), 120);
+        assert_eq!(StateDigest::of(&playback.sim.save()), recorded);
+        assert!(playback.tick().is_empty());
+    }
+
+    #[test]
+    pub fn testControls() {
+        let (replay, _) = record(60);
+        let mut playback = Playback::new(replay).unwrap();
+        assert_eq!(playback.tick().len(), 1);
+
+        playback.slower();
+        playback.slower();
+        // a quarter speed steps every fourth tick
+        let stepped: usize = (0..8).map(|_| playback.tick().len()).sum();
+        assert_eq!(stepped, 2);
+
+        playback.toggle_pause();
+        assert!(playback.tick().is_empty());
+        assert_eq!(playback.step().map(|events| events.frame), Some(3));
+        assert_eq!(playback.status(), "frame 4/60  x0.25  paused");
+
+        for _ in 0..10 { playback.faster(); }
+        assert_eq!(playback.speed(), 4.0);
+        playback.toggle_pause();
+        assert_eq!(playback.tick().len(), 4);
+
+        playback.restart().unwrap();
+        assert_eq!(playback.sim.frame(), 0);
+    }
+}
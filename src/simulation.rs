@@ -0,0 +1,358 @@
+use std::collections::HashSet;
+use rand::RngCore;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::characters::characterAbstract::Fighter;
+use crate::characters::registry::{self, CharacterId};
+use crate::input;
+use crate::networking::rollback::FrameSnapshot;
+use crate::physics::collisions::{build_stage, BVHierarchy};
+use crate::physics::hazard::Hazard;
+use crate::physics::vecmath::PhysVec;
+use crate::view::globals::FRAME_RATE;
+
+// where player 2 starts, relative to player 1
+const P2_SPAWN_OFFSET: f32 = 300.0;
+
+// how a match is set up, saved with replays and sent by the server with Start
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimConfig {
+    pub p1: CharacterId,
+    pub p2: CharacterId,
+    pub seed: u64, // for MatchRng
+}
+
+impl Default for SimConfig {
+    // Python against Java
+    fn default() -> SimConfig {
+        SimConfig { p1: CharacterId(1), p2: CharacterId(2), seed: 0 }
+    }
+}
+
+impl SimConfig {
+    // whether every character it names was found, a match with one that wasn't can't be played here
+    pub fn check(&self) -> Result<(), String> {
+        for id in [self.p1, self.p2].iter() {
+            if registry::global()?.get(*id).is_none() {
+                return Err(format!("no character has id {}, is {} the same as the other player's?", id.0, registry::CHARACTERS_DIR));
+            }
+        }
+        Ok(())
+    }
+}
+
+// the match's own random numbers, anything that changes how the match plays out draws from here
+// and anything that doesn't (like the music) uses thread_rng so it can't shift the sequence
+// SplitMix64: the same numbers on every platform and its whole state is one u64 to snapshot
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchRng {
+    state: u64,
+}
+
+impl MatchRng {
+    pub fn new(seed: u64) -> MatchRng { MatchRng { state: seed } }
+    pub fn state(&self) -> u64 { self.state }
+}
+
+impl RngCore for MatchRng {
+    fn next_u32(&mut self) -> u32 { (self.next_u64() >> 32) as u32 }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// what happened during one step, for whoever plays the sounds and draws the end screen
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameEvents {
+    pub frame: u32,          // the frame that was just simulated
+    pub hit: bool,           // someone was hit
+    pub hazard_reset: bool,  // the stalactite landed and went back up
+    pub knockout: Option<u8>, // the player knocked out this frame
+}
+
+// one match with no window, sound or socket, stepped a frame at a time
+// the local game, the clients, the server and anything replaying inputs all run matches through this,
+// so the server and every client stay in step and rollback re-simulation matches what was predicted
+pub struct Simulation {
+    pub fighter1: Fighter,
+    pub fighter2: Fighter,
+    pub hazard: Hazard,
+    pub collisions: BVHierarchy,
+    pub rng: MatchRng,
+    frame: u32,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Result<Simulation, String> {
+        let fighter1 = registry::character(config.p1)?.build();
+        let fighter2 = registry::character(config.p2)?.build();
+        //this is just to make fighter2 spawn a little to the right of fighter
+        fighter2.char_state.particle.borrow_mut().position.replace(&PhysVec::new(P2_SPAWN_OFFSET, 0.0));
+
+        Ok(Simulation {
+            fighter1,
+            fighter2,
+            hazard: Hazard::new(),
+            collisions: build_stage(),
+            rng: MatchRng::new(config.seed),
+            frame: 0,
+        })
+    }
+
+    // the next frame step will simulate
+    pub fn frame(&self) -> u32 { self.frame }
+
+    // the first player out of health, once the round is over
+    pub fn knocked_out(&self) -> Option<u8> {
+        if self.fighter1.char_state.health() <= 0 { Some(1) }
+        else if self.fighter2.char_state.health() <= 0 { Some(2) }
+        else { None }
+    }
+
+    // advance the match by one frame with both players' converted inputs (see inputHandler::convert_input)
+    pub fn step(&mut self, input_1: &HashSet<u8>, input_2: &HashSet<u8>) -> FrameEvents {
+        let standing = self.knocked_out().is_none();
+
+        //process player movement
+        input::inputHandler::keyboard_input(input_1, &mut self.fighter1);
+        input::inputHandler::keyboard_input(input_2, &mut self.fighter2);
+
+        //select frame to be rendered
+        self.fighter1.char_state.advance_frame();
+        self.fighter2.char_state.advance_frame();
+
+        //move character based on current frame
+        input::movement::move_char(&mut self.fighter1);
+        input::movement::move_char(&mut self.fighter2);
+
+        self.collisions.clear_moving();
+        self.fighter1.char_state.update_bounding_boxes(&self.collisions);
+        self.fighter2.char_state.update_bounding_boxes(&self.collisions);
+        self.hazard.update_bounding_box(&self.collisions);
+
+        let (hazard_reset, hit) = self.collisions.resolve_collisions();
+        self.fighter1.char_state.particle.borrow_mut().integrate(FRAME_RATE as f32);
+        self.fighter2.char_state.particle.borrow_mut().integrate(FRAME_RATE as f32);
+        self.hazard.particle.borrow_mut().integrate(FRAME_RATE as f32);
+
+        //move hazard
+        self.hazard.update_position();
+        if hazard_reset {
+            self.hazard.reset(&mut self.rng);
+        }
+
+        let events = FrameEvents {
+            frame: self.frame,
+            hit,
+            hazard_reset,
+            knockout: if standing { self.knocked_out() } else { None },
+        };
+        self.frame += 1;
+        events
+    }
+
+    // the state at the start of the next frame, for rollback and resyncs
+    pub fn save(&self) -> FrameSnapshot {
+        FrameSnapshot::save(self.frame, &self.fighter1, &self.fighter2, &self.hazard, &self.rng, &self.collisions)
+    }
+
+    // rewind (or jump ahead) to a saved frame
+    pub fn load(&mut self, snapshot: &FrameSnapshot) {
+        snapshot.load(&mut self.fighter1, &mut self.fighter2, &mut self.hazard, &mut self.rng, &self.collisions);
+        self.frame = snapshot.frame;
+    }
+
+    // keep the collision boxes where the fighters are after their state was set from outside, like a spectator's
+    pub fn update_bounding_boxes(&mut self) {
+        self.collisions.clear_moving();
+        self.fighter1.char_state.update_bounding_boxes(&self.collisions);
+        self.fighter2.char_state.update_bounding_boxes(&self.collisions);
+        self.hazard.update_bounding_box(&self.collisions);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::networking::checksum::StateDigest;
+
+    fn keys(keys: &[u8]) -> HashSet<u8> { keys.iter().cloned().collect() }
+
+    #[test]
+    pub fn testStepCountsFrames() {
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+        assert_eq!(sim.fighter2.character, CharacterId(2));
+        for frame in 0..10 {
+            assert_eq!(sim.step(&keys(&[2]), &keys(&[])).frame, frame);
+        }
+        assert_eq!(sim.frame(), 10);
+        assert_eq!(sim.knocked_out(), None);
+    }
+
+    #[test]
+    pub fn testSaveAndLoad() {
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+        for _ in 0..20 { sim.step(&keys(&[2]), &keys(&[1])); }
+        let saved = sim.save();
+        for _ in 0..20 { sim.step(&keys(&[4, 5]), &keys(&[7])); }
+        let ahead = StateDigest::of(&sim.save());
+
+        // going back and playing the same inputs gets to the same place
+        sim.load(&saved);
+        assert_eq!(sim.frame(), 20);
+        for _ in 0..20 { sim.step(&keys(&[4, 5]), &keys(&[7])); }
+        assert_eq!(StateDigest::of(&sim.save()), ahead);
+    }
+
+    #[test]
+    pub fn testKeyOrder() {
+        // left and right held together, the lower key wins whatever order each set keeps them in
+        // so peers given the same inputs play the same frame
+        for _ in 0..20 {
+            let mut sim = Simulation::new(SimConfig::default()).unwrap();
+            sim.step(&keys(&[2, 1]), &keys(&[1, 2]));
+            assert_eq!(sim.fighter1.char_state.direction, input::movement::Direction::Left);
+            assert_eq!(sim.fighter2.char_state.direction, input::movement::Direction::Left);
+        }
+    }
+
+    #[test]
+    pub fn testKnockout() {
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+        sim.step(&keys(&[]), &keys(&[]));
+        sim.fighter2.char_state.particle.borrow_mut().health = 0;
+        assert_eq!(sim.knocked_out(), Some(2));
+        // a knockout from before the step isn't reported again
+        assert_eq!(sim.step(&keys(&[]), &keys(&[])).knockout, None);
+    }
+
+    // how long `attack` steps for once the key is pressed, all of the light punch
+    const ATTACK_FRAMES: i32 = 17;
+
+    // the frames player 1's `attack` (a key) has its hitbox out, with player 2 `gap` away holding `defend`
+    fn attack(attack: u8, gap: f32, defend: &[u8]) -> (Simulation, Vec<i32>) {
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+        // both on the ground, where player 2 spawns
+        sim.fighter1.char_state.particle.borrow_mut().position.replace(&PhysVec::new(-300.0, 0.0));
+        sim.fighter2.char_state.particle.borrow_mut().position.replace(&PhysVec::new(-300.0 + gap, 0.0));
+        for _ in 0..30 { sim.step(&keys(&[]), &keys(&[])); }
+        sim.fighter1.char_state.direction = if gap > 0.0 { input::movement::Direction::Right } else { input::movement::Direction::Left };
+        let mut out = Vec::new();
+        for frame in 1..=ATTACK_FRAMES {
+            let pressed = [attack];
+            sim.step(&keys(if frame == 1 { &pressed } else { &[] }), &keys(defend));
+            if !sim.fighter1.char_state.hitboxes.is_empty() { out.push(frame); }
+        }
+        (sim, out)
+    }
+
+    #[test]
+    pub fn testAttackFrameData() {
+        use crate::animation::sprites::State;
+        // out of reach the hitbox is there for the active frames only, after startup
+        let (sim, out) = attack(7, 600.0, &[]);
+        assert_eq!(out, vec![6, 7, 8, 9, 10]);
+        assert_eq!(sim.fighter2.char_state.health(), 270);
+
+        // up close it lands once on its first active frame, for the move's damage and hitstun
+        let (sim, out) = attack(7, 100.0, &[]);
+        assert_eq!(out, vec![6]);
+        assert_eq!(sim.fighter2.char_state.health(), 260);
+        // the stun has been running down since the hit, for the frames after it that `attack` stepped
+        let lpunch = sim.fighter1.char_state.animations[&State::LPunch].attack.clone().unwrap();
+        assert_eq!(sim.fighter2.char_state.particle.borrow().stun, lpunch.hitstun - (ATTACK_FRAMES - out[0]));
+        assert!(sim.fighter2.char_state.x() > -200);
+    }
+
+    #[test]
+    pub fn testHitboxShapes() {
+        // the low kick reaches a standing fighter on either side, boxes turn with the attacker
+        assert_eq!(attack(5, 100.0, &[]).0.fighter2.char_state.health(), 255);
+        assert_eq!(attack(5, -100.0, &[]).0.fighter2.char_state.health(), 255);
+        // but goes under one who jumped
+        assert_eq!(attack(5, 100.0, &[4]).0.fighter2.char_state.health(), 270);
+        // and a block takes only the chip damage
+        assert_eq!(attack(5, 100.0, &[3]).0.fighter2.char_state.health(), 268);
+    }
+
+    #[test]
+    pub fn testStunStates() {
+        use crate::animation::sprites::State;
+        // locked in hitstun for the light punch's 12 frames, walking doesn't get out of it
+        let (mut sim, _) = attack(7, 100.0, &[]);
+        let mut states = vec![sim.fighter2.char_state.state];
+        for _ in 0..2 {
+            sim.step(&keys(&[]), &keys(&[2]));
+            states.push(sim.fighter2.char_state.state);
+        }
+        assert_eq!(states, vec![State::Hitstun, State::Hitstun, State::Walk]);
+
+        // blocking stays guarded through the blockstun
+        let (sim, _) = attack(6, 100.0, &[3]);
+        assert_eq!(sim.fighter2.char_state.state, State::Blockstun);
+        assert_eq!(sim.fighter2.char_state.health(), 266);
+
+        // the high kick knocks down, the fighter gets up once the stun is over and only then acts again
+        let (mut sim, _) = attack(6, 100.0, &[]);
+        let mut states = vec![sim.fighter2.char_state.state];
+        for _ in 0..60 {
+            sim.step(&keys(&[]), &keys(&[2]));
+            if states.last() != Some(&sim.fighter2.char_state.state) { states.push(sim.fighter2.char_state.state); }
+        }
+        assert_eq!(states, vec![State::Knockdown, State::GetUp, State::Walk]);
+    }
+
+    // where the stalactite was put back each time it landed
+    fn drops(seed: u64, frames: u32) -> Vec<i32> {
+        let mut sim = Simulation::new(SimConfig { seed, ..SimConfig::default() }).unwrap();
+        let mut drops = Vec::new();
+        for _ in 0..frames {
+            if sim.step(&keys(&[]), &keys(&[])).hazard_reset { drops.push(sim.hazard.sprite.x()); }
+        }
+        drops
+    }
+
+    #[test]
+    pub fn testSeededHazard() {
+        let first = drops(7, 600);
+        assert!(first.len() >= 2);
+        assert_eq!(drops(7, 600), first);
+        assert_ne!(drops(8, 600), first);
+
+        let mut rng = MatchRng::new(7);
+        let mut copy = rng.clone();
+        assert_eq!(rng.next_u64(), copy.next_u64());
+        assert_ne!(rng.state(), MatchRng::new(7).state());
+    }
+
+    #[test]
+    pub fn testHazardFall() {
+        // integrated with the fighters and again as it moves, the same as the local game always did
+        let mut sim = Simulation::new(SimConfig::default()).unwrap();
+        let mut alone = Hazard::new();
+        for _ in 0..10 {
+            sim.step(&keys(&[]), &keys(&[]));
+            alone.particle.borrow_mut().integrate(FRAME_RATE as f32);
+            alone.update_position();
+        }
+        assert_eq!(sim.hazard.particle.borrow().position, alone.particle.borrow().position);
+        assert_eq!(sim.hazard.sprite, alone.sprite);
+    }
+}
@@ -2,8 +2,9 @@ extern crate sdl2;
 
 use sdl2::keyboard::TextInputUtil;
 use sdl2::rect::Rect;
-use sdl2::render::{WindowCanvas, Texture, TextureCreator};
+use sdl2::render::{BlendMode, WindowCanvas, Texture, TextureCreator};
 use sdl2::pixels::Color;
+use sdl2::ttf::Font;
 use std::collections::HashMap;
 use sdl2::video::WindowContext;
 use sdl2::rect::Point;
@@ -15,12 +16,35 @@ use crate::physics;
 
 use super::globals::*;
 
+pub const FONT_PATH: &str = "src/assets/fonts/DejaVuSansMono.ttf";
+pub const FONT_SIZE: u16 = 16;
+const OVERLAY_W: u32 = 560;
+const STATS_W: u32 = 300;
+const STATS_TOP: i32 = 60; // under the left health bar
+const HEALTHBAR_W: u32 = 300;
+const HEALTHBAR_LOW: u32 = 30; // fill still drawn at 1 health
+
+// how many pixels of a health bar's fill to draw, scaled by the fighter's own most health
+fn health_fill(health: i32, max_health: i32) -> u32 {
+	let health = health.clamp(0, max_health) as u32;
+	HEALTHBAR_LOW + (HEALTHBAR_W - HEALTHBAR_LOW) * health / max_health.max(1) as u32
+}
+
+// text drawn over the match: chat in the bottom left corner, network stats in the top left
+pub struct Overlay<'a, 'ttf> {
+	pub font: &'a Font<'ttf, 'static>,
+	pub lines: Vec<String>,
+	pub prompt: Option<String>, // what the player is typing, if they are
+	pub stats: Option<Vec<String>>, // the network stats HUD, if it is toggled on
+}
+
 pub struct SDLCore{
 	sdl_cxt: sdl2::Sdl,
 	pub wincan: sdl2::render::WindowCanvas,
 	pub event_pump: sdl2::EventPump,
 	pub audio: sdl2::AudioSubsystem,
 	pub timer: sdl2::TimerSubsystem,
+	pub text_input: TextInputUtil,
 }
 
 impl SDLCore{
@@ -37,6 +61,10 @@ impl SDLCore{
     let audio = sdl_cxt.audio()?;
     let mut timer = sdl_cxt.timer()?;
 
+		// typed text only arrives while the chat prompt is open
+		let text_input = video_subsys.text_input();
+		text_input.stop();
+
 		let window = video_subsys.window(title, width, height).build().map_err(|e| e.to_string())?;
 		let wincan = window.into_canvas().accelerated();
 
@@ -60,6 +88,7 @@ impl SDLCore{
 			event_pump,
 			audio,
 			timer,
+			text_input,
 		})
 	}
 
@@ -76,6 +105,7 @@ impl SDLCore{
 				healthbar_right: &Texture,
 				healthbar_fill_left: &Texture,
 				healthbar_fill_right: &Texture,
+				overlay: Option<&Overlay>,
 				) -> Result<(), String>{
 
 		// set canvas height
@@ -92,14 +122,16 @@ impl SDLCore{
 
 		// fill health bars
 		if fighter.char_state.health() > 0 {
+			let fill = health_fill(fighter.char_state.health(), fighter.char_state.max_health());
 			self.wincan.copy(healthbar_fill_left, 
-				Rect::new(0,0, 300-(270-fighter.char_state.health() as u32), 40), 
-				Rect::new(3,10, 300-(270-fighter.char_state.health() as u32), 40))?;
+				Rect::new(0,0, fill, 40), 
+				Rect::new(3,10, fill, 40))?;
 		}
 		if fighter2.char_state.health() > 0 {
+			let fill = health_fill(fighter2.char_state.health(), fighter2.char_state.max_health());
 			self.wincan.copy(healthbar_fill_right, 
-				Rect::new(270-fighter2.char_state.health(),0, 300-(270-fighter2.char_state.health() as u32), 40), 
-				Rect::new(CAM_W as i32-(300-(270-fighter2.char_state.health()))-3,10, 300-(270-fighter2.char_state.health() as u32), 40))?;
+				Rect::new((HEALTHBAR_W-fill) as i32,0, fill, 40), 
+				Rect::new(CAM_W as i32-fill as i32-3,10, fill, 40))?;
 		}
 		self.wincan.copy(healthbar_left, None, Rect::new(3,10, 300, 40))?;
 		self.wincan.copy(healthbar_right, None, Rect::new(CAM_W as i32-300-3,10, 300, 40))?;
@@ -128,9 +160,9 @@ impl SDLCore{
         // (0, 0) cordinate = center of the scren
 		// make new rect and screen pos //
 
-        let screen_position = fighter.char_state.particle.borrow().to_point() + Point::new(width as i32 / 2, height as i32 / 2);
+        let screen_position = Point::from(fighter.char_state.particle.borrow().to_point()) + Point::new(width as i32 / 2, height as i32 / 2);
         let screen_rect = Rect::from_center(screen_position, frame_width, frame_height);
-		let screen_position2 = fighter2.char_state.particle.borrow().to_point() + Point::new(width as i32 / 2, height as i32 / 2);
+		let screen_position2 = Point::from(fighter2.char_state.particle.borrow().to_point()) + Point::new(width as i32 / 2, height as i32 / 2);
         let screen_rect2 = Rect::from_center(screen_position2, frame_width, frame_height);
 
 
@@ -154,12 +186,18 @@ impl SDLCore{
 		// self.wincan.copy_ex(texture2, current_frame2, screen_rect2, 0.0, None, true, false)?;
 		self.wincan.copy(hazard_texture, hazard_frame, hazard_screen_rectangle)?;
 		self.wincan.set_draw_color(Color::RED);
-		self.wincan.draw_rects(&[fighter.char_state.get_bb(), fighter2.char_state.get_bb(), hazard.get_bb()])?;
+		let mut boxes: Vec<sdl2::rect::Rect> = fighter.char_state.hit_and_hurt_boxes().into_iter()
+			.chain(fighter2.char_state.hit_and_hurt_boxes()).map(|b| b.into()).collect();
+		boxes.push(hazard.get_bb().into());
+		self.wincan.draw_rects(&boxes)?;
 		if end.is_some() {
 			self.wincan.copy(end.unwrap(), 
 				Rect::new((700-415)/2,(300-155)/2,415, 155), 
 				Rect::new((CAM_W as i32-415)/2, (CAM_H as i32-155)/2, 415, 155))?;
 		}
+		if let Some(overlay) = overlay {
+			self.draw_overlay(overlay)?;
+		}
         self.wincan.present();
 
         /*
@@ -171,6 +209,44 @@ impl SDLCore{
 
         Ok(())
 	} // closing render fun
+
+	// chat with the newest line at the bottom, and the stats if they are on
+	fn draw_overlay(&mut self, overlay: &Overlay) -> Result<(), String>{
+		if let Some(stats) = overlay.stats.as_ref() {
+			let lines: Vec<(&str, Color)> = stats.iter().map(|line| (line.as_str(), Color::GREEN)).collect();
+			self.draw_text_box(overlay.font, &lines, STATS_TOP, STATS_W)?;
+		}
+
+		let prompt = overlay.prompt.as_ref().map(|text| format!("> {}_", text));
+		let mut lines: Vec<(&str, Color)> = overlay.lines.iter().map(|line| (line.as_str(), Color::WHITE)).collect();
+		if let Some(prompt) = prompt.as_ref() {
+			lines.push((prompt.as_str(), Color::YELLOW));
+		}
+		let top = CAM_H as i32 - 20 - overlay.font.recommended_line_spacing() * lines.len() as i32;
+		self.draw_text_box(overlay.font, &lines, top, OVERLAY_W)
+	}
+
+	// a dark box on the left with one line of text per row
+	fn draw_text_box(&mut self, font: &Font, lines: &[(&str, Color)], top: i32, width: u32) -> Result<(), String>{
+		if lines.is_empty() {
+			return Ok(());
+		}
+
+		let line_height = font.recommended_line_spacing();
+		self.wincan.set_blend_mode(BlendMode::Blend);
+		self.wincan.set_draw_color(Color::RGBA(0, 0, 0, 160));
+		self.wincan.fill_rect(Rect::new(10, top - 5, width, (line_height * lines.len() as i32 + 10) as u32))?;
+		self.wincan.set_blend_mode(BlendMode::None);
+
+		let texture_creator = self.wincan.texture_creator();
+		for (row, (text, color)) in lines.iter().enumerate() {
+			let surface = font.render(text).blended(*color).map_err(|e| e.to_string())?;
+			let texture = texture_creator.create_texture_from_surface(&surface).map_err(|e| e.to_string())?;
+			let query = texture.query();
+			self.wincan.copy(&texture, None, Rect::new(20, top + line_height * row as i32, query.width, query.height))?;
+		}
+		Ok(())
+	}
 /*
     // NOT FUNCTIONING YET
     fn load_textures(texture_creator: &'t TextureCreator<WindowContext>,
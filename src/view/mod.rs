@@ -1,3 +1,5 @@
+// SDL window and rendering, not needed by the headless server
+#[cfg(feature = "gui")]
 pub mod core;
 
 pub mod globals {
@@ -0,0 +1,312 @@
+#![allow(non_snake_case)]
+// a server and two scripted clients in one process, talking over SimSockets
+// run with --nocapture to see what the simulated network did
+
+use std::collections::HashSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use street_code_fighter::characters::registry;
+use street_code_fighter::networking::checksum::StateDigest;
+use street_code_fighter::networking::netsim::{Conditions, SimSocket};
+use street_code_fighter::networking::protocol::{self, Channel, ClientHello, Cookie, JoinRequest, MatchStart, MessageType, RoomChoice, Welcome};
+use street_code_fighter::networking::rollback::RollbackSession;
+use street_code_fighter::networking::server::Lobby;
+use street_code_fighter::networking::transmit::{self, MatchMessage, RecentInputs};
+use street_code_fighter::replay::{Playback, Replay};
+use street_code_fighter::simulation::{SimConfig, Simulation};
+
+// how long the scripted match runs, in frames, long enough for the stalactite to land and drop again
+const MATCH_FRAMES: u32 = 400;
+// the game loop's pace, stalled clients repeat their inputs this often
+const LOOP_INTERVAL: Duration = Duration::from_millis(16);
+const GIVE_UP_AFTER: Duration = Duration::from_secs(60);
+
+fn bad_network() -> Conditions {
+    Conditions {
+        latency: Duration::from_millis(20),
+        jitter: Duration::from_millis(15),
+        loss: 0.1,
+        duplicate: 0.05,
+        reorder: 0.05,
+        reorder_delay: Duration::from_millis(25),
+    }
+}
+
+// walk in, jump, punch and kick, each player on their own rhythm
+fn script(player: u8, frame: u32) -> HashSet<u8> {
+    let (towards, phase) = if player == 1 { (2, frame) } else { (1, frame + 17) };
+    let keys: &[u8] = match phase % 60 {
+        0..=19 => &[towards],
+        20..=24 => &[4],
+        25..=34 => &[5],
+        35..=44 => &[7],
+        _ => &[],
+    };
+    keys.iter().cloned().collect()
+}
+
+struct Server {
+    socket: SimSocket,
+    lobby: Lobby,
+}
+
+impl Server {
+    fn new() -> Server {
+        Server { socket: SimSocket::bind("127.0.0.1:0", Conditions::perfect(), 0).unwrap(), lobby: Lobby::new("netcode test").unwrap() }
+    }
+
+    // what the lobby loop in run_server does, without blocking
+    fn poll(&mut self) {
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        while let Ok(Some((n, address))) = transmit::receive_packet(&self.socket, &mut buffer) {
+            self.lobby.route(&self.socket, address, &buffer[..n]);
+        }
+        let now = Instant::now();
+        for room in self.lobby.rooms.iter_mut() {
+            room.tick(&self.socket, Duration::from_secs(5), Duration::from_secs(20), now);
+        }
+        self.lobby.close_finished_rooms();
+    }
+}
+
+// the netcode half of run_client, pressing keys from a script instead of the keyboard
+struct Client {
+    socket: SimSocket,
+    channel: Channel,
+    name: String,
+    player: u8,
+    epoch: u32,
+    session: RollbackSession,
+    recent: RecentInputs,
+    sim: Simulation,
+    recording: Replay,
+    started: bool,
+    last_repeat: Instant,
+    digests: Vec<StateDigest>,
+    desyncs: u32,
+}
+
+impl Client {
+    fn new(server: &Server, seed: u64) -> Client {
+        let socket = SimSocket::bind("127.0.0.1:0", Conditions::perfect(), seed).unwrap();
+        socket.connect(server.socket.local_addr().unwrap()).unwrap();
+        Client {
+            socket,
+            channel: Channel::new(),
+            name: format!("script {}", seed),
+            player: 0,
+            epoch: 0,
+            session: RollbackSession::new(0),
+            recent: RecentInputs::new(),
+            sim: Simulation::new(SimConfig::default()).unwrap(),
+            recording: Replay::new(SimConfig::default()),
+            started: false,
+            last_repeat: Instant::now(),
+            digests: Vec::new(),
+            desyncs: 0,
+        }
+    }
+
+    fn hello(&self) -> ClientHello { ClientHello::new(&self.name, registry::global().unwrap().hash(), None) }
+
+    fn join(&mut self) {
+        let packet = Channel::new().encode(MessageType::Hello, 0, &self.hello());
+        transmit::Transport::send(&self.socket, &packet).unwrap();
+    }
+
+    // Challenge, Welcome and Start come before any match traffic
+    fn handshake(&mut self) {
+        let mut buffer = [0u8; protocol::MAX_PACKET_SIZE];
+        while let Some((n, _)) = transmit::receive_packet(&self.socket, &mut buffer).unwrap() {
+            let bytes = &buffer[..n];
+            match protocol::read_header(bytes).map(|header| header.msg_type) {
+                Ok(MessageType::Challenge) => {
+                    let (_, cookie) = self.channel.decode::<Cookie>(bytes).unwrap();
+                    // the seat's channel starts with the Join
+                    self.channel = Channel::new();
+                    let join = JoinRequest { hello: self.hello(), cookie, room: RoomChoice::Any };
+                    transmit::Transport::send(&self.socket, &self.channel.encode(MessageType::Join, 0, &join)).unwrap();
+                },
+                Ok(MessageType::Welcome) => {
+                    let (_, welcome) = self.channel.decode::<Welcome>(bytes).unwrap();
+                    self.player = welcome.player;
+                    self.session = RollbackSession::new(welcome.player);
+                    self.channel.set_session(welcome.session);
+                },
+                Ok(MessageType::Start) => {
+                    let (_, start) = self.channel.decode::<MatchStart>(bytes).unwrap();
+                    self.session.set_input_delay(start.input_delay);
+                    start.check().unwrap();
+                    self.sim = Simulation::new(start.config).unwrap();
+                    self.recording = Replay::new(start.config);
+                    self.started = true;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn done(&self) -> bool {
+        // with an input delay the opponent's inputs run a few frames past the end
+        self.sim.frame() == MATCH_FRAMES && self.session.confirmed_frame() >= MATCH_FRAMES
+    }
+
+    // one pass of the game loop
+    fn tick(&mut self) {
+        let current_frame = self.sim.frame();
+        let mut advancing = current_frame < MATCH_FRAMES && self.session.can_advance(current_frame);
+        if advancing {
+            let mut newest = None;
+            for (frame, keys) in self.session.add_delayed_input(current_frame, script(self.player, current_frame)) {
+                newest = Some(self.recent.next(frame, self.epoch, keys));
+            }
+            if let Some(input) = newest {
+                transmit::send_input(&self.socket, &mut self.channel, &input);
+            }
+        }
+        else if self.last_repeat.elapsed() >= LOOP_INTERVAL {
+            if let Some(input) = self.recent.repeat() {
+                transmit::send_input(&self.socket, &mut self.channel, &input);
+            }
+            self.last_repeat = Instant::now();
+        }
+
+        loop {
+            match transmit::receive_server_message(&self.socket, &mut self.channel) {
+                Ok(Some(MatchMessage::Input(remote))) => {
+                    if remote.epoch == self.epoch {
+                        for (frame, keys) in remote.frames() {
+                            self.session.add_remote_input(frame, keys.clone());
+                        }
+                    }
+                },
+                Ok(Some(MatchMessage::Ping(id))) => transmit::send_pong(&self.socket, &mut self.channel, id),
+                Ok(Some(MatchMessage::Desync{ frame, values })) => {
+                    self.desyncs += 1;
+                    if let Some(ours) = self.digests.iter().find(|d| d.frame == frame) {
+                        println!("player {} desynced at frame {}: {:?}", self.player, frame, ours.diff(&values));
+                    }
+                },
+                // the server repeats a Resync until it is acked, a repeat is dropped
+                Ok(Some(MatchMessage::Resync(resync))) if resync.epoch > self.epoch => {
+                    self.epoch = resync.epoch;
+                    self.sim.load(&resync.snapshot);
+                    self.session.resync(self.sim.frame());
+                    advancing = false;
+                },
+                Ok(Some(_)) => {},
+                Ok(None) => break,
+                Err(e) => panic!("player {} lost the server: {}", self.player, e),
+            }
+        }
+
+        let current_frame = self.sim.frame();
+        if let Some(rollback_frame) = self.session.take_rollback(current_frame) {
+            if let Some(snapshot) = self.session.snapshot(rollback_frame) {
+                self.sim.load(snapshot);
+            }
+            for frame in rollback_frame .. current_frame {
+                self.step(frame);
+            }
+        }
+        if advancing {
+            self.step(current_frame);
+        }
+
+        for digest in self.session.take_confirmed() {
+            transmit::send_checksum(&self.socket, &mut self.channel, self.epoch, &digest);
+            self.digests.push(digest);
+        }
+        for (frame, input_1, input_2) in self.session.take_final_inputs() {
+            if frame == self.recording.len() { self.recording.record(&input_1, &input_2); }
+        }
+        if self.channel.needs_heartbeat(Instant::now()) {
+            transmit::send_heartbeat(&self.socket, &mut self.channel);
+        }
+    }
+
+    fn step(&mut self, frame: u32) {
+        self.session.save_snapshot(self.sim.save());
+        let (input_1, input_2) = self.session.inputs_for(frame);
+        self.sim.step(&input_1, &input_2);
+    }
+
+    fn state(&self) -> StateDigest {
+        StateDigest::of(&self.sim.save())
+    }
+}
+
+fn play(conditions: Conditions) -> (Client, Client) {
+    let mut server = Server::new();
+    let mut a = Client::new(&server, 1);
+    let mut b = Client::new(&server, 2);
+
+    // the lobby handshake isn't what is being tested, the match is
+    a.join();
+    b.join();
+    let start = Instant::now();
+    while !(a.started && b.started) {
+        assert!(start.elapsed() < Duration::from_secs(1), "no reply from the lobby");
+        server.poll();
+        a.handshake();
+        b.handshake();
+        thread::sleep(Duration::from_millis(1));
+    }
+    for socket in [&server.socket, &a.socket, &b.socket].iter() {
+        socket.set_conditions(conditions.clone());
+    }
+
+    let start = Instant::now();
+    while !(a.done() && b.done()) {
+        assert!(start.elapsed() < GIVE_UP_AFTER, "stuck at frames {} and {}", a.sim.frame(), b.sim.frame());
+        a.tick();
+        server.poll();
+        b.tick();
+        server.poll();
+        thread::sleep(Duration::from_millis(1));
+    }
+    // let the last checksums reach the server and any answer come back
+    let until = Instant::now() + Duration::from_millis(200);
+    while Instant::now() < until {
+        a.tick();
+        b.tick();
+        server.poll();
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    println!("server sent {:?}", server.socket.stats());
+    println!("player 1 sent {:?}", a.socket.stats());
+    println!("player 2 sent {:?}", b.socket.stats());
+    (a, b)
+}
+
+#[test]
+fn testMatchOverPerfectNetwork() {
+    let (a, b) = play(Conditions::perfect());
+    assert_eq!(a.state(), b.state());
+    assert_eq!(a.desyncs + b.desyncs, 0);
+}
+
+#[test]
+fn testMatchOverBadNetwork() {
+    let (a, b) = play(bad_network());
+
+    // both ended up at the same place, agreeing with each other and the server on the way
+    assert_eq!(a.state(), b.state());
+    assert_eq!(a.digests, b.digests);
+    assert_eq!(a.desyncs + b.desyncs, 0);
+    assert_eq!((a.epoch, b.epoch), (0, 0));
+    assert!(a.socket.stats().lost > 0 && b.socket.stats().lost > 0);
+
+    // what a player recorded plays back through the same checkpoints
+    let mut playback = Playback::new(a.recording.clone()).unwrap();
+    let mut checked = 0;
+    while let Some(_) = playback.step() {
+        if let Some(digest) = a.digests.iter().find(|d| d.frame == playback.sim.frame()) {
+            assert_eq!(&StateDigest::of(&playback.sim.save()), digest);
+            checked += 1;
+        }
+    }
+    assert!(checked > 0);
+}